@@ -0,0 +1,53 @@
+use crate::core::chat_data::ChatItem;
+use crate::core::presence::PresenceDot;
+use crate::core::routes::Route;
+use dioxus::prelude::*;
+
+/// Chat List Component
+///
+/// Renders one row per chat, in the order given: the contact's presence and
+/// name, a preview of the last message, the time of the last activity and the
+/// number of unread messages. Each row opens the conversation.
+#[component]
+pub fn ChatList(chats: Vec<ChatItem>) -> Element {
+    rsx! {
+        ul {
+            class: "chat-list",
+            role: "list",
+
+            for chat in chats {
+                li {
+                    key: "{chat.id}",
+                    class: if chat.unread_count > 0 { "chat-item unread" } else { "chat-item" },
+                    Link {
+                        to: Route::Conversation { id: chat.id.clone() },
+                        class: "chat-link",
+                        div {
+                            class: "chat-header",
+                            PresenceDot { status: chat.presence }
+                            span { class: "chat-name", "{chat.name}" }
+                            span {
+                                class: "chat-time",
+                                {chat.timestamp.with_timezone(&chrono::Local).format("%H:%M").to_string()}
+                            }
+                        }
+                        div {
+                            class: "chat-summary",
+                            p {
+                                class: "chat-preview",
+                                {chat.last_message.clone().unwrap_or_default()}
+                            }
+                            if chat.unread_count > 0 {
+                                span {
+                                    class: "unread-badge",
+                                    aria_label: "{chat.unread_count} unread",
+                                    "{chat.unread_count}"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,13 +1,59 @@
-use std::collections::VecDeque;
+use crate::core::chat_data::ChatId;
+use crate::core::protocol::MessageId;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-pub const MAX_MESSAGES: usize = 256;
-pub const MAX_TOTAL_BYTES: usize = 16 * 1024 * 1024; // 16MB Limit
+pub const MAX_MESSAGES: usize = 256;    // Per chat
+pub const MAX_TOTAL_BYTES: usize = 16 * 1024 * 1024; // 16MB Limit per chat
 
-// ---- Shared state for the message buffer ----
+/// A text message received from a peer, tagged with its origin
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReceivedMessage {
+    pub peer_address: String,    // Yggdrasil address of the sending contact
+    pub contact_id: i32,    // contacts.id of the sending contact
+    pub message_id: MessageId,    // Sender-assigned envelope id
+    pub sent_at: chrono::DateTime<chrono::Utc>,
+    pub body: String,
+}
+
+impl ReceivedMessage {
+    /// Memory held by the message: the struct itself plus its heap strings
+    pub fn size_bytes(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.peer_address.len()
+            + self.message_id.as_str().len()
+            + self.body.len()
+    }
+}
+
+/// Limits applied to each chat's buffer separately
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferLimits {
+    pub max_messages: usize,
+    pub max_bytes: usize,
+}
+
+impl Default for BufferLimits {
+    fn default() -> Self {
+        Self {
+            max_messages: MAX_MESSAGES,
+            max_bytes: MAX_TOTAL_BYTES,
+        }
+    }
+}
+
+/// Messages evicted unread from one chat's buffer
+#[derive(Clone, Debug, PartialEq)]
+pub struct Overflow {
+    pub chat_id: ChatId,
+    pub peer_address: String,
+    pub dropped: usize,
+}
+
+// ---- Shared state for one chat's buffer ----
 struct SlidingWindowBuffer {
-    messages: VecDeque<String>,
+    messages: VecDeque<ReceivedMessage>,
     total_bytes: usize,
     max_messages: usize,
     max_bytes: usize,
@@ -23,59 +69,223 @@ impl SlidingWindowBuffer {
         }
     }
 
-    fn add_message(&mut self, msg: String) {
-        let msg_bytes = msg.len();
-
+    // Returns how many older messages had to be evicted
+    fn add_message(&mut self, msg: ReceivedMessage) -> usize {
         // Add new message
+        self.total_bytes += msg.size_bytes();
         self.messages.push_back(msg);
-        self.total_bytes += msg_bytes;
 
-        // Evict old messages if limits exceeded
-        while (self.messages.len() > self.max_messages) || (self.total_bytes > self.max_bytes) {
-            if let Some(oldest) = self.messages.pop_front() {
-                self.total_bytes -= oldest.len();
-            } else {
+        self.evict()
+    }
+
+    fn set_limits(&mut self, max_messages: usize, max_bytes: usize) -> usize {
+        self.max_messages = max_messages;
+        self.max_bytes = max_bytes;
+        self.evict()
+    }
+
+    // Evict old messages while limits are exceeded, always keeping the newest:
+    // a message larger than `max_bytes` on its own stays until it is taken
+    fn evict(&mut self) -> usize {
+        let mut dropped = 0;
+        let over_bytes = |buffer: &Self| buffer.total_bytes > buffer.max_bytes && buffer.messages.len() > 1;
+        while (self.messages.len() > self.max_messages) || over_bytes(self) {
+            let Some(_) = self.pop_front() else {
                 break; // Safety check
-            }
+            };
+            dropped += 1;
         }
+        dropped
     }
 
-    fn get_next_n_messages(&mut self, count: usize) -> Vec<String> {
-        let mut result = Vec::new();
-        let count = std::cmp::min(count, self.messages.len());
+    fn pop_front(&mut self) -> Option<ReceivedMessage> {
+        let msg = self.messages.pop_front()?;
+        self.total_bytes -= msg.size_bytes();
+        Some(msg)
+    }
 
-        for _ in 0..count {
-            if let Some(msg) = self.messages.pop_front() {
-                self.total_bytes -= msg.len();
-                result.push(msg);
-            }
-        }
-        result
+    fn get_next_n_messages(&mut self, count: usize) -> Vec<ReceivedMessage> {
+        let count = std::cmp::min(count, self.messages.len());
+        (0..count).filter_map(|_| self.pop_front()).collect()
     }
 }
 
-struct MessageBuffer {
-    buffer: Arc<Mutex<SlidingWindowBuffer>>,
+struct ChatBuffers {
+    chats: HashMap<ChatId, SlidingWindowBuffer>,
+    limits: BufferLimits,
+}
+
+/// Received messages waiting for a consumer, kept per chat
+///
+/// Each chat has its own window with its own limits, so a chatty peer can
+/// only evict its own unread messages. Evictions are reported back to the
+/// caller as an `Overflow`.
+///
+/// Messages are stored in the database before they are buffered; the buffer
+/// only holds copies for consumers that take them (an open conversation
+/// takes its chat's messages). A chat that nobody opens therefore fills its
+/// window and then reports an `Overflow` for every further message. Nothing
+/// is lost when that happens, and opening the chat empties its window.
+#[derive(Clone)]
+pub struct MessageBuffer {
+    buffer: Arc<Mutex<ChatBuffers>>,
 }
 
 impl MessageBuffer {
-    fn new() -> Self {
+    pub fn new() -> Self {
+        Self::with_limits(BufferLimits::default())
+    }
+
+    pub fn with_limits(limits: BufferLimits) -> Self {
         Self {
-            buffer: Arc::new(Mutex::new(
-                SlidingWindowBuffer::new(MAX_MESSAGES, MAX_TOTAL_BYTES)
-            ))
+            buffer: Arc::new(Mutex::new(ChatBuffers {
+                chats: HashMap::new(),
+                limits,
+            }))
         }
-    } 
+    }
 
-    // Add message to the buffer
-    pub async fn push_message(&self, msg: String) {
+    // Add message to its chat's buffer, reporting any evictions
+    pub async fn push_message(&self, msg: ReceivedMessage) -> Option<Overflow> {
         let mut buffer_guard = self.buffer.lock().await;
-        buffer_guard.add_message(msg);
+        let ChatBuffers { chats, limits } = &mut *buffer_guard;
+
+        let chat_id = ChatId::from(msg.contact_id);
+        let peer_address = msg.peer_address.clone();
+
+        let dropped = chats
+            .entry(chat_id.clone())
+            .or_insert_with(|| SlidingWindowBuffer::new(limits.max_messages, limits.max_bytes))
+            .add_message(msg);
+
+        (dropped > 0).then_some(Overflow { chat_id, peer_address, dropped })
     }
 
-    // Take messages from the buffer
-    pub async fn take_messages(&self, count: usize) -> Vec<String> {
+    // Change the per-chat limits, evicting the oldest messages where now over them
+    pub async fn set_limits(&self, limits: BufferLimits) -> Vec<Overflow> {
         let mut buffer_guard = self.buffer.lock().await;
-        buffer_guard.get_next_n_messages(count)
+        buffer_guard.limits = limits;
+
+        let mut overflows = Vec::new();
+        for (chat_id, chat) in buffer_guard.chats.iter_mut() {
+            let peer_address = chat.messages.front().map(|msg| msg.peer_address.clone());
+            let dropped = chat.set_limits(limits.max_messages, limits.max_bytes);
+            if let (true, Some(peer_address)) = (dropped > 0, peer_address) {
+                overflows.push(Overflow { chat_id: chat_id.clone(), peer_address, dropped });
+            }
+        }
+        buffer_guard.chats.retain(|_, chat| !chat.messages.is_empty());
+        overflows
+    }
+
+    // Take messages from one chat, leaving the others buffered
+    pub async fn take_messages_for(&self, chat_id: &ChatId, count: usize) -> Vec<ReceivedMessage> {
+        let mut buffer_guard = self.buffer.lock().await;
+        let Some(chat) = buffer_guard.chats.get_mut(chat_id) else {
+            return Vec::new();
+        };
+
+        let taken = chat.get_next_n_messages(count);
+        if chat.messages.is_empty() {
+            buffer_guard.chats.remove(chat_id);
+        }
+        taken
+    }
+}
+
+impl Default for MessageBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(contact_id: i32, body: &str) -> ReceivedMessage {
+        ReceivedMessage {
+            peer_address: format!("200::{}", contact_id),
+            contact_id,
+            message_id: MessageId::new(),
+            sent_at: chrono::Utc::now(),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_eviction_keeps_newest_and_counts_dropped() {
+        let mut window = SlidingWindowBuffer::new(2, usize::MAX);
+
+        assert_eq!(window.add_message(message(1, "a")), 0);
+        assert_eq!(window.add_message(message(1, "b")), 0);
+        assert_eq!(window.add_message(message(1, "c")), 1);
+
+        let bodies: Vec<String> = window.get_next_n_messages(10).into_iter().map(|m| m.body).collect();
+        assert_eq!(bodies, vec!["b", "c"]);
+    }
+
+    #[test]
+    fn test_byte_accounting_is_exact() {
+        let small = message(1, "hi");
+        let large = message(1, &"x".repeat(1000));
+        let limit = small.size_bytes() + large.size_bytes();
+        let mut window = SlidingWindowBuffer::new(usize::MAX, limit);
+
+        window.add_message(small.clone());
+        window.add_message(large.clone());
+        assert_eq!(window.total_bytes, limit);
+
+        // One byte over the limit evicts the oldest message only
+        assert_eq!(window.add_message(message(1, "")), 1);
+        assert_eq!(window.total_bytes, limit - small.size_bytes() + message(1, "").size_bytes());
+
+        assert_eq!(window.get_next_n_messages(1), vec![large]);
+        assert_eq!(window.get_next_n_messages(5).len(), 1);
+        assert_eq!(window.total_bytes, 0);
+        assert!(window.get_next_n_messages(1).is_empty());
+    }
+
+    #[test]
+    fn test_oversized_message_is_kept_alone() {
+        let mut window = SlidingWindowBuffer::new(10, 100);
+        window.add_message(message(1, "a"));
+
+        // Larger than the whole window: everything older goes, it stays
+        let huge = message(1, &"x".repeat(500));
+        assert_eq!(window.add_message(huge.clone()), 1);
+        assert_eq!(window.get_next_n_messages(10), vec![huge]);
+    }
+
+    #[tokio::test]
+    async fn test_chats_are_buffered_separately() {
+        let buffer = MessageBuffer::with_limits(BufferLimits { max_messages: 2, max_bytes: usize::MAX });
+
+        buffer.push_message(message(1, "quiet")).await;
+        for body in ["one", "two"] {
+            assert_eq!(buffer.push_message(message(2, body)).await, None);
+        }
+        let overflow = buffer.push_message(message(2, "three")).await.unwrap();
+        assert_eq!(overflow.chat_id, ChatId::from(2));
+        assert_eq!(overflow.dropped, 1);
+
+        // The chatty peer only evicted its own message
+        assert_eq!(buffer.take_messages_for(&ChatId::from(1), 10).await.len(), 1);
+
+        let rest: Vec<String> = buffer.take_messages_for(&ChatId::from(2), 10).await.into_iter().map(|m| m.body).collect();
+        assert_eq!(rest, vec!["two", "three"]);
+    }
+
+    #[tokio::test]
+    async fn test_lowering_limits_reports_overflow() {
+        let buffer = MessageBuffer::new();
+        for body in ["a", "b", "c"] {
+            buffer.push_message(message(1, body)).await;
+        }
+
+        let overflows = buffer.set_limits(BufferLimits { max_messages: 1, max_bytes: MAX_TOTAL_BYTES }).await;
+        assert_eq!(overflows.len(), 1);
+        assert_eq!(overflows[0].dropped, 2);
+        assert_eq!(buffer.take_messages_for(&ChatId::from(1), 10).await.len(), 1);
     }
 }
@@ -1,42 +1,42 @@
-use crate::database;
-use tokio::sync::{Mutex, Notify, atomic::{AtomicBool, Ordering}};
-use tracing::{info, error, warn, debug, trace};
-use std::sync::Arc;
-use chrono;
 /// Chat Data Management Module for Syggrel Chat
 /// 
 /// This module provides the core data management functionality for chat contacts
 /// in the Syggrel Chat application. It handles:
 /// 
 /// - Data modeling for chat items and contacts
-/// - Robust error handling with comprehensive error types
+/// - Errors for loads that time out or fail in the database
 /// - Asynchronous data loading with retry mechanisms and timeout protection
 /// - Integration with the database layer for persistent storage
 /// - State management patterns for UI components
-
+use crate::core::connection_manager::PeerState;
+use crate::core::events::MessengerEvent;
+use crate::core::messenger::YggdrasilMessenger;
+use crate::core::protocol::PresenceStatus;
+use crate::core::retry::RetryPolicy;
+use crate::core::supervisor::SessionEvent;
+use crate::database;
+use dioxus::prelude::*;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::{Mutex, Notify, broadcast::error::RecvError, watch};
+use tokio::task::JoinHandle;
+use tracing::{error, warn, debug};
+use std::sync::Arc;
+use chrono;
 /// Enhanced error types for chat data operations
 /// 
-/// Provides error categorization for all possible failure modes
-/// in chat data operations including network, timeout, server, parsing, authentication,
-/// and database errors. Each error type includes detailed context for debugging.
+/// Chats are loaded from the local database only, so a load either times
+/// out or fails in the database layer.
 #[derive(Debug, Clone)]
 pub enum DataError {
-    Network(String),
     Timeout,
-    Server(String),
-    Parse(String),
-    Unauthorized,
-    Database(String),    // Added specific database error type
+    Database(String),
 }
 
 impl std::fmt::Display for DataError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            DataError::Network(msg) => write!(f, "Network error: {}", msg),
             DataError::Timeout => write!(f, "Request timed out"),
-            DataError::Server(msg) => write!(f, "Server error: {}", msg),
-            DataError::Parse(msg) => write!(f, "Data parse error: {}", msg),
-            DataError::Unauthorized => write!(f, "Authentication required"),
             DataError::Database(msg) => write!(f, "Database error: {}", msg),
         }
     }
@@ -55,6 +55,37 @@ type AppResult<T> = Result<T, DataError>;
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct ChatId(String);
 
+impl ChatId {
+    /// The contact this chat is with
+    pub fn contact_id(&self) -> Option<i32> {
+        self.0.parse().ok()
+    }
+}
+
+impl std::fmt::Display for ChatId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Parses the `:id` segment of a chat route
+impl std::str::FromStr for ChatId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<i32>()
+            .map(ChatId::from)
+            .map_err(|_| format!("Invalid chat id '{}'", s))
+    }
+}
+
+/// Chats are one-to-one with contacts, so a contact's row id identifies its chat
+impl From<i32> for ChatId {
+    fn from(contact_id: i32) -> Self {
+        Self(contact_id.to_string())
+    }
+}
+
 /// Represents a single chat conversation with metadata
 /// 
 /// Contains all relevant information for displaying a chat in the UI including:
@@ -63,15 +94,18 @@ pub struct ChatId(String);
 /// - Last message content (if available)
 /// - Timestamp of last activity
 /// - Unread message count
-/// - Online status of the contact
+/// - Online status and presence (online, away, offline) of the contact
+/// - Peer address, to match session events
 #[derive(Clone, Debug, PartialEq)]
 pub struct ChatItem {
     pub id: ChatId,
     pub name: String,
+    pub address: String,    // Yggdrasil address of the contact
     pub last_message: Option<String>,
     pub timestamp: chrono::DateTime<chrono::Utc>,
     pub unread_count: u32,
     pub is_online: bool,
+    pub presence: PresenceStatus,    // Drives the status dot; `is_online` unless offline
 }
 
 /// Thread-safe data provider for chat conversations with caching and coordination
@@ -98,23 +132,39 @@ pub struct ChatItem {
 /// 6. Notification System: Uses tokio::sync::Notify for efficient coordination
 ///    between loading threads and waiting consumers.
 /// 
+/// 7. Live Updates: `follow` applies messenger events to the cache in place,
+///    and every change to the cache is published on a watch channel that the
+///    `use_chats` hook turns into a Dioxus signal.
+///
 /// The provider integrates with the application's database layer through the
 /// crate::database module and provides a clean async interface for UI components.
+#[derive(Clone)]
 pub struct ChatDataProvider {
     chats: Arc<Mutex<Option<Arc<[ChatItem]>>>>,
     is_loading: Arc<AtomicBool>,
     notify: Arc<Notify>,
+    updates: Arc<watch::Sender<Option<Arc<[ChatItem]>>>>,    // Latest cached chats, for UI subscribers
 }
 
+/// Time allowed for a reload triggered by `follow`
+pub const RELOAD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
 impl ChatDataProvider {
     pub fn new() -> Self {
         Self {
             chats: Arc::new(Mutex::new(None)),
             is_loading: Arc::new(AtomicBool::new(false)),
             notify: Arc::new(Notify::new()),
+            updates: Arc::new(watch::Sender::new(None)),
         }
     }
 
+    // Replace the cached chats and tell subscribers about it
+    fn store(&self, guard: &mut Option<Arc<[ChatItem]>>, chats: Arc<[ChatItem]>) {
+        *guard = Some(chats.clone());
+        self.updates.send_replace(Some(chats));
+    }
+
 /// Loads chat data with coordination, caching, and timeout protection
 /// 
 /// This method implements the core loading coordination logic:
@@ -148,12 +198,12 @@ impl ChatDataProvider {
             Ordering::SeqCst
         ).is_ok() {
             // The loader = perform the load with timeout
-            let result = match timeout_at(deadline, self.do_load_chats()).await {
+            let result = match timeout_at(deadline, self.load_with_backoff_and_timeout(deadline)).await {
                 Ok(Ok(data)) => {
                     // Successfully loaded - update cache first
                     {
                         let mut guard = self.chats.lock().await;
-                        *guard = Some(data.clone());
+                        self.store(&mut guard, data.clone());
                     }
 
                     // Now safely clear loading flag and notify
@@ -186,7 +236,6 @@ impl ChatDataProvider {
             }
 
             // Wait for notification or timeout
-            let remaining_time = deadline - Instant::now();
             if timeout_at(deadline, self.notify.notified()).await.is_err() {
                 return Err(DataError::Timeout);
             }
@@ -201,7 +250,7 @@ impl ChatDataProvider {
 /// Internal method for loading data with exponential backoff retry logic
 /// 
 /// Implements resilient data loading with:
-/// - Configurable retry attempts (default: 3)
+/// - Retry attempts and delays from `RetryPolicy::chat_loading()` (3 attempts)
 /// - Exponential backoff (100ms, 200ms, 400ms) capped at 2 seconds
 /// - Deadline enforcement for the entire operation
 /// - Detailed logging for monitoring and debugging
@@ -211,11 +260,10 @@ impl ChatDataProvider {
     async fn load_with_backoff_and_timeout(&self, deadline: tokio::time::Instant) -> AppResult<Arc<[ChatItem]>> {
         use tokio::time::{sleep, Instant};
 
-        const MAX_RETRIES: u32 = 3;
-        const BASE_DELAY_MS: u64 = 100;
-        const MAX_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+        let policy = RetryPolicy::chat_loading();
+        let mut attempt = 0;
 
-        for attempt in 0..MAX_RETRIES {
+        loop {
             let remaining_time = deadline.saturating_duration_since(Instant::now());
             if remaining_time == std::time::Duration::ZERO {
                 return Err(DataError::Timeout);
@@ -226,23 +274,21 @@ impl ChatDataProvider {
                     debug!("Chat loading successful on attempt {}", attempt + 1);
                     return Ok(data);
                 },
-                Ok(Err(e)) if attempt < MAX_RETRIES - 1 => {
-                    let delay = std::cmp::min(
-                        BASE_DELAY_MS * (1u64 << attempt),    // Exponential: 100ms, 200ms, 400ms
-                        MAX_DELAY
-                    );
+                Ok(Err(e)) if policy.allows(attempt + 1) => {
+                    let delay = policy.delay_for(attempt);    // Exponential: 100ms, 200ms, 400ms
                     warn!("Chat loading failed on attempt {}, retrying in {:?}: {}",
                                     attempt + 1, delay, e);
                     
                     // Check if we still have time for another attempt after delay
-                    if Instant::now().checked_add(delay).map_or(true, |t| t >= deadline) {
+                    if Instant::now().checked_add(delay).is_none_or(|t| t >= deadline) {
                         return Err(DataError::Timeout);
                     }
 
                     sleep(delay).await;
+                    attempt += 1;
                 },
                 Ok(Err(e)) => {
-                    error!("Chat loading failed permanently after {} attempts: {}", MAX_RETRIES, e);
+                    error!("Chat loading failed permanently after {} attempts: {}", attempt + 1, e);
                     return Err(e);
                 },
                 Err(_) => {
@@ -251,25 +297,16 @@ impl ChatDataProvider {
                 }
             }
         }
-
-        unreachable!()
-    }
-
-/// Retrieves cached chat data without triggering a new load operation
-/// 
-/// Returns an Arc-wrapped array of ChatItems if available, or None if
-/// no data has been loaded yet. The Arc clone operation is efficient
-/// as it only increments a reference counter without copying the data.
-    pub async fn get_chats(&self) -> Option<Arc<[ChatItem]>> {
-        self.chats.lock().await.clone()
     }
 
-/// Checks if a data loading operation is currently in progress
-/// 
-/// Uses atomic operations for thread-safe access without blocking,
-/// suitable for UI components that need to display loading indicators.
-    pub fn is_loading(&self) -> bool {
-        self.is_loading.load(Ordering::SeqCst)
+/// Loads every contact's chat from the database
+///
+/// Single attempt; retries and deadlines are handled by the callers.
+    async fn do_load_chats(&self) -> AppResult<Arc<[ChatItem]>> {
+        database::load_contacts_from_db()
+            .await
+            .map(Arc::from)
+            .map_err(DataError::Database)
     }
 
 /// Forces a cache refresh by clearing cached data and loading fresh data
@@ -283,13 +320,262 @@ impl ChatDataProvider {
         self.load_chats(timeout_duration).await
     }
 
-/// Checks if cached data is available without blocking
-    /// 
-    /// Uses try_lock to check cache state without blocking, returning true
-    /// if data exists in cache, false otherwise. Useful for quick UI state
-    /// decisions without waiting for mutex acquisition.    
-    pub fn has_cached_data(&self) -> bool {
-        self.chats.try_lock().map(|guard| guard.is_some()).unwrap_or(false)
+/// Applies per-peer connection state from the messenger to cached chats
+/// 
+/// Updates `is_online` in place for every chat whose peer state is known,
+/// without reloading from the database. Chats missing from `states` keep
+/// their current value. Does nothing if no data has been loaded yet.
+    pub async fn apply_peer_states(&self, states: &HashMap<ChatId, PeerState>) {
+        let mut guard = self.chats.lock().await;
+        if let Some(chats) = guard.as_ref() {
+            let updated: Arc<[ChatItem]> = chats
+                .iter()
+                .map(|chat| {
+                    let mut chat = chat.clone();
+                    if let Some(state) = states.get(&chat.id) {
+                        chat.is_online = *state == PeerState::Connected;
+                    }
+                    chat
+                })
+                .collect();
+            self.store(&mut guard, updated);
+        }
+    }
+
+/// Applies a messenger event to the cached chats without reloading
+///
+/// New messages update the chat's preview, timestamp and unread count,
+/// reading a chat clears its unread count, and sessions coming up or down
+/// and presence changes update `is_online` and `presence`. Returns `false`
+/// if the event concerns a chat that isn't cached (e.g. a contact added
+/// since the last load), in which case the caller should reload. Does
+/// nothing if no data has been loaded yet.
+    pub async fn apply_event(&self, event: &MessengerEvent) -> bool {
+        match event {
+            MessengerEvent::MessageReceived(message) => {
+                self.update_chats(|chat| {
+                    let matches = chat.id == ChatId::from(message.contact_id);
+                    if matches {
+                        chat.last_message = Some(message.body.clone());
+                        chat.timestamp = message.sent_at;
+                        chat.unread_count += 1;
+                    }
+                    matches
+                }).await
+            }
+            MessengerEvent::MessageSent { contact_id, body, sent_at, .. } => {
+                self.update_chats(|chat| {
+                    let matches = chat.id == ChatId::from(*contact_id);
+                    if matches {
+                        chat.last_message = Some(body.clone());
+                        chat.timestamp = *sent_at;
+                    }
+                    matches
+                }).await
+            }
+            MessengerEvent::ChatRead { contact_id, .. } => {
+                self.update_chats(|chat| {
+                    let matches = chat.id == ChatId::from(*contact_id);
+                    if matches {
+                        chat.unread_count = 0;
+                    }
+                    matches
+                }).await
+            }
+            MessengerEvent::PresenceChanged { contact_id, status, .. } => {
+                self.update_chats(|chat| {
+                    let matches = chat.id == ChatId::from(*contact_id);
+                    if matches {
+                        chat.presence = *status;
+                        chat.is_online = *status != PresenceStatus::Offline;
+                    }
+                    matches
+                }).await;
+                true    // Presence of contacts that aren't listed doesn't matter
+            }
+            MessengerEvent::Session(SessionEvent::Connected { address }) => {
+                self.set_online(address, true).await;
+                true
+            }
+            MessengerEvent::Session(SessionEvent::Disconnected { address, .. }) => {
+                self.set_online(address, false).await;
+                true
+            }
+            _ => true,
+        }
+    }
+
+    async fn set_online(&self, address: &str, online: bool) {
+        // Sessions for contacts that aren't listed have nothing to update
+        self.update_chats(|chat| {
+            let matches = chat.address == address;
+            if matches {
+                chat.is_online = online;
+            }
+            matches
+        }).await;
+    }
+
+    // Run `update` on every cached chat; it returns whether it changed the chat.
+    // Publishes only if something changed, and reports whether anything matched.
+    async fn update_chats(&self, mut update: impl FnMut(&mut ChatItem) -> bool) -> bool {
+        let mut guard = self.chats.lock().await;
+        let Some(chats) = guard.as_ref() else {
+            return true;    // Nothing loaded yet; the first load will be current
+        };
+
+        let mut changed = false;
+        let updated: Arc<[ChatItem]> = chats
+            .iter()
+            .map(|chat| {
+                let mut chat = chat.clone();
+                changed |= update(&mut chat);
+                chat
+            })
+            .collect();
+
+        if changed {
+            self.store(&mut guard, updated);
+        }
+        changed
+    }
+
+/// Keeps the cached chats in sync with the messenger until the task is aborted
+///
+/// Subscribes to messenger events before the first load so nothing is missed,
+/// then applies each event with `apply_event`. Reloads from the database when
+/// events were dropped (`RecvError::Lagged`) or concern an unknown chat.
+    pub fn follow(&self, messenger: Arc<YggdrasilMessenger>) -> JoinHandle<()> {
+        let provider = self.clone();
+        let mut events = messenger.subscribe();
+
+        tokio::spawn(async move {
+            provider.reload(&messenger).await;
+            loop {
+                match events.recv().await {
+                    Ok(event) => {
+                        if !provider.apply_event(&event).await {
+                            provider.reload(&messenger).await;
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Chat list missed {} messenger events, reloading", skipped);
+                        provider.reload(&messenger).await;
+                    }
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        })
+    }
+
+    async fn reload(&self, messenger: &YggdrasilMessenger) {
+        match self.refresh(RELOAD_TIMEOUT).await {
+            Ok(_) => {
+                self.apply_peer_states(&messenger.connections().chat_states()).await;
+                let presence = messenger.presence().chat_statuses();
+                self.update_chats(|chat| match presence.get(&chat.id) {
+                    Some(status) => {
+                        chat.presence = *status;
+                        true
+                    }
+                    None => false,
+                }).await;
+            }
+            Err(e) => error!("Failed to reload chats: {}", e),
+        }
+    }
+
+/// Receives the cached chats every time they change
+///
+/// Holds `None` until the first successful load. Clearing the cache for a
+/// refresh is not published, so subscribers keep showing the old list.
+    pub fn subscribe(&self) -> watch::Receiver<Option<Arc<[ChatItem]>>> {
+        self.updates.subscribe()
+    }
+}
+
+impl Default for ChatDataProvider {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
+/// Chats from the `ChatDataProvider` context, as a signal
+///
+/// The component re-renders whenever the provider's cache changes, whether
+/// from a load or from a live update.
+pub fn use_chats() -> Signal<Option<Arc<[ChatItem]>>> {
+    let provider = use_context::<ChatDataProvider>();
+    let mut chats = use_signal(|| provider.subscribe().borrow().clone());
+
+    use_future(move || {
+        let mut updates = provider.subscribe();
+        async move {
+            while updates.changed().await.is_ok() {
+                chats.set(updates.borrow_and_update().clone());
+            }
+        }
+    });
+
+    chats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::ReceivedMessage;
+    use crate::core::protocol::MessageId;
+
+    fn chat(contact_id: i32) -> ChatItem {
+        ChatItem {
+            id: ChatId::from(contact_id),
+            name: format!("Contact {}", contact_id),
+            address: format!("200::{}", contact_id),
+            last_message: None,
+            timestamp: chrono::Utc::now(),
+            unread_count: 0,
+            is_online: false,
+            presence: PresenceStatus::Offline,
+        }
+    }
+
+    fn received(contact_id: i32, body: &str) -> MessengerEvent {
+        MessengerEvent::MessageReceived(ReceivedMessage {
+            peer_address: format!("200::{}", contact_id),
+            contact_id,
+            message_id: MessageId::new(),
+            sent_at: chrono::Utc::now(),
+            body: body.to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_events_update_cached_chats_in_place() {
+        let provider = ChatDataProvider::new();
+        provider.store(&mut *provider.chats.lock().await, Arc::from(vec![chat(1), chat(2)]));
+        let mut updates = provider.subscribe();
+        updates.mark_unchanged();
+
+        assert!(provider.apply_event(&received(1, "hello")).await);
+        assert!(provider.apply_event(&received(1, "again")).await);
+        assert!(provider.apply_event(&MessengerEvent::Session(SessionEvent::Connected { address: "200::2".to_string() })).await);
+        assert!(updates.has_changed().unwrap());
+
+        let chats = provider.subscribe().borrow().clone().unwrap();
+        assert_eq!(chats[0].last_message.as_deref(), Some("again"));
+        assert_eq!(chats[0].unread_count, 2);
+        assert!(chats[1].is_online);
+
+        let away = MessengerEvent::PresenceChanged { peer_address: "200::2".to_string(), contact_id: 2, status: PresenceStatus::Away };
+        assert!(provider.apply_event(&away).await);
+        let chats = provider.subscribe().borrow().clone().unwrap();
+        assert_eq!(chats[1].presence, PresenceStatus::Away);
+        assert!(chats[1].is_online);
+
+        provider.apply_event(&MessengerEvent::ChatRead { peer_address: "200::1".to_string(), contact_id: 1 }).await;
+        assert_eq!(provider.subscribe().borrow().clone().unwrap()[0].unread_count, 0);
+
+        // A chat that isn't cached asks the caller to reload
+        assert!(!provider.apply_event(&received(3, "new contact")).await);
+    }
+}
@@ -0,0 +1,336 @@
+/// Connection Manager for Syggrel Chat
+///
+/// Keeps at most one live session per contact, keyed by the contact's
+/// `yggdrasil_address`. Outbound dials and inbound accepts both register
+/// their sessions here, so a new connection to the same peer replaces the
+/// old one while sessions with other peers are left untouched.
+///
+/// The exception is two peers dialing each other at the same time: each
+/// would replace the session the other kept and both would end. A session
+/// opened the other way within `CROSSED_DIAL_WINDOW` is therefore kept if it
+/// was dialed by the peer with the lower static key, on both ends alike.
+use crate::core::chat_data::ChatId;
+use crate::core::protocol::Envelope;
+use crate::core::session::SessionHandle;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::task::{AbortHandle, JoinHandle};
+use tokio::time::Instant;
+
+/// How long after a session comes up one opened the other way counts as a crossed dial
+pub const CROSSED_DIAL_WINDOW: Duration = Duration::from_secs(10);
+
+/// Connection state of a single peer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PeerState {
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+/// Which side opened a session's connection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Outbound,    // We dialed the peer
+    Inbound,    // The peer dialed us
+}
+
+struct PeerSession {
+    contact_id: i32,
+    state: PeerState,
+    task: Option<AbortHandle>,
+    tx: Option<mpsc::UnboundedSender<Envelope>>,
+    rtt: Option<watch::Receiver<Option<Duration>>>,
+    opened: Option<(Direction, Instant)>,
+}
+
+impl PeerSession {
+    /// Reports the stored state, downgraded if the session task has ended
+    fn current_state(&self) -> PeerState {
+        match (&self.state, &self.task) {
+            (PeerState::Connected, Some(task)) if task.is_finished() => PeerState::Disconnected,
+            (state, _) => *state,
+        }
+    }
+}
+
+/// Thread-safe registry of peer sessions
+///
+/// Cloning is cheap and every clone refers to the same registry, so the
+/// messenger and the inbound listener can share it.
+#[derive(Clone, Default)]
+pub struct ConnectionManager {
+    peers: Arc<Mutex<HashMap<String, PeerSession>>>,
+}
+
+impl ConnectionManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that a connection attempt to `address` is in progress
+    pub fn mark_connecting(&self, address: &str, contact_id: i32) {
+        let mut peers = self.peers.lock().unwrap();
+        let peer = peers.entry(address.to_string()).or_insert_with(|| PeerSession {
+            contact_id,
+            state: PeerState::Disconnected,
+            task: None,
+            tx: None,
+            rtt: None,
+            opened: None,
+        });
+        if peer.current_state() != PeerState::Connected {
+            peer.state = PeerState::Connecting;
+        }
+    }
+
+    /// Registers an established session, aborting any previous one for the same peer
+    ///
+    /// `we_dial_first` tells whether our static key is the lower one. If the
+    /// live session crossed this one and wins (see the module docs), the new
+    /// session is aborted instead and `None` is returned. Otherwise returns
+    /// the session task so the caller can watch for it ending.
+    pub fn install(
+        &self,
+        address: &str,
+        contact_id: i32,
+        session: SessionHandle,
+        direction: Direction,
+        we_dial_first: bool,
+    ) -> Option<JoinHandle<tokio::io::Error>> {
+        let mut peers = self.peers.lock().unwrap();
+
+        let crossed = peers
+            .get(address)
+            .filter(|p| p.current_state() == PeerState::Connected)
+            .and_then(|p| p.opened)
+            .filter(|(current, since)| *current != direction && since.elapsed() < CROSSED_DIAL_WINDOW);
+        if let Some((current, _)) = crossed
+            && (current == Direction::Outbound) == we_dial_first
+        {
+            session.task.abort();
+            return None;
+        }
+
+        let previous = peers.insert(
+            address.to_string(),
+            PeerSession {
+                contact_id,
+                state: PeerState::Connected,
+                task: Some(session.task.abort_handle()),
+                tx: Some(session.tx),
+                rtt: Some(session.rtt),
+                opened: Some((direction, Instant::now())),
+            },
+        );
+
+        if let Some(task) = previous.and_then(|p| p.task) {
+            task.abort();
+        }
+
+        Some(session.task)
+    }
+
+    /// Records that the session for `address` has ended
+    ///
+    /// Only clears the entry if its task has actually finished, so a newer
+    /// session installed in the meantime is left alone. Returns whether the
+    /// entry was cleared.
+    pub fn mark_disconnected(&self, address: &str) -> bool {
+        let mut peers = self.peers.lock().unwrap();
+        let Some(peer) = peers.get_mut(address) else {
+            return false;
+        };
+        if !peer.task.as_ref().is_none_or(|t| t.is_finished()) {
+            return false;
+        }
+
+        peer.state = PeerState::Disconnected;
+        peer.tx = None;
+        peer.rtt = None;
+        peer.task = None;
+        true
+    }
+
+    /// Queues an envelope on the session for `address`
+    pub fn send(&self, address: &str, envelope: Envelope) -> Result<(), String> {
+        let peers = self.peers.lock().unwrap();
+        let peer = peers
+            .get(address)
+            .filter(|p| p.current_state() == PeerState::Connected)
+            .ok_or_else(|| format!("Not connected to {}", address))?;
+
+        peer.tx
+            .as_ref()
+            .ok_or_else(|| format!("Not connected to {}", address))?
+            .send(envelope)
+            .map_err(|e| format!("Failed to queue message for {}: {}", address, e))
+    }
+
+    /// Connection state of a single peer
+    pub fn state(&self, address: &str) -> PeerState {
+        self.peers
+            .lock()
+            .unwrap()
+            .get(address)
+            .map(|p| p.current_state())
+            .unwrap_or(PeerState::Disconnected)
+    }
+
+    /// Latest heartbeat round-trip time to `address`, while connected
+    pub fn rtt(&self, address: &str) -> Option<Duration> {
+        self.peers
+            .lock()
+            .unwrap()
+            .get(address)
+            .filter(|p| p.current_state() == PeerState::Connected)
+            .and_then(|p| *p.rtt.as_ref()?.borrow())
+    }
+
+    /// Snapshot of every known peer's state, keyed by the chat it belongs to
+    pub fn chat_states(&self) -> HashMap<ChatId, PeerState> {
+        self.peers
+            .lock()
+            .unwrap()
+            .values()
+            .map(|p| (ChatId::from(p.contact_id), p.current_state()))
+            .collect()
+    }
+
+    /// Tears down the session for `address`, returning whether it was up or being dialed
+    pub fn disconnect(&self, address: &str) -> bool {
+        self.peers.lock().unwrap().get_mut(address).is_some_and(tear_down)
+    }
+
+    /// Tears down every session, returning the addresses that were up or being dialed
+    pub fn disconnect_all(&self) -> Vec<String> {
+        self.peers
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter_map(|(address, peer)| tear_down(peer).then(|| address.clone()))
+            .collect()
+    }
+}
+
+// Aborts the peer's session, returning whether it wasn't already disconnected
+fn tear_down(peer: &mut PeerSession) -> bool {
+    let was_up = peer.state != PeerState::Disconnected;
+    peer.state = PeerState::Disconnected;
+    peer.tx = None;
+    peer.rtt = None;
+    if let Some(task) = peer.task.take() {
+        task.abort();
+    }
+    was_up
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A session that runs until aborted, with its outgoing queue
+    fn session() -> (SessionHandle, mpsc::UnboundedReceiver<Envelope>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(std::future::pending());
+        let (_, rtt) = watch::channel(None);
+        (SessionHandle { task, tx, rtt }, rx)
+    }
+
+    #[tokio::test]
+    async fn test_install_replaces_previous_session_only_for_same_peer() {
+        let manager = ConnectionManager::new();
+        let (first, _first_rx) = session();
+        let (other, _other_rx) = session();
+        let (second, mut second_rx) = session();
+
+        let first_task = manager.install("200::1", 1, first, Direction::Outbound, true).unwrap();
+        let other_task = manager.install("200::2", 2, other, Direction::Outbound, true).unwrap();
+        manager.install("200::1", 1, second, Direction::Outbound, true);
+
+        assert!(first_task.await.unwrap_err().is_cancelled());
+        assert!(!other_task.is_finished());
+        assert_eq!(manager.state("200::1"), PeerState::Connected);
+
+        manager.send("200::1", Envelope::text("hi")).unwrap();
+        assert!(second_rx.try_recv().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_send_fails_when_not_connected() {
+        let manager = ConnectionManager::new();
+        assert!(manager.send("200::1", Envelope::text("hi")).is_err());
+
+        manager.mark_connecting("200::1", 1);
+        assert_eq!(manager.state("200::1"), PeerState::Connecting);
+        assert!(manager.send("200::1", Envelope::text("hi")).is_err());
+
+        let (handle, _rx) = session();
+        manager.install("200::1", 1, handle, Direction::Outbound, true);
+        assert!(manager.disconnect("200::1"));
+        assert_eq!(manager.state("200::1"), PeerState::Disconnected);
+        assert!(manager.send("200::1", Envelope::text("hi")).is_err());
+
+        // Nothing left to tear down
+        assert!(!manager.disconnect("200::1"));
+        assert!(manager.disconnect_all().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_redial_keeps_live_session() {
+        let manager = ConnectionManager::new();
+        let (handle, _rx) = session();
+        let task = manager.install("200::1", 1, handle, Direction::Outbound, true).unwrap();
+
+        // A dial attempt failing elsewhere must not tear down the live session
+        manager.mark_connecting("200::1", 1);
+        assert!(!manager.mark_disconnected("200::1"));
+
+        assert_eq!(manager.state("200::1"), PeerState::Connected);
+        assert!(!task.is_finished());
+
+        // Once the session itself has ended the entry is cleared
+        task.abort();
+        let _ = task.await;
+        assert!(manager.mark_disconnected("200::1"));
+        assert_eq!(manager.state("200::1"), PeerState::Disconnected);
+    }
+
+    #[tokio::test]
+    async fn test_crossed_dials_keep_the_session_dialed_by_the_lower_key() {
+        // Our key is lower: our outbound session stays, the peer's is dropped
+        let manager = ConnectionManager::new();
+        let (ours, _ours_rx) = session();
+        let (theirs, _theirs_rx) = session();
+        let ours = manager.install("200::1", 1, ours, Direction::Outbound, true).unwrap();
+        assert!(manager.install("200::1", 1, theirs, Direction::Inbound, true).is_none());
+        assert!(!ours.is_finished());
+
+        // The peer's key is lower: its session replaces ours
+        let manager = ConnectionManager::new();
+        let (ours, _ours_rx) = session();
+        let (theirs, _theirs_rx) = session();
+        let ours = manager.install("200::1", 1, ours, Direction::Outbound, false).unwrap();
+        assert!(manager.install("200::1", 1, theirs, Direction::Inbound, false).is_some());
+        assert!(ours.await.unwrap_err().is_cancelled());
+
+        // A second session opened the same way always replaces the first
+        let (again, _again_rx) = session();
+        assert!(manager.install("200::1", 1, again, Direction::Inbound, true).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_chat_states_are_keyed_by_contact() {
+        let manager = ConnectionManager::new();
+        let (handle, _rx) = session();
+        manager.install("200::1", 1, handle, Direction::Outbound, true);
+        manager.mark_connecting("200::2", 2);
+
+        let states = manager.chat_states();
+        assert_eq!(states[&ChatId::from(1)], PeerState::Connected);
+        assert_eq!(states[&ChatId::from(2)], PeerState::Connecting);
+        assert_eq!(states.len(), 2);
+    }
+}
@@ -0,0 +1,276 @@
+/// Shareable Contact Cards for Syggrel Chat
+///
+/// A contact card is everything needed to add someone as a contact, packed
+/// into one short string instead of a hand-typed IPv6 address: their
+/// Yggdrasil address, the port they listen on if it isn't the default, their
+/// display name and the fingerprint of their public key.
+///
+/// Cards are encoded as bech32m with the `syggrel` prefix, e.g.
+/// `syggrel1qyp...`, and as a URI by adding the `syggrel:` scheme in front.
+/// The checksum catches typos and truncated copies. Both forms are accepted
+/// when importing, in either letter case.
+///
+/// The fingerprint can't be pinned on import (pinning needs the full key,
+/// which arrives with the first handshake). It is stored with the contact
+/// instead, and the first key presented must match it before it is pinned.
+/// It is also shown so the user can compare it with the one the contact
+/// reads out.
+use crate::core::identity::{self, FINGERPRINT_LEN, Identity};
+use crate::core::listener::DEFAULT_LISTEN_PORT;
+use crate::database::address::YggdrasilAddress;
+use crate::database::models::{Contact, ValidationError};
+use bech32::{Bech32m, Hrp};
+use std::net::Ipv6Addr;
+
+/// Human-readable part of the bech32m encoding
+const CARD_HRP: &str = "syggrel";
+
+/// URI scheme for links and QR codes
+pub const URI_SCHEME: &str = "syggrel:";
+
+/// Version of the binary card layout
+const CARD_VERSION: u8 = 1;
+
+/// Longest display name kept on a card, in bytes
+pub const MAX_NAME_BYTES: usize = 64;
+
+const FLAG_PORT: u8 = 0x01;
+const FLAG_FINGERPRINT: u8 = 0x02;
+
+/// Errors produced while reading or creating a contact card
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CardError {
+    InvalidEncoding(String),    // Not bech32, bad checksum, wrong prefix
+    UnsupportedVersion(u8),
+    Truncated,
+    InvalidAddress(ValidationError),
+    InvalidName,
+    MissingAddress,    // Our identity has no Yggdrasil address to share
+}
+
+impl std::fmt::Display for CardError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CardError::InvalidEncoding(msg) => write!(f, "Not a valid contact card: {}", msg),
+            CardError::UnsupportedVersion(version) => {
+                write!(f, "Contact card version {} is not supported, update the app", version)
+            }
+            CardError::Truncated => write!(f, "Contact card is incomplete"),
+            CardError::InvalidAddress(err) => write!(f, "Contact card has an invalid address: {}", err),
+            CardError::InvalidName => write!(f, "Contact card has an invalid display name"),
+            CardError::MissingAddress => {
+                write!(f, "Set your Yggdrasil address before sharing your contact card")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CardError {}
+
+impl From<ValidationError> for CardError {
+    fn from(err: ValidationError) -> Self {
+        CardError::InvalidAddress(err)
+    }
+}
+
+/// What a contact card says about its owner
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContactCard {
+    pub address: YggdrasilAddress,    // Port is only set when not the default
+    pub display_name: String,
+    pub fingerprint: Option<[u8; FINGERPRINT_LEN]>,
+}
+
+impl ContactCard {
+    /// Our own card, listening on `listen_port`
+    pub fn for_identity(identity: &Identity, listen_port: u16) -> Result<Self, CardError> {
+        let address = identity.yggdrasil_address.as_deref().ok_or(CardError::MissingAddress)?;
+        let address = YggdrasilAddress::parse(address)?;
+
+        Ok(Self {
+            address: YggdrasilAddress {
+                ip: address.ip,
+                port: (listen_port != DEFAULT_LISTEN_PORT).then_some(listen_port),
+            },
+            display_name: truncate_name(identity.display_name.trim()).to_string(),
+            fingerprint: Some(identity::fingerprint_bytes(identity.public_key())),
+        })
+    }
+
+    /// Text form, `syggrel1...`
+    pub fn encode(&self) -> String {
+        bech32::encode::<Bech32m>(hrp(), &self.to_bytes()).expect("card fits in a bech32m string")
+    }
+
+    /// URI form, `syggrel:syggrel1...`
+    pub fn to_uri(&self) -> String {
+        format!("{}{}", URI_SCHEME, self.encode())
+    }
+
+    /// Reads a card in text or URI form
+    pub fn decode(input: &str) -> Result<Self, CardError> {
+        let input = input.trim();
+        let text = match input.get(..URI_SCHEME.len()) {
+            Some(scheme) if scheme.eq_ignore_ascii_case(URI_SCHEME) => &input[URI_SCHEME.len()..],
+            _ => input,
+        };
+
+        let (prefix, data) = bech32::decode(text).map_err(|e| CardError::InvalidEncoding(e.to_string()))?;
+        if prefix != hrp() {
+            return Err(CardError::InvalidEncoding(format!("unexpected prefix '{}'", prefix)));
+        }
+
+        Self::from_bytes(&data)
+    }
+
+    /// Fingerprint formatted like `identity::fingerprint`
+    pub fn fingerprint_text(&self) -> Option<String> {
+        self.fingerprint.as_ref().map(identity::format_fingerprint)
+    }
+
+    /// A new, validated contact for the card's owner, expecting the card's key
+    pub fn to_contact(&self) -> Result<Contact, ValidationError> {
+        let contact = Contact::new(self.address.to_string(), "", self.display_name.clone(), false);
+        Contact { card_fingerprint: self.fingerprint_text(), ..contact }.normalized()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if self.address.port.is_some() {
+            flags |= FLAG_PORT;
+        }
+        if self.fingerprint.is_some() {
+            flags |= FLAG_FINGERPRINT;
+        }
+
+        let mut bytes = vec![CARD_VERSION, flags];
+        bytes.extend_from_slice(&self.address.ip.octets());
+        if let Some(port) = self.address.port {
+            bytes.extend_from_slice(&port.to_be_bytes());
+        }
+        if let Some(fingerprint) = &self.fingerprint {
+            bytes.extend_from_slice(fingerprint);
+        }
+        bytes.extend_from_slice(truncate_name(&self.display_name).as_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, CardError> {
+        let mut reader = Reader(bytes);
+
+        let version = reader.take::<1>()?[0];
+        if version != CARD_VERSION {
+            return Err(CardError::UnsupportedVersion(version));
+        }
+        let flags = reader.take::<1>()?[0];
+
+        let ip = Ipv6Addr::from(reader.take::<16>()?);
+        let port = match flags & FLAG_PORT {
+            0 => None,
+            _ => Some(u16::from_be_bytes(reader.take::<2>()?)),
+        };
+        let fingerprint = match flags & FLAG_FINGERPRINT {
+            0 => None,
+            _ => Some(reader.take::<FINGERPRINT_LEN>()?),
+        };
+
+        // The address goes through the same checks as a typed one
+        let address = match port {
+            Some(port) => format!("[{}]:{}", ip, port),
+            None => ip.to_string(),
+        };
+        let address = YggdrasilAddress::parse(&address)?;
+
+        let display_name = std::str::from_utf8(reader.0).map_err(|_| CardError::InvalidName)?;
+        if display_name.len() > MAX_NAME_BYTES {
+            return Err(CardError::InvalidName);
+        }
+
+        Ok(Self {
+            address,
+            display_name: display_name.to_string(),
+            fingerprint,
+        })
+    }
+}
+
+// Reads fixed-size fields off the front of a card
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], CardError> {
+        let (field, rest) = self.0.split_first_chunk::<N>().ok_or(CardError::Truncated)?;
+        self.0 = rest;
+        Ok(*field)
+    }
+}
+
+fn hrp() -> Hrp {
+    Hrp::parse_unchecked(CARD_HRP)
+}
+
+/// Cuts `name` to `MAX_NAME_BYTES` without splitting a character
+fn truncate_name(name: &str) -> &str {
+    let mut end = name.len().min(MAX_NAME_BYTES);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    &name[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn card(port: Option<u16>) -> ContactCard {
+        ContactCard {
+            address: YggdrasilAddress { ip: "200:1234::1".parse().unwrap(), port },
+            display_name: "Alice".to_string(),
+            fingerprint: Some(identity::fingerprint_bytes(&[1u8; 32])),
+        }
+    }
+
+    #[test]
+    fn test_card_round_trips_in_text_and_uri_form() {
+        for original in [card(None), card(Some(9000))] {
+            let text = original.encode();
+            assert!(text.starts_with("syggrel1"));
+
+            assert_eq!(ContactCard::decode(&text).unwrap(), original);
+            assert_eq!(ContactCard::decode(&original.to_uri()).unwrap(), original);
+            assert_eq!(ContactCard::decode(&original.to_uri().to_uppercase()).unwrap(), original);
+        }
+
+        let contact = card(Some(9000)).to_contact().unwrap();
+        assert_eq!(contact.yggdrasil_address, "[200:1234::1]:9000");
+        assert_eq!(contact.display_name, "Alice");
+    }
+
+    #[test]
+    fn test_damaged_or_foreign_cards_are_rejected() {
+        let text = card(None).encode();
+        let typo = text.replacen('q', "p", 1);
+
+        assert!(matches!(ContactCard::decode(&typo), Err(CardError::InvalidEncoding(_))));
+        assert!(matches!(ContactCard::decode("200:1234::1"), Err(CardError::InvalidEncoding(_))));
+
+        let foreign = bech32::encode::<Bech32m>(Hrp::parse_unchecked("other"), &card(None).to_bytes()).unwrap();
+        assert!(matches!(ContactCard::decode(&foreign), Err(CardError::InvalidEncoding(_))));
+
+        let short = bech32::encode::<Bech32m>(hrp(), &[CARD_VERSION, 0, 2]).unwrap();
+        assert_eq!(ContactCard::decode(&short), Err(CardError::Truncated));
+    }
+
+    #[test]
+    fn test_own_card_omits_default_port_and_truncates_name() {
+        let mut identity = Identity::generate("é".repeat(40)).unwrap();
+        assert_eq!(ContactCard::for_identity(&identity, DEFAULT_LISTEN_PORT), Err(CardError::MissingAddress));
+
+        identity.yggdrasil_address = Some("200:1234::1".to_string());
+        let own = ContactCard::for_identity(&identity, DEFAULT_LISTEN_PORT).unwrap();
+        assert_eq!(own.address.port, None);
+        assert_eq!(own.display_name.len(), MAX_NAME_BYTES);
+        assert_eq!(own.fingerprint_text(), Some(identity.fingerprint()));
+        assert_eq!(own.to_contact().unwrap().card_fingerprint, Some(identity.fingerprint()));
+    }
+}
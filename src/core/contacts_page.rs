@@ -0,0 +1,451 @@
+use crate::core::chat_data::{ChatDataProvider, ChatId, RELOAD_TIMEOUT};
+use crate::core::identity;
+use crate::core::messenger::YggdrasilMessenger;
+use crate::core::routes::Route;
+use crate::database::{self, error::DatabaseError};
+use crate::database::contact_repository::{ContactError, ContactRepository};
+use crate::database::models::{Contact, ValidationError};
+use crate::database::proxy_url::{self, ProxyConfig};
+use dioxus::prelude::*;
+use std::sync::Arc;
+
+/// Contacts Page
+///
+/// Lists contacts from the `contacts` table, filtered by a search over names
+/// and addresses. Inactive contacts are hidden from the chat list and only
+/// shown here on request. Each contact can be edited in place (display name,
+/// proxy, notes), switched between active and inactive, or deleted after a
+/// confirmation, which also removes its messages. Changes are pushed to the
+/// chat list through the `ChatDataProvider` context when one is provided, and
+/// a deactivated or deleted contact is disconnected when a messenger is.
+///
+/// Proxy passwords are never shown: the list and the edit field display the
+/// masked proxy URL, and a password left masked keeps the stored one.
+/// Each contact shows the fingerprint of its pinned key, or of the key its
+/// contact card promised until one is pinned, for comparing with the contact.
+#[component]
+pub fn ContactsPage() -> Element {
+    let chat_data = try_use_context::<ChatDataProvider>();
+    let messenger = try_use_context::<Arc<YggdrasilMessenger>>();
+
+    let mut query = use_signal(String::new);
+    let mut show_inactive = use_signal(|| false);
+    let mut contacts = use_resource(move || async move { list_contacts(query(), show_inactive()).await });
+
+    let mut editing = use_signal(|| None::<Contact>);    // Draft of the contact being edited
+    let mut proxy_input = use_signal(String::new);    // Proxy field text, starts out masked
+    let mut confirm_delete = use_signal(|| None::<Contact>);
+    let mut field_error = use_signal(|| None::<ContactError>);
+    let mut action_error = use_signal(|| None::<String>);
+    let mut busy = use_signal(|| false);
+
+    // Errors about an editable field go under it, anything else above the list
+    let mut show_error = move |e: ContactError| {
+        if Field::of(&e).is_some() {
+            field_error.set(Some(e));
+        } else {
+            action_error.set(Some(e.to_string()));
+        }
+    };
+
+    // Runs a change, then reloads the list here and in the chat list
+    let run = use_callback(move |change: Change| {
+        if *busy.peek() {
+            return;
+        }
+        busy.set(true);
+        action_error.set(None);
+
+        let chat_data = chat_data.clone();
+        let messenger = messenger.clone();
+        spawn(async move {
+            let disconnects = change.disconnects().map(str::to_string);
+            match apply_change(change).await {
+                Ok(()) => {
+                    if let Some(messenger) = messenger
+                        && let Some(address) = disconnects
+                    {
+                        messenger.disconnect_peer(&address).await;
+                    }
+                    editing.set(None);
+                    confirm_delete.set(None);
+                    field_error.set(None);
+                    contacts.restart();
+                    if let Some(chat_data) = chat_data
+                        && let Err(e) = chat_data.refresh(RELOAD_TIMEOUT).await
+                    {
+                        tracing::warn!("Failed to refresh chats after contact change: {}", e);
+                    }
+                }
+                Err(e) => show_error(e),
+            }
+            busy.set(false);
+        });
+    });
+
+    let on_save = move |evt: FormEvent| {
+        evt.prevent_default();
+        let Some(contact) = editing.read().clone() else {
+            return;
+        };
+
+        let notes = contact.notes.as_deref().map(str::trim).unwrap_or_default().to_string();
+        let contact = Contact {
+            display_name: contact.display_name.trim().to_string(),
+            socks5_proxy: proxy_url::unmask(&proxy_input.read(), &contact.socks5_proxy),
+            notes: (!notes.is_empty()).then_some(notes),
+            ..contact
+        };
+
+        // Validate up front so mistakes show without a database round trip
+        if let Err(e) = contact.validate() {
+            show_error(ContactError::Validation(e));
+            return;
+        }
+        run.call(Change::Update(contact));
+    };
+
+    let error_for = move |field: Field| -> Option<String> {
+        field_error
+            .read()
+            .as_ref()
+            .filter(|e| Field::of(e) == Some(field))
+            .map(|e| e.to_string())
+    };
+
+    rsx! {
+        div {
+            class: "contacts-container",
+
+            header {
+                class: "top-bar",
+                Link {
+                    to: Route::Home {},
+                    class: "nav-button back-button",
+                    aria_label: "Back to chats",
+                    "←"
+                }
+                h1 { "Contacts" }
+                Link {
+                    to: Route::AddContact {},
+                    class: "nav-button add-contact-button",
+                    aria_label: "Add contact",
+                    "+"
+                }
+            }
+
+            div {
+                class: "contacts-filter",
+                input {
+                    r#type: "search",
+                    placeholder: "Search by name or address",
+                    aria_label: "Search contacts",
+                    value: "{query}",
+                    oninput: move |e| query.set(e.value()),
+                }
+                div {
+                    class: "form-field checkbox-field",
+                    input {
+                        id: "show-inactive",
+                        r#type: "checkbox",
+                        checked: show_inactive(),
+                        onchange: move |e| show_inactive.set(e.checked()),
+                    }
+                    label { r#for: "show-inactive", "Show inactive contacts" }
+                }
+            }
+
+            if let Some(message) = action_error.read().as_ref() {
+                p { class: "form-error", role: "alert", "{message}" }
+            }
+
+            main {
+                class: "contact-list",
+                match &*contacts.read() {
+                    None => rsx! {
+                        div {
+                            class: "loading-container",
+                            aria_busy: "true",
+                            div { class: "loading-spinner" }
+                            p { "Loading contacts..." }
+                        }
+                    },
+                    Some(Err(e)) => rsx! {
+                        p { class: "form-error", role: "alert", "{e}" }
+                    },
+                    Some(Ok(list)) if list.is_empty() => rsx! {
+                        div {
+                            class: "empty-state",
+                            if query.read().trim().is_empty() {
+                                p { "No contacts yet" }
+                            } else {
+                                p { "No contacts match \"{query}\"" }
+                            }
+                        }
+                    },
+                    Some(Ok(list)) => rsx! {
+                        ul {
+                            for contact in list.iter().cloned() {
+                                li {
+                                    key: "{contact.id.unwrap_or_default()}",
+                                    class: if contact.is_active { "contact-row" } else { "contact-row inactive" },
+
+                                    if editing.read().as_ref().is_some_and(|draft| draft.id == contact.id) {
+                                        form {
+                                            class: "contact-form",
+                                            onsubmit: on_save,
+
+                                            div {
+                                                class: "form-field",
+                                                label { r#for: "edit-display-name", "Display name" }
+                                                input {
+                                                    id: "edit-display-name",
+                                                    r#type: "text",
+                                                    value: editing.read().as_ref().map(|d| d.display_name.clone()),
+                                                    aria_invalid: error_for(Field::DisplayName).is_some(),
+                                                    oninput: move |e| {
+                                                        if let Some(draft) = editing.write().as_mut() {
+                                                            draft.display_name = e.value();
+                                                        }
+                                                    },
+                                                }
+                                                if let Some(message) = error_for(Field::DisplayName) {
+                                                    p { class: "field-error", role: "alert", "{message}" }
+                                                }
+                                            }
+
+                                            div {
+                                                class: "form-field",
+                                                label { r#for: "edit-proxy", "SOCKS5 proxy (leave empty to use the default)" }
+                                                input {
+                                                    id: "edit-proxy",
+                                                    r#type: "text",
+                                                    placeholder: "socks5://127.0.0.1:1080",
+                                                    value: "{proxy_input}",
+                                                    aria_invalid: error_for(Field::Proxy).is_some(),
+                                                    oninput: move |e| proxy_input.set(e.value()),
+                                                }
+                                                if let Some(message) = error_for(Field::Proxy) {
+                                                    p { class: "field-error", role: "alert", "{message}" }
+                                                }
+                                            }
+
+                                            div {
+                                                class: "form-field",
+                                                label { r#for: "edit-notes", "Notes" }
+                                                textarea {
+                                                    id: "edit-notes",
+                                                    rows: "3",
+                                                    value: editing.read().as_ref().and_then(|d| d.notes.clone()),
+                                                    oninput: move |e| {
+                                                        if let Some(draft) = editing.write().as_mut() {
+                                                            draft.notes = Some(e.value());
+                                                        }
+                                                    },
+                                                }
+                                            }
+
+                                            div {
+                                                class: "form-actions",
+                                                button {
+                                                    class: "primary-button",
+                                                    r#type: "submit",
+                                                    disabled: busy(),
+                                                    "Save"
+                                                }
+                                                button {
+                                                    r#type: "button",
+                                                    onclick: move |_| {
+                                                        editing.set(None);
+                                                        field_error.set(None);
+                                                    },
+                                                    "Cancel"
+                                                }
+                                            }
+                                        }
+                                    } else {
+                                        ContactSummary { contact: contact.clone() }
+
+                                        div {
+                                            class: "contact-actions",
+                                            button {
+                                                r#type: "button",
+                                                onclick: {
+                                                    let contact = contact.clone();
+                                                    move |_| {
+                                                        field_error.set(None);
+                                                        proxy_input.set(masked_proxy(&contact.socks5_proxy));
+                                                        editing.set(Some(contact.clone()));
+                                                    }
+                                                },
+                                                "Edit"
+                                            }
+                                            button {
+                                                r#type: "button",
+                                                disabled: busy(),
+                                                onclick: {
+                                                    let contact = contact.clone();
+                                                    move |_| {
+                                                        run.call(Change::SetActive(contact.clone(), !contact.is_active));
+                                                    }
+                                                },
+                                                if contact.is_active { "Deactivate" } else { "Activate" }
+                                            }
+                                            button {
+                                                class: "danger-button",
+                                                r#type: "button",
+                                                onclick: {
+                                                    let contact = contact.clone();
+                                                    move |_| confirm_delete.set(Some(contact.clone()))
+                                                },
+                                                "Delete"
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    },
+                }
+            }
+
+            if let Some(contact) = confirm_delete.read().clone() {
+                div {
+                    class: "confirm-dialog",
+                    role: "alertdialog",
+                    aria_modal: "true",
+                    aria_labelledby: "confirm-delete-title",
+
+                    h2 { id: "confirm-delete-title", "Delete {contact.display_name}?" }
+                    p { "The contact and all messages with them are removed permanently. Deactivate the contact instead to keep its history." }
+                    div {
+                        class: "form-actions",
+                        button {
+                            class: "danger-button",
+                            r#type: "button",
+                            disabled: busy(),
+                            onclick: move |_| run.call(Change::Delete(contact.clone())),
+                            "Delete"
+                        }
+                        button {
+                            r#type: "button",
+                            onclick: move |_| confirm_delete.set(None),
+                            "Cancel"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// One contact's details in the list
+#[component]
+fn ContactSummary(contact: Contact) -> Element {
+    rsx! {
+        div {
+            class: "contact-summary",
+            if let Some(id) = contact.id {
+                Link {
+                    to: Route::Conversation { id: ChatId::from(id) },
+                    class: "contact-name",
+                    "{contact.display_name}"
+                }
+            } else {
+                span { class: "contact-name", "{contact.display_name}" }
+            }
+            span { class: "contact-address", "{contact.yggdrasil_address}" }
+            if contact.is_hidden_peer {
+                span { class: "badge hidden-peer", "Hidden peer" }
+            }
+            if !contact.is_active {
+                span { class: "badge inactive", "Inactive" }
+            }
+            if !contact.socks5_proxy.is_empty() {
+                span { class: "contact-proxy", "via {masked_proxy(&contact.socks5_proxy)}" }
+            }
+            if let Some(fingerprint) = contact.public_key.as_deref().and_then(identity::pinned_fingerprint) {
+                p { class: "card-fingerprint", "Key fingerprint: " code { "{fingerprint}" } }
+            } else if let Some(fingerprint) = contact.card_fingerprint.as_ref() {
+                p { class: "card-fingerprint", "Expected key fingerprint: " code { "{fingerprint}" } }
+            } else {
+                p { class: "card-fingerprint", "No key pinned yet" }
+            }
+            if let Some(notes) = contact.notes.as_ref() {
+                p { class: "contact-notes", "{notes}" }
+            }
+        }
+    }
+}
+
+/// A change made from the contacts page
+#[derive(Clone, Debug, PartialEq)]
+enum Change {
+    Update(Contact),
+    SetActive(Contact, bool),
+    Delete(Contact),
+}
+
+impl Change {
+    /// Address of the peer whose session should end once the change is saved
+    fn disconnects(&self) -> Option<&str> {
+        match self {
+            Change::SetActive(contact, false) | Change::Delete(contact) => Some(&contact.yggdrasil_address),
+            Change::Update(_) | Change::SetActive(_, true) => None,
+        }
+    }
+}
+
+/// Form field an error is displayed under
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    DisplayName,
+    Proxy,
+}
+
+impl Field {
+    fn of(error: &ContactError) -> Option<Self> {
+        match error {
+            ContactError::Validation(ValidationError::InvalidDisplayName) => Some(Field::DisplayName),
+            ContactError::Validation(ValidationError::InvalidProxy | ValidationError::InvalidProxyAuth) => {
+                Some(Field::Proxy)
+            }
+            _ => None,
+        }
+    }
+}
+
+async fn list_contacts(query: String, include_inactive: bool) -> Result<Vec<Contact>, ContactError> {
+    repository()?.list(&query, include_inactive).await
+}
+
+async fn apply_change(change: Change) -> Result<(), ContactError> {
+    let repo = repository()?;
+    match change {
+        Change::Update(contact) => repo.update(contact).await.map(drop),
+        Change::SetActive(contact, is_active) => repo.set_active(saved_id(&contact)?, is_active).await.map(drop),
+        Change::Delete(contact) => repo.delete(saved_id(&contact)?).await,
+    }
+}
+
+fn saved_id(contact: &Contact) -> Result<i32, ContactError> {
+    contact
+        .id
+        .ok_or_else(|| ContactError::Database(DatabaseError::Query("Contact is not saved".to_string())))
+}
+
+/// A stored proxy URL with its password hidden
+fn masked_proxy(proxy: &str) -> String {
+    match ProxyConfig::parse(proxy) {
+        Ok(config) => config.to_string(),
+        Err(_) if proxy.trim().is_empty() => String::new(),
+        Err(_) => "invalid proxy".to_string(),
+    }
+}
+
+fn repository() -> Result<ContactRepository, ContactError> {
+    let db = database::get_db()
+        .ok_or_else(|| DatabaseError::Connection("Database not initialized".to_string()))?;
+
+    Ok(ContactRepository::new(db))
+}
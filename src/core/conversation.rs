@@ -0,0 +1,727 @@
+use crate::core::chat_data::{ChatDataProvider, ChatId, RELOAD_TIMEOUT};
+use crate::core::events::MessengerEvent;
+use crate::core::messenger::YggdrasilMessenger;
+use crate::core::presence::PresenceDot;
+use crate::core::protocol::PresenceStatus;
+use crate::core::routes::Route;
+use crate::database::{self, error::DatabaseError};
+use crate::database::contact_repository::ContactRepository;
+use crate::database::message_repository::MessageRepository;
+use crate::database::message_schema::Model as Message;
+use crate::database::models::Contact;
+use crate::database::outbox_repository::OutboxRepository;
+use crate::database::outbox_schema::DeliveryStatus;
+use dioxus::prelude::*;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Messages loaded per page of history
+const PAGE_SIZE: u64 = 50;
+
+/// Distance from the top, in pixels, at which older history is loaded
+const LOAD_OLDER_THRESHOLD: f64 = 80.0;
+
+/// Distance from the bottom, in pixels, still counted as "at the bottom"
+const STICK_TO_BOTTOM_THRESHOLD: f64 = 40.0;
+
+/// How often the round-trip time in the header is refreshed
+const ROUND_TRIP_REFRESH: Duration = Duration::from_secs(5);
+
+/// What the user sees next to a message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MessageStatus {
+    Received,    // Incoming message
+    Pending,    // Stored, not yet acknowledged by the peer
+    Delivered,
+    Read,
+    Failed,    // Gave up after too many attempts
+}
+
+impl MessageStatus {
+    fn of(message: &Message, outbox: Option<DeliveryStatus>) -> Self {
+        if !message.is_outgoing {
+            MessageStatus::Received
+        } else if message.read_at.is_some() {
+            MessageStatus::Read
+        } else if message.delivered_at.is_some() {
+            MessageStatus::Delivered
+        } else if outbox == Some(DeliveryStatus::Failed) {
+            MessageStatus::Failed
+        } else {
+            MessageStatus::Pending
+        }
+    }
+
+    /// Whether the status can still change
+    fn is_settled(&self) -> bool {
+        matches!(self, MessageStatus::Received | MessageStatus::Read)
+    }
+
+    fn css_class(&self) -> &'static str {
+        match self {
+            MessageStatus::Received => "received",
+            MessageStatus::Pending => "pending",
+            MessageStatus::Delivered => "delivered",
+            MessageStatus::Read => "read",
+            MessageStatus::Failed => "failed",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            MessageStatus::Received => "",
+            MessageStatus::Pending => "Sending",
+            MessageStatus::Delivered => "Delivered",
+            MessageStatus::Read => "Read",
+            MessageStatus::Failed => "Not delivered",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct ChatMessage {
+    message: Message,
+    status: MessageStatus,
+}
+
+/// Part of the loaded history to reload after an event
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Refresh {
+    Nothing,
+    From(i32),    // Everything from this id on, including messages not loaded yet
+    Messages(Vec<i32>),    // Only these loaded messages
+}
+
+/// Conversation Page
+///
+/// Shows the history of one chat and a composer. History is loaded from the
+/// database a page at a time, newest first; scrolling to the top loads the
+/// previous page. New messages and receipts are picked up from the
+/// messenger's event stream, and the view follows new messages as long as
+/// the user is scrolled to the bottom. The header shows whether the contact
+/// is online, away or offline, and the latest heartbeat round-trip time
+/// while connected. Opening the chat dials the contact if it isn't
+/// connected. Opening it, and every message that arrives while it is open,
+/// marks the chat as read.
+///
+/// Sending goes through the messenger's durable send path, so messages
+/// written while the contact is offline are kept and show as "Sending"
+/// until the peer acknowledges them. Messages that failed can be retried or
+/// discarded, which deletes them from the history.
+#[component]
+pub fn Conversation(id: ChatId) -> Element {
+    let messenger = try_use_context::<Arc<YggdrasilMessenger>>();
+    let chat_data = try_use_context::<ChatDataProvider>();
+
+    let mut contact = use_signal(|| None::<Contact>);
+    let mut messages = use_signal(Vec::<ChatMessage>::new);
+    let mut older_cursor = use_signal(|| None::<i32>);    // Id to load older history before
+    let mut loading_older = use_signal(|| false);
+    let mut load_error = use_signal(|| None::<String>);
+    let mut presence = use_signal(|| PresenceStatus::Offline);
+    let mut round_trip = use_signal(|| None::<Duration>);
+
+    let mut draft = use_signal(String::new);
+    let mut send_error = use_signal(|| None::<String>);
+    let mut sending = use_signal(|| false);
+
+    let mut at_bottom = use_signal(|| true);
+    let mut end_anchor = use_signal(|| None::<Rc<MountedData>>);
+    let mut first_anchor = use_signal(|| None::<Rc<MountedData>>);    // Oldest loaded message
+    let mut keep_in_view = use_signal(|| None::<Rc<MountedData>>);    // Message to hold in place after older history is added
+
+    // (Re)load when navigating to a different chat
+    let open_messenger = messenger.clone();
+    use_effect(use_reactive!(|id| {
+        contact.set(None);
+        messages.set(Vec::new());
+        older_cursor.set(None);
+        load_error.set(None);
+        at_bottom.set(true);
+
+        let messenger = open_messenger.clone();
+        spawn(async move {
+            match open_chat(&id).await {
+                Ok((loaded, page, cursor)) => {
+                    if let Some(messenger) = &messenger {
+                        messenger.presence().record_own_activity();
+                        messenger.ensure_connected(&loaded);
+                        presence.set(messenger.presence().status(&loaded.yggdrasil_address));
+                        // The history shown comes from the database, so the buffered copies can go
+                        if let Some(contact_id) = loaded.id {
+                            messenger.receive_messages_for(contact_id, usize::MAX).await;
+                        }
+                        if let Err(e) = messenger.mark_chat_read(&loaded).await {
+                            tracing::warn!("Failed to mark chat {} read: {}", id, e);
+                        }
+                    }
+                    contact.set(Some(loaded));
+                    messages.set(page);
+                    older_cursor.set(cursor);
+                }
+                Err(e) => load_error.set(Some(e)),
+            }
+        });
+    }));
+
+    // Poll the round-trip time, which changes with every heartbeat and has no event
+    let rtt_messenger = messenger.clone();
+    use_future(move || {
+        let messenger = rtt_messenger.clone();
+        async move {
+            let Some(messenger) = messenger else { return };
+            let mut ticker = tokio::time::interval(ROUND_TRIP_REFRESH);
+            loop {
+                ticker.tick().await;
+                let address = contact.peek().as_ref().map(|c| c.yggdrasil_address.clone());
+                round_trip.set(address.and_then(|address| messenger.round_trip_time(&address)));
+            }
+        }
+    });
+
+    // Follow the contact's messages and receipts as they happen
+    let event_messenger = messenger.clone();
+    use_future(move || {
+        let messenger = event_messenger.clone();
+        async move {
+            let Some(messenger) = messenger else { return };
+            let mut events = messenger.subscribe();
+
+            loop {
+                // After a lag some events are lost, so refresh as if a message arrived
+                let event = match events.recv().await {
+                    Ok(event) => Some(event),
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::debug!("Conversation missed {} events, reloading", skipped);
+                        None
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+
+                let Some(current) = contact.peek().clone() else { continue };
+                let Some(contact_id) = current.id else { continue };
+                if event.as_ref().is_some_and(|e| e.peer_address() != current.yggdrasil_address) {
+                    continue;
+                }
+                if let Some(MessengerEvent::PresenceChanged { status, .. }) = event {
+                    presence.set(status);
+                    continue;
+                }
+
+                let arrived = matches!(event, None | Some(MessengerEvent::MessageReceived(_)));
+                let refresh = refresh_for(&messages.peek(), event.as_ref());
+
+                let result = match refresh {
+                    Refresh::Nothing => Ok(()),
+                    Refresh::From(from_id) => {
+                        load_since(contact_id, from_id).await.map(|fresh| merge_tail(&mut messages.write(), from_id, fresh))
+                    }
+                    Refresh::Messages(ids) => {
+                        load_many(contact_id, &ids).await.map(|fresh| replace_messages(&mut messages.write(), fresh))
+                    }
+                };
+                if let Err(e) = result {
+                    tracing::warn!("Failed to refresh chat {}: {}", contact_id, e);
+                }
+
+                if arrived {
+                    // Shown here, so nobody needs to pull them from the buffer
+                    messenger.receive_messages_for(contact_id, usize::MAX).await;
+                    if let Err(e) = messenger.mark_chat_read(&current).await {
+                        tracing::warn!("Failed to mark chat {} read: {}", contact_id, e);
+                    }
+                }
+            }
+        }
+    });
+
+    // Follow new messages while the user is at the bottom; keep the view
+    // where it was when older history was added above it
+    use_effect(move || {
+        let _ = messages.read().len();
+        if let Some(anchor) = keep_in_view.take() {
+            spawn(async move {
+                let _ = anchor.scroll_to(ScrollBehavior::Instant).await;
+            });
+            return;
+        }
+        if !*at_bottom.peek() {
+            return;
+        }
+        if let Some(anchor) = end_anchor.peek().clone() {
+            spawn(async move {
+                let _ = anchor.scroll_to(ScrollBehavior::Smooth).await;
+            });
+        }
+    });
+
+    let load_older = use_callback(move |_: ()| {
+        let Some(cursor) = *older_cursor.peek() else { return };
+        let Some(contact_id) = contact.peek().as_ref().and_then(|c| c.id) else { return };
+        if *loading_older.peek() {
+            return;
+        }
+
+        loading_older.set(true);
+        spawn(async move {
+            match load_page(contact_id, Some(cursor)).await {
+                Ok((older, next_cursor)) => {
+                    if !older.is_empty() {
+                        keep_in_view.set(first_anchor.peek().clone());
+                    }
+                    messages.write().splice(0..0, older);
+                    older_cursor.set(next_cursor);
+                }
+                Err(e) => load_error.set(Some(e)),
+            }
+            loading_older.set(false);
+        });
+    });
+
+    let retry_messenger = messenger.clone();
+    let retry = use_callback(move |_: ()| {
+        let Some(current) = contact.peek().clone() else { return };
+        let Some(contact_id) = current.id else { return };
+        let Some(messenger) = retry_messenger.clone() else {
+            send_error.set(Some("Messaging is not running".to_string()));
+            return;
+        };
+        let first_failed = messages.peek().iter().find(|m| m.status == MessageStatus::Failed).map(|m| m.message.id);
+        let Some(from_id) = first_failed else { return };
+
+        send_error.set(None);
+        spawn(async move {
+            if let Err(e) = messenger.retry_failed(&current).await {
+                send_error.set(Some(e.to_string()));
+                return;
+            }
+            match load_since(contact_id, from_id).await {
+                Ok(fresh) => merge_tail(&mut messages.write(), from_id, fresh),
+                Err(e) => tracing::warn!("Failed to refresh chat {}: {}", contact_id, e),
+            }
+        });
+    });
+
+    let discard = use_callback(move |message_id: i32| {
+        let chat_data = chat_data.clone();
+        send_error.set(None);
+        spawn(async move {
+            if let Err(e) = discard_message(message_id).await {
+                send_error.set(Some(e));
+                return;
+            }
+            messages.write().retain(|m| m.message.id != message_id);
+
+            // The chat list may be showing it as the last message
+            if let Some(chat_data) = chat_data
+                && let Err(e) = chat_data.refresh(RELOAD_TIMEOUT).await
+            {
+                tracing::warn!("Failed to refresh chats after discarding a message: {}", e);
+            }
+        });
+    });
+
+    let send = use_callback(move |_: ()| {
+        let body = draft.peek().trim().to_string();
+        if body.is_empty() || *sending.peek() {
+            return;
+        }
+        let Some(current) = contact.peek().clone() else { return };
+        let Some(messenger) = messenger.clone() else {
+            send_error.set(Some("Messaging is not running".to_string()));
+            return;
+        };
+
+        send_error.set(None);
+        sending.set(true);
+        spawn(async move {
+            match messenger.send_message(&current, body).await {
+                Ok(stored) => {
+                    draft.set(String::new());
+                    at_bottom.set(true);
+                    let status = MessageStatus::of(&stored, None);
+                    insert_sent(&mut messages.write(), ChatMessage { message: stored, status });
+                }
+                Err(e) => send_error.set(Some(e.to_string())),
+            }
+            sending.set(false);
+        });
+    });
+
+    let title = contact.read().as_ref().map(|c| c.display_name.clone()).unwrap_or_default();
+
+    rsx! {
+        div {
+            class: "conversation-container",
+
+            header {
+                class: "top-bar",
+                Link {
+                    to: Route::Home {},
+                    class: "nav-button back-button",
+                    aria_label: "Back to chats",
+                    "←"
+                }
+                h1 { "{title}" }
+                if contact.read().is_some() {
+                    PresenceDot { status: presence() }
+                }
+                if let Some(rtt) = round_trip() {
+                    span { class: "round-trip", title: "Round-trip time", "{rtt.as_millis()} ms" }
+                }
+            }
+
+            if let Some(message) = load_error.read().as_ref() {
+                p { class: "form-error", role: "alert", "{message}" }
+            }
+
+            div {
+                class: "message-list",
+                role: "log",
+                aria_live: "polite",
+                onscroll: move |e| {
+                    let data = e.data();
+                    let top = data.scroll_top();
+                    let from_bottom = data.scroll_height() as f64 - top - data.client_height() as f64;
+                    at_bottom.set(from_bottom < STICK_TO_BOTTOM_THRESHOLD);
+                    if top < LOAD_OLDER_THRESHOLD {
+                        load_older.call(());
+                    }
+                },
+
+                if older_cursor.read().is_some() {
+                    button {
+                        class: "load-older-button",
+                        disabled: *loading_older.read(),
+                        onclick: move |_| load_older.call(()),
+                        if *loading_older.read() { "Loading..." } else { "Load older messages" }
+                    }
+                }
+
+                for (index, entry) in messages.read().iter().enumerate() {
+                    div {
+                        key: "{entry.message.id}",
+                        class: if entry.message.is_outgoing { "message outgoing" } else { "message incoming" },
+                        onmounted: move |e| {
+                            if index == 0 {
+                                first_anchor.set(Some(e.data()));
+                            }
+                        },
+                        p { class: "message-body", "{entry.message.body}" }
+                        div {
+                            class: "message-meta",
+                            span {
+                                class: "message-time",
+                                {entry.message.sent_at.with_timezone(&chrono::Local).format("%H:%M").to_string()}
+                            }
+                            if entry.message.is_outgoing {
+                                span {
+                                    class: "message-status status-{entry.status.css_class()}",
+                                    "{entry.status.label()}"
+                                }
+                                if entry.status == MessageStatus::Failed {
+                                    button {
+                                        class: "retry-button",
+                                        onclick: move |_| retry.call(()),
+                                        "Retry"
+                                    }
+                                    button {
+                                        class: "retry-button",
+                                        onclick: {
+                                            let message_id = entry.message.id;
+                                            move |_| discard.call(message_id)
+                                        },
+                                        "Discard"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+
+                div {
+                    class: "scroll-anchor",
+                    onmounted: move |e| end_anchor.set(Some(e.data())),
+                }
+            }
+
+            form {
+                class: "composer",
+                onsubmit: move |evt: FormEvent| {
+                    evt.prevent_default();
+                    send.call(());
+                },
+
+                textarea {
+                    class: "composer-input",
+                    rows: "2",
+                    placeholder: "Write a message",
+                    aria_label: "Message",
+                    value: "{draft}",
+                    oninput: move |e| draft.set(e.value()),
+                    onkeydown: move |e| {
+                        // Enter sends, Shift+Enter starts a new line
+                        if e.key() == Key::Enter && !e.modifiers().shift() {
+                            e.prevent_default();
+                            send.call(());
+                        }
+                    },
+                }
+                button {
+                    class: "primary-button",
+                    r#type: "submit",
+                    disabled: *sending.read() || draft.read().trim().is_empty() || contact.read().is_none(),
+                    "Send"
+                }
+                if let Some(message) = send_error.read().as_ref() {
+                    p { class: "form-error", role: "alert", "{message}" }
+                }
+            }
+        }
+    }
+}
+
+/// Which part of the loaded history an event may have changed
+///
+/// New messages are picked up after the newest loaded one, and receipts only
+/// reload the messages they name. Session changes may have failed pending
+/// sends. After missed events (`None`) everything from the oldest message
+/// whose status can still change is reloaded.
+fn refresh_for(loaded: &[ChatMessage], event: Option<&MessengerEvent>) -> Refresh {
+    let after_newest = || Refresh::From(loaded.last().map(|m| m.message.id + 1).unwrap_or(0));
+    let carried_by = |envelope_ids: &[&str]| {
+        let ids: Vec<i32> = loaded
+            .iter()
+            .filter(|m| m.message.is_outgoing)
+            .filter(|m| m.message.envelope_id.as_deref().is_some_and(|id| envelope_ids.contains(&id)))
+            .map(|m| m.message.id)
+            .collect();
+        if ids.is_empty() { Refresh::Nothing } else { Refresh::Messages(ids) }
+    };
+
+    match event {
+        None => match loaded.iter().find(|m| !m.status.is_settled()) {
+            Some(unsettled) => Refresh::From(unsettled.message.id),
+            None => after_newest(),
+        },
+        Some(MessengerEvent::MessageReceived(_)) => after_newest(),
+        Some(MessengerEvent::MessageDelivered { message_id, .. }) => carried_by(&[message_id.as_str()]),
+        Some(MessengerEvent::MessagesRead { message_ids, .. }) => {
+            carried_by(&message_ids.iter().map(|id| id.as_str()).collect::<Vec<_>>())
+        }
+        Some(MessengerEvent::Session(_)) => {
+            let pending: Vec<i32> =
+                loaded.iter().filter(|m| m.status == MessageStatus::Pending).map(|m| m.message.id).collect();
+            if pending.is_empty() { Refresh::Nothing } else { Refresh::Messages(pending) }
+        }
+        Some(_) => Refresh::Nothing,
+    }
+}
+
+/// Replaces everything from `from_id` on with freshly loaded messages
+fn merge_tail(loaded: &mut Vec<ChatMessage>, from_id: i32, fresh: Vec<ChatMessage>) {
+    loaded.retain(|m| m.message.id < from_id);
+    loaded.extend(fresh);
+}
+
+/// Adds a message we just sent, unless a refresh has already loaded it
+fn insert_sent(loaded: &mut Vec<ChatMessage>, sent: ChatMessage) {
+    if let Err(at) = loaded.binary_search_by_key(&sent.message.id, |m| m.message.id) {
+        loaded.insert(at, sent);
+    }
+}
+
+/// Swaps in freshly loaded copies of messages that are already shown
+fn replace_messages(loaded: &mut [ChatMessage], fresh: Vec<ChatMessage>) {
+    for update in fresh {
+        if let Some(shown) = loaded.iter_mut().find(|m| m.message.id == update.message.id) {
+            *shown = update;
+        }
+    }
+}
+
+async fn open_chat(id: &ChatId) -> Result<(Contact, Vec<ChatMessage>, Option<i32>), String> {
+    let contact_id = id.contact_id().ok_or_else(|| format!("Invalid chat id '{}'", id))?;
+
+    let contact = ContactRepository::new(db()?)
+        .find(contact_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "This contact no longer exists".to_string())?;
+
+    let (page, cursor) = load_page(contact_id, None).await?;
+    Ok((contact, page, cursor))
+}
+
+async fn load_page(contact_id: i32, before: Option<i32>) -> Result<(Vec<ChatMessage>, Option<i32>), String> {
+    let page = MessageRepository::new(db()?)
+        .page_before(contact_id, before, PAGE_SIZE)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok((with_status(page.messages).await?, page.next_cursor))
+}
+
+async fn load_since(contact_id: i32, from_id: i32) -> Result<Vec<ChatMessage>, String> {
+    let messages = MessageRepository::new(db()?)
+        .since(contact_id, from_id)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    with_status(messages).await
+}
+
+async fn load_many(contact_id: i32, ids: &[i32]) -> Result<Vec<ChatMessage>, String> {
+    let messages = MessageRepository::new(db()?)
+        .find_many(contact_id, ids)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    with_status(messages).await
+}
+
+/// Deletes a message that was never delivered; its outbox entry cascades
+async fn discard_message(message_id: i32) -> Result<(), String> {
+    MessageRepository::new(db()?)
+        .delete(message_id)
+        .await
+        .map(drop)
+        .map_err(|e| e.to_string())
+}
+
+/// Pairs messages with their status, looking up the outbox for outgoing ones
+async fn with_status(messages: Vec<Message>) -> Result<Vec<ChatMessage>, String> {
+    let outgoing: Vec<i32> = messages.iter().filter(|m| m.is_outgoing).map(|m| m.id).collect();
+    let outbox = if outgoing.is_empty() {
+        Default::default()
+    } else {
+        OutboxRepository::new(db()?).statuses(&outgoing).await.map_err(|e| e.to_string())?
+    };
+
+    Ok(messages
+        .into_iter()
+        .map(|message| {
+            let status = MessageStatus::of(&message, outbox.get(&message.id).copied());
+            ChatMessage { message, status }
+        })
+        .collect())
+}
+
+fn db() -> Result<Arc<sea_orm::DatabaseConnection>, String> {
+    database::get_db()
+        .ok_or_else(|| DatabaseError::Connection("Database not initialized".to_string()).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::ReceivedMessage;
+    use crate::core::protocol::MessageId;
+
+    fn message(id: i32, is_outgoing: bool) -> Message {
+        Message {
+            id,
+            contact_id: 1,
+            is_outgoing,
+            body: format!("m{}", id),
+            sent_at: chrono::Utc::now(),
+            received_at: None,
+            created_at: chrono::Utc::now(),
+            envelope_id: Some(format!("env-{}", id)),
+            delivered_at: None,
+            read_at: None,
+        }
+    }
+
+    fn entry(id: i32, status: MessageStatus) -> ChatMessage {
+        ChatMessage { message: message(id, status != MessageStatus::Received), status }
+    }
+
+    #[test]
+    fn test_status_follows_receipts_then_outbox() {
+        let incoming = message(1, false);
+        let mut outgoing = message(2, true);
+
+        assert_eq!(MessageStatus::of(&incoming, None), MessageStatus::Received);
+        assert_eq!(MessageStatus::of(&outgoing, Some(DeliveryStatus::Pending)), MessageStatus::Pending);
+        assert_eq!(MessageStatus::of(&outgoing, Some(DeliveryStatus::Failed)), MessageStatus::Failed);
+
+        // A receipt outranks whatever the outbox says
+        outgoing.delivered_at = Some(chrono::Utc::now());
+        assert_eq!(MessageStatus::of(&outgoing, Some(DeliveryStatus::Failed)), MessageStatus::Delivered);
+        outgoing.read_at = Some(chrono::Utc::now());
+        assert_eq!(MessageStatus::of(&outgoing, Some(DeliveryStatus::Sent)), MessageStatus::Read);
+    }
+
+    #[test]
+    fn test_receipts_refresh_only_the_messages_they_name() {
+        let loaded = vec![
+            entry(1, MessageStatus::Delivered),
+            entry(2, MessageStatus::Received),
+            entry(3, MessageStatus::Pending),
+            entry(4, MessageStatus::Pending),
+        ];
+        let delivered = |id: &str| MessengerEvent::MessageDelivered {
+            peer_address: "200::1".to_string(),
+            contact_id: 1,
+            message_id: MessageId::from(id.to_string()),
+        };
+
+        assert_eq!(refresh_for(&loaded, Some(&delivered("env-4"))), Refresh::Messages(vec![4]));
+        assert_eq!(refresh_for(&loaded, Some(&delivered("env-2"))), Refresh::Nothing);    // Not one of ours
+        assert_eq!(refresh_for(&loaded, Some(&delivered("elsewhere"))), Refresh::Nothing);
+
+        let read = MessengerEvent::MessagesRead {
+            peer_address: "200::1".to_string(),
+            contact_id: 1,
+            message_ids: vec![MessageId::from("env-1".to_string()), MessageId::from("env-3".to_string())],
+        };
+        assert_eq!(refresh_for(&loaded, Some(&read)), Refresh::Messages(vec![1, 3]));
+    }
+
+    #[test]
+    fn test_new_and_missed_events_refresh_the_tail() {
+        let loaded = vec![entry(5, MessageStatus::Read), entry(6, MessageStatus::Delivered), entry(7, MessageStatus::Received)];
+        let received = MessengerEvent::MessageReceived(ReceivedMessage {
+            peer_address: "200::1".to_string(),
+            contact_id: 1,
+            message_id: MessageId::new(),
+            sent_at: chrono::Utc::now(),
+            body: "hi".to_string(),
+        });
+        let chat_read = MessengerEvent::ChatRead { peer_address: "200::1".to_string(), contact_id: 1 };
+
+        assert_eq!(refresh_for(&loaded, Some(&received)), Refresh::From(8));
+        assert_eq!(refresh_for(&[], Some(&received)), Refresh::From(0));
+        assert_eq!(refresh_for(&loaded, Some(&chat_read)), Refresh::Nothing);
+
+        // After a lag a delivered message may have been read meanwhile
+        assert_eq!(refresh_for(&loaded, None), Refresh::From(6));
+        assert_eq!(refresh_for(&loaded[..1], None), Refresh::From(6));
+    }
+
+    #[test]
+    fn test_merge_and_replace_keep_history_in_order() {
+        let mut loaded = vec![entry(1, MessageStatus::Received), entry(2, MessageStatus::Pending), entry(3, MessageStatus::Pending)];
+
+        replace_messages(&mut loaded, vec![entry(2, MessageStatus::Delivered), entry(9, MessageStatus::Read)]);
+        let statuses: Vec<_> = loaded.iter().map(|m| (m.message.id, m.status)).collect();
+        assert_eq!(statuses, [(1, MessageStatus::Received), (2, MessageStatus::Delivered), (3, MessageStatus::Pending)]);
+
+        merge_tail(&mut loaded, 3, vec![entry(3, MessageStatus::Failed), entry(4, MessageStatus::Received)]);
+        let statuses: Vec<_> = loaded.iter().map(|m| (m.message.id, m.status)).collect();
+        assert_eq!(
+            statuses,
+            [(1, MessageStatus::Received), (2, MessageStatus::Delivered), (3, MessageStatus::Failed), (4, MessageStatus::Received)]
+        );
+
+        // A sent message a refresh already picked up is not shown twice
+        insert_sent(&mut loaded, entry(4, MessageStatus::Pending));
+        insert_sent(&mut loaded, entry(5, MessageStatus::Pending));
+        let ids: Vec<_> = loaded.iter().map(|m| m.message.id).collect();
+        assert_eq!(ids, [1, 2, 3, 4, 5]);
+        assert_eq!(loaded[3].status, MessageStatus::Received);
+    }
+}
@@ -0,0 +1,103 @@
+/// Messenger Event Bus for Syggrel Chat
+///
+/// Everything that happens on peer sessions is published as a
+/// `MessengerEvent` on a broadcast channel. Any number of consumers (the UI,
+/// notifications, background persistence) can subscribe, and each of them
+/// sees every event published after it subscribed.
+///
+/// Messages and receipts are written to the database before their event is
+/// published. A subscriber that falls more than `EVENT_CAPACITY` events
+/// behind gets `RecvError::Lagged`, loses the oldest events, and should
+/// reload whatever it shows from the database.
+use crate::core::buffer::{Overflow, ReceivedMessage};
+use crate::core::protocol::{MessageId, PresenceStatus};
+use crate::core::supervisor::SessionEvent;
+use tokio::sync::broadcast;
+
+/// Events kept for slow subscribers before the oldest are dropped
+pub const EVENT_CAPACITY: usize = 1024;
+
+/// Something that happened on a peer session
+#[derive(Clone, Debug, PartialEq)]
+pub enum MessengerEvent {
+    MessageReceived(ReceivedMessage),    // New text message, already stored
+    MessageDelivered { peer_address: String, contact_id: i32, message_id: MessageId },    // Peer acked one of ours
+    MessagesRead { peer_address: String, contact_id: i32, message_ids: Vec<MessageId> },    // Peer read some of ours
+    MessageSent { peer_address: String, contact_id: i32, body: String, sent_at: chrono::DateTime<chrono::Utc> },    // Stored in the outbox
+    ChatRead { peer_address: String, contact_id: i32 },    // We read the peer's messages
+    PresenceChanged { peer_address: String, contact_id: i32, status: PresenceStatus },    // Online, away or offline
+    Session(SessionEvent),    // Connect, disconnect, reconnect attempts
+    BufferOverflow(Overflow),    // Unread messages evicted from a chat's buffer
+}
+
+impl MessengerEvent {
+    /// Yggdrasil address of the peer the event concerns
+    pub fn peer_address(&self) -> &str {
+        match self {
+            MessengerEvent::MessageReceived(message) => &message.peer_address,
+            MessengerEvent::MessageDelivered { peer_address, .. } => peer_address,
+            MessengerEvent::MessagesRead { peer_address, .. } => peer_address,
+            MessengerEvent::MessageSent { peer_address, .. } => peer_address,
+            MessengerEvent::ChatRead { peer_address, .. } => peer_address,
+            MessengerEvent::PresenceChanged { peer_address, .. } => peer_address,
+            MessengerEvent::BufferOverflow(overflow) => &overflow.peer_address,
+            MessengerEvent::Session(event) => match event {
+                SessionEvent::Connected { address }
+                | SessionEvent::Disconnected { address, .. }
+                | SessionEvent::ReconnectScheduled { address, .. }
+                | SessionEvent::GaveUp { address, .. } => address,
+            },
+        }
+    }
+}
+
+/// Publishing side of the event stream, cheap to clone into tasks
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<MessengerEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(EVENT_CAPACITY);
+        Self { tx }
+    }
+
+    /// Sends an event to every current subscriber
+    pub fn publish(&self, event: MessengerEvent) {
+        // Nobody subscribed is fine; events are informational
+        let _ = self.tx.send(event);
+    }
+
+    /// Starts receiving events published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<MessengerEvent> {
+        self.tx.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_every_subscriber_sees_each_event() {
+        let bus = EventBus::new();
+        bus.publish(MessengerEvent::Session(SessionEvent::Connected { address: "200::1".to_string() }));
+
+        let mut ui = bus.subscribe();
+        let mut notifications = bus.subscribe();
+        let event = MessengerEvent::Session(SessionEvent::Connected { address: "200::2".to_string() });
+        bus.publish(event.clone());
+
+        // Events published before subscribing are not replayed
+        assert_eq!(ui.recv().await.unwrap(), event);
+        assert_eq!(notifications.recv().await.unwrap(), event);
+        assert_eq!(event.peer_address(), "200::2");
+    }
+}
@@ -0,0 +1,125 @@
+/// Session Heartbeats for Syggrel Chat
+///
+/// A peer behind a dead yggstack or a vanished route doesn't close its TCP
+/// connection, it just stops sending, and a session waiting for the next
+/// frame would wait forever. To notice, each side pings the other every
+/// `interval`. Any frame received counts as a sign of life; a session that
+/// hears nothing for `timeout` is stale and is torn down, which reports it
+/// like any other dropped session (and redials it if it was outbound).
+///
+/// A pong echoes the id of the ping it answers, which gives the round-trip
+/// time to each peer for diagnostics.
+use crate::core::protocol::{Envelope, FrameKind, MessageId};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Time between two pings
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Silence after which a session is considered dead
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Shortest allowed interval, so a typo can't flood peers with pings
+pub const MIN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often to ping and how long to wait for a sign of life
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeartbeatConfig {
+    pub interval: Duration,
+    pub timeout: Duration,    // Should span a few intervals, so one lost pong isn't fatal
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_INTERVAL,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+/// What a session should do when its heartbeat timer fires
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tick {
+    Ping(Envelope),    // Send this ping
+    Stale(Duration),    // Nothing heard for this long; tear the session down
+}
+
+/// Liveness bookkeeping for one session
+pub struct Heartbeat {
+    config: HeartbeatConfig,
+    last_received: Instant,
+    outstanding: Option<(MessageId, Instant)>,    // Latest ping still awaiting its pong
+}
+
+impl Heartbeat {
+    pub fn new(config: HeartbeatConfig, now: Instant) -> Self {
+        Self {
+            config,
+            last_received: now,
+            outstanding: None,
+        }
+    }
+
+    /// Records a received frame, returning the round-trip time if it answers our ping
+    pub fn received(&mut self, envelope: &Envelope, now: Instant) -> Option<Duration> {
+        self.last_received = now;
+
+        let FrameKind::Pong { ping_id } = &envelope.kind else {
+            return None;
+        };
+        match self.outstanding.take() {
+            Some((id, sent)) if id == *ping_id => Some(now.duration_since(sent)),
+            other => {
+                self.outstanding = other;    // Answer to an older ping; keep waiting
+                None
+            }
+        }
+    }
+
+    /// Decides whether to ping or give up on the peer
+    pub fn tick(&mut self, now: Instant) -> Tick {
+        let silent_for = now.duration_since(self.last_received);
+        if silent_for >= self.config.timeout {
+            return Tick::Stale(silent_for);
+        }
+
+        let ping = Envelope::ping();
+        self.outstanding = Some((ping.id.clone(), now));
+        Tick::Ping(ping)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pong_gives_round_trip_time() {
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(HeartbeatConfig::default(), start);
+
+        let Tick::Ping(ping) = heartbeat.tick(start) else { panic!("expected a ping") };
+
+        // A pong for some other ping doesn't count
+        assert_eq!(heartbeat.received(&Envelope::pong(MessageId::new()), start), None);
+
+        let rtt = heartbeat.received(&Envelope::pong(ping.id), start + Duration::from_millis(250));
+        assert_eq!(rtt, Some(Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn test_silent_peer_goes_stale() {
+        let start = Instant::now();
+        let config = HeartbeatConfig { interval: MIN_INTERVAL, timeout: Duration::from_secs(20) };
+        let mut heartbeat = Heartbeat::new(config, start);
+
+        assert!(matches!(heartbeat.tick(start + Duration::from_secs(10)), Tick::Ping(_)));
+
+        // Any frame resets the clock
+        heartbeat.received(&Envelope::text("hi"), start + Duration::from_secs(15));
+        assert!(matches!(heartbeat.tick(start + Duration::from_secs(30)), Tick::Ping(_)));
+
+        assert_eq!(heartbeat.tick(start + Duration::from_secs(35)), Tick::Stale(Duration::from_secs(20)));
+    }
+}
@@ -0,0 +1,644 @@
+/// Long-term Identity Keys for Syggrel Chat
+///
+/// Each installation owns one static X25519 keypair, generated on first start
+/// and kept in a key file in the config directory (`db_paths::get_identity_path`),
+/// optionally sealed with a passphrase. It authenticates us in the Noise
+/// handshake (see `core::noise`), so peers can tell it is really us behind an
+/// address. The identity also carries our display name and own Yggdrasil
+/// address, and can be exported to move it to another device.
+///
+/// The profile is edited on the settings page (`core::identity_settings`),
+/// which also sets, changes or removes the passphrase and exports or imports
+/// the identity. Changing the profile rewrites only the public part of the key
+/// file, so it needs no passphrase.
+///
+/// Peers' public keys are pinned on first contact: the first key a contact
+/// presents is stored with it, and any later session presenting a different
+/// key is refused.
+use crate::core::noise::NOISE_PARAMS;
+use crate::database::address::YggdrasilAddress;
+use crate::database::{self, schema};
+use argon2::Argon2;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use snow::params::DHChoice;
+use snow::resolvers::{CryptoResolver, DefaultResolver};
+use std::path::Path;
+use tracing::info;
+
+/// Version of the key file layout written by `Identity::save`
+const KEY_FILE_VERSION: u8 = 1;
+
+/// Marks an exported identity so imports can reject unrelated text early
+const EXPORT_PREFIX: &str = "syggrel-identity:";
+
+/// Bytes of the public key hash kept in a fingerprint
+pub const FINGERPRINT_LEN: usize = 20;
+
+/// Errors produced while loading, storing or transferring the identity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdentityError {
+    Io(String),
+    InvalidKeyFile(String),
+    KeyGeneration(String),
+    PassphraseRequired,
+    WrongPassphrase,
+    Encryption(String),
+    InvalidAddress(String),    // Own address isn't a usable Yggdrasil node address
+}
+
+impl std::fmt::Display for IdentityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdentityError::Io(msg) => write!(f, "Identity key file error: {}", msg),
+            IdentityError::InvalidKeyFile(msg) => write!(f, "Invalid identity key file: {}", msg),
+            IdentityError::KeyGeneration(msg) => write!(f, "Failed to generate identity key: {}", msg),
+            IdentityError::PassphraseRequired => write!(f, "The identity is protected by a passphrase"),
+            IdentityError::WrongPassphrase => write!(f, "Wrong passphrase for the identity"),
+            IdentityError::Encryption(msg) => write!(f, "Failed to protect identity key: {}", msg),
+            IdentityError::InvalidAddress(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for IdentityError {}
+
+/// On-disk and export representation of an identity
+///
+/// The public part stays readable so the fingerprint can be shown before
+/// the passphrase is entered; only the private key is sealed.
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    version: u8,
+    display_name: String,
+    yggdrasil_address: Option<String>,
+    #[serde(with = "base64_key")]
+    public_key: Vec<u8>,
+    secret: StoredSecret,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum StoredSecret {
+    Plain {
+        #[serde(with = "base64_key")]
+        private_key: Vec<u8>,
+    },
+    /// Private key sealed with ChaCha20-Poly1305 under an Argon2id-derived key
+    Encrypted {
+        #[serde(with = "base64_key")]
+        salt: Vec<u8>,
+        #[serde(with = "base64_key")]
+        nonce: Vec<u8>,
+        #[serde(with = "base64_key")]
+        ciphertext: Vec<u8>,
+    },
+}
+
+/// Who we are: our static keypair plus the profile we present to contacts
+#[derive(Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub display_name: String,
+    pub yggdrasil_address: Option<String>,    // Our own node address, announced to peers
+    private_key: Vec<u8>,
+    public_key: Vec<u8>,
+}
+
+impl Identity {
+    /// Generates a fresh keypair
+    pub fn generate(display_name: impl Into<String>) -> Result<Self, IdentityError> {
+        let params = NOISE_PARAMS
+            .parse()
+            .map_err(|e| IdentityError::KeyGeneration(format!("{:?}", e)))?;
+        let keypair = snow::Builder::new(params)
+            .generate_keypair()
+            .map_err(|e| IdentityError::KeyGeneration(e.to_string()))?;
+
+        Ok(Self {
+            display_name: display_name.into(),
+            yggdrasil_address: None,
+            private_key: keypair.private,
+            public_key: keypair.public,
+        })
+    }
+
+    /// Loads the identity stored at `path`, creating it on first start
+    ///
+    /// A newly created identity is protected with `passphrase` if one is given.
+    pub fn load_or_generate(path: &Path, passphrase: Option<&str>) -> Result<Self, IdentityError> {
+        if path.exists() {
+            return Self::load(path, passphrase);
+        }
+
+        let identity = Self::generate("")?;
+        identity.save(path, passphrase)?;
+        info!("Generated new identity {}", identity.fingerprint());
+
+        Ok(identity)
+    }
+
+    /// Loads the identity stored at `path`
+    pub fn load(path: &Path, passphrase: Option<&str>) -> Result<Self, IdentityError> {
+        Self::from_key_file(read_key_file(path)?, passphrase)
+    }
+
+    /// Whether the identity at `path` needs a passphrase to be loaded
+    pub fn requires_passphrase(path: &Path) -> Result<bool, IdentityError> {
+        Ok(matches!(read_key_file(path)?.secret, StoredSecret::Encrypted { .. }))
+    }
+
+    /// Writes the identity to `path`, readable only by the current user
+    ///
+    /// Replaces any existing file atomically, so a crash never leaves a
+    /// half-written key behind.
+    pub fn save(&self, path: &Path, passphrase: Option<&str>) -> Result<(), IdentityError> {
+        write_key_file(path, &self.to_key_file(passphrase)?)
+    }
+
+    /// Stores the display name and own address in the key file at `path`
+    ///
+    /// The private key is kept as stored, sealed or not, so no passphrase is
+    /// needed. Fails if the file holds a different identity.
+    pub fn save_profile(&self, path: &Path) -> Result<(), IdentityError> {
+        let mut file = read_key_file(path)?;
+        if file.public_key != self.public_key {
+            return Err(IdentityError::InvalidKeyFile("key file holds a different identity".to_string()));
+        }
+
+        file.display_name = self.display_name.clone();
+        file.yggdrasil_address = self.yggdrasil_address.clone();
+        write_key_file(path, &file)
+    }
+
+    /// Sets the display name and own address, validating the address
+    ///
+    /// An empty address clears it. The address must be a node address without
+    /// a port, since peers dial the listen port from our contact card.
+    pub fn set_profile(&mut self, display_name: &str, address: &str) -> Result<(), IdentityError> {
+        let address = address.trim();
+        let address = if address.is_empty() {
+            None
+        } else {
+            let parsed = YggdrasilAddress::parse(address).map_err(|e| IdentityError::InvalidAddress(e.to_string()))?;
+            if parsed.port.is_some() {
+                return Err(IdentityError::InvalidAddress(
+                    "Enter your address without a port; the listen port is set separately".to_string(),
+                ));
+            }
+            Some(parsed.ip.to_string())
+        };
+
+        self.display_name = display_name.trim().to_string();
+        self.yggdrasil_address = address;
+        Ok(())
+    }
+
+    /// Re-seals the identity at `path` under a new passphrase, or none
+    ///
+    /// `current` must unlock the stored key first, so someone at an unlocked
+    /// app can't silently take the key over.
+    pub fn change_passphrase(path: &Path, current: Option<&str>, new: Option<&str>) -> Result<Self, IdentityError> {
+        let identity = Self::load(path, current)?;
+        identity.save(path, new)?;
+        Ok(identity)
+    }
+
+    /// Replaces the identity at `path` with one produced by `export`
+    ///
+    /// The imported key is stored sealed with the same passphrase it was
+    /// exported with, or in the clear if it had none.
+    pub fn restore(path: &Path, data: &str, passphrase: Option<&str>) -> Result<Self, IdentityError> {
+        let identity = Self::import(data, passphrase)?;
+        identity.save(path, passphrase)?;
+        info!("Restored identity {}", identity.fingerprint());
+        Ok(identity)
+    }
+
+    /// Serializes the identity into a single line of text for backup or transfer
+    ///
+    /// Without a passphrase the export contains the private key in the clear.
+    pub fn export(&self, passphrase: Option<&str>) -> Result<String, IdentityError> {
+        let json = serde_json::to_vec(&self.to_key_file(passphrase)?)
+            .map_err(|e| IdentityError::Encryption(e.to_string()))?;
+        Ok(format!("{}{}", EXPORT_PREFIX, BASE64.encode(json)))
+    }
+
+    /// Restores an identity produced by `export`
+    pub fn import(data: &str, passphrase: Option<&str>) -> Result<Self, IdentityError> {
+        let encoded = data
+            .trim()
+            .strip_prefix(EXPORT_PREFIX)
+            .ok_or_else(|| IdentityError::InvalidKeyFile("not an exported identity".to_string()))?;
+        let json = BASE64
+            .decode(encoded)
+            .map_err(|e| IdentityError::InvalidKeyFile(e.to_string()))?;
+        let file: KeyFile = serde_json::from_slice(&json)
+            .map_err(|e| IdentityError::InvalidKeyFile(e.to_string()))?;
+
+        Self::from_key_file(file, passphrase)
+    }
+
+    pub fn private_key(&self) -> &[u8] {
+        &self.private_key
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    /// Short form of our public key for out-of-band verification
+    pub fn fingerprint(&self) -> String {
+        fingerprint(&self.public_key)
+    }
+
+    fn to_key_file(&self, passphrase: Option<&str>) -> Result<KeyFile, IdentityError> {
+        let secret = match passphrase {
+            Some(passphrase) => seal(&self.private_key, passphrase)?,
+            None => StoredSecret::Plain { private_key: self.private_key.clone() },
+        };
+
+        Ok(KeyFile {
+            version: KEY_FILE_VERSION,
+            display_name: self.display_name.clone(),
+            yggdrasil_address: self.yggdrasil_address.clone(),
+            public_key: self.public_key.clone(),
+            secret,
+        })
+    }
+
+    fn from_key_file(file: KeyFile, passphrase: Option<&str>) -> Result<Self, IdentityError> {
+        if file.version != KEY_FILE_VERSION {
+            return Err(IdentityError::InvalidKeyFile(format!("unsupported version {}", file.version)));
+        }
+
+        let private_key = match file.secret {
+            StoredSecret::Plain { private_key } => private_key,
+            StoredSecret::Encrypted { salt, nonce, ciphertext } => {
+                let passphrase = passphrase.ok_or(IdentityError::PassphraseRequired)?;
+                open(&salt, &nonce, &ciphertext, passphrase)?
+            }
+        };
+
+        // A key file whose halves don't belong together would fail every handshake
+        if private_key.len() != 32 || derive_public_key(&private_key) != file.public_key {
+            return Err(IdentityError::InvalidKeyFile("public key does not match private key".to_string()));
+        }
+
+        Ok(Self {
+            display_name: file.display_name,
+            yggdrasil_address: file.yggdrasil_address,
+            private_key,
+            public_key: file.public_key,
+        })
+    }
+}
+
+/// Never print the private key, even in debug logs
+impl std::fmt::Debug for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Identity")
+            .field("display_name", &self.display_name)
+            .field("yggdrasil_address", &self.yggdrasil_address)
+            .field("fingerprint", &self.fingerprint())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Human-comparable fingerprint of a public key
+///
+/// The first 20 bytes of its SHA-256 hash as ten groups of four hex digits,
+/// e.g. `3f2a 91c0 ...`. Both sides read theirs aloud (or compare a QR code)
+/// to confirm the pinned key is the right one.
+pub fn fingerprint(public_key: &[u8]) -> String {
+    format_fingerprint(&fingerprint_bytes(public_key))
+}
+
+/// Raw fingerprint of a public key, as carried on contact cards
+pub fn fingerprint_bytes(public_key: &[u8]) -> [u8; FINGERPRINT_LEN] {
+    let mut bytes = [0u8; FINGERPRINT_LEN];
+    bytes.copy_from_slice(&Sha256::digest(public_key)[..FINGERPRINT_LEN]);
+    bytes
+}
+
+/// Formats raw fingerprint bytes the way `fingerprint` shows them
+pub fn format_fingerprint(bytes: &[u8; FINGERPRINT_LEN]) -> String {
+    bytes
+        .chunks(2)
+        .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn write_key_file(path: &Path, key_file: &KeyFile) -> Result<(), IdentityError> {
+    use std::io::Write;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| IdentityError::Io(e.to_string()))?;
+    }
+
+    let contents = serde_json::to_vec_pretty(key_file).map_err(|e| IdentityError::Io(e.to_string()))?;
+
+    let tmp_path = path.with_extension("tmp");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);    // The private key must not be readable by others
+    }
+
+    let mut file = options.open(&tmp_path).map_err(|e| IdentityError::Io(e.to_string()))?;
+    file.write_all(&contents).map_err(|e| IdentityError::Io(e.to_string()))?;
+    file.sync_all().map_err(|e| IdentityError::Io(e.to_string()))?;
+
+    std::fs::rename(&tmp_path, path).map_err(|e| IdentityError::Io(e.to_string()))
+}
+
+fn read_key_file(path: &Path) -> Result<KeyFile, IdentityError> {
+    let contents = std::fs::read_to_string(path).map_err(|e| IdentityError::Io(e.to_string()))?;
+    serde_json::from_str(&contents).map_err(|e| IdentityError::InvalidKeyFile(e.to_string()))
+}
+
+fn derive_public_key(private_key: &[u8]) -> Vec<u8> {
+    let mut dh = DefaultResolver
+        .resolve_dh(&DHChoice::Curve25519)
+        .expect("default resolver supports Curve25519");
+    dh.set(private_key);
+    dh.pubkey().to_vec()
+}
+
+fn derive_cipher(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305, IdentityError> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| IdentityError::Encryption(e.to_string()))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+fn seal(private_key: &[u8], passphrase: &str) -> Result<StoredSecret, IdentityError> {
+    let mut salt = vec![0u8; 16];
+    let mut nonce = vec![0u8; 12];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let ciphertext = derive_cipher(passphrase, &salt)?
+        .encrypt(Nonce::from_slice(&nonce), private_key)
+        .map_err(|e| IdentityError::Encryption(e.to_string()))?;
+
+    Ok(StoredSecret::Encrypted { salt, nonce, ciphertext })
+}
+
+fn open(salt: &[u8], nonce: &[u8], ciphertext: &[u8], passphrase: &str) -> Result<Vec<u8>, IdentityError> {
+    if nonce.len() != 12 {
+        return Err(IdentityError::InvalidKeyFile("bad nonce length".to_string()));
+    }
+
+    derive_cipher(passphrase, salt)?
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| IdentityError::WrongPassphrase)
+}
+
+/// Outcome of comparing a presented key with a contact's pinned key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PinCheck {
+    Matches,
+    FirstContact,    // Nothing pinned yet; the presented key should be pinned
+    Mismatch,
+}
+
+/// Compares the key a peer presented in the handshake with the pinned one
+pub fn check_pin(pinned: Option<&str>, presented: &[u8]) -> PinCheck {
+    match pinned {
+        None => PinCheck::FirstContact,
+        Some(pinned) if BASE64.decode(pinned).ok().as_deref() == Some(presented) => PinCheck::Matches,
+        Some(_) => PinCheck::Mismatch,
+    }
+}
+
+/// Whether a key matches the fingerprint from the contact's card, if they had one
+pub fn matches_card(card_fingerprint: Option<&str>, presented: &[u8]) -> bool {
+    card_fingerprint.is_none_or(|expected| fingerprint(presented) == expected)
+}
+
+/// Fingerprint of a pinned key as stored on a contact
+pub fn pinned_fingerprint(pinned: &str) -> Option<String> {
+    BASE64.decode(pinned).ok().map(|key| fingerprint(&key))
+}
+
+/// Checks a peer's handshake key against the contact, pinning it on first contact
+///
+/// Returns an error if the contact is unknown, pinned to a different key, or
+/// was added from a card whose fingerprint the first key doesn't match, in
+/// which case the session must be dropped.
+pub async fn verify_peer_key(contact_id: i32, presented: &[u8]) -> Result<(), String> {
+    let db = database::get_db().ok_or_else(|| "Database not initialized".to_string())?;
+
+    let contact = schema::Entity::find_by_id(contact_id)
+        .one(&*db)
+        .await
+        .map_err(|e| format!("Contact lookup failed: {}", e))?
+        .ok_or_else(|| format!("Contact {} not found", contact_id))?;
+
+    match check_pin(contact.public_key.as_deref(), presented) {
+        PinCheck::Matches => Ok(()),
+        PinCheck::Mismatch => Err(format!(
+            "{} presented key {} which does not match the pinned key",
+            contact.yggdrasil_address,
+            BASE64.encode(presented)
+        )),
+        PinCheck::FirstContact if !matches_card(contact.card_fingerprint.as_deref(), presented) => Err(format!(
+            "{} presented key {} which does not match the fingerprint on their contact card",
+            contact.yggdrasil_address,
+            BASE64.encode(presented)
+        )),
+        PinCheck::FirstContact => {
+            let key = BASE64.encode(presented);
+
+            // Only pin if nobody else pinned a key in the meantime
+            let result = schema::Entity::update_many()
+                .col_expr(schema::Column::PublicKey, Expr::value(Some(key.clone())))
+                .filter(schema::Column::Id.eq(contact_id))
+                .filter(schema::Column::PublicKey.is_null())
+                .exec(&*db)
+                .await
+                .map_err(|e| format!("Failed to pin key: {}", e))?;
+
+            if result.rows_affected == 0 {
+                return Err(format!("Key for {} changed while pinning", contact.yggdrasil_address));
+            }
+
+            info!("Pinned key {} for {}", key, contact.yggdrasil_address);
+            Ok(())
+        }
+    }
+}
+
+mod base64_key {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(key: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&BASE64.encode(key))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        BASE64.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_is_created_once_and_reloaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.key");
+
+        let created = Identity::load_or_generate(&path, None).unwrap();
+        let loaded = Identity::load_or_generate(&path, None).unwrap();
+
+        assert_eq!(created, loaded);
+        assert_eq!(created.public_key().len(), 32);
+        assert!(!Identity::requires_passphrase(&path).unwrap());
+    }
+
+    #[test]
+    fn test_corrupt_key_file_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.key");
+        std::fs::write(&path, "not a key").unwrap();
+
+        assert!(matches!(Identity::load(&path, None), Err(IdentityError::InvalidKeyFile(_))));
+    }
+
+    #[test]
+    fn test_passphrase_protects_saved_identity() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.key");
+        let mut identity = Identity::generate("Alice").unwrap();
+        identity.yggdrasil_address = Some("200:1234::1".to_string());
+        identity.save(&path, Some("correct horse")).unwrap();
+
+        assert!(Identity::requires_passphrase(&path).unwrap());
+        assert_eq!(Identity::load(&path, None), Err(IdentityError::PassphraseRequired));
+        assert_eq!(Identity::load(&path, Some("wrong")), Err(IdentityError::WrongPassphrase));
+        assert_eq!(Identity::load(&path, Some("correct horse")).unwrap(), identity);
+    }
+
+    #[test]
+    fn test_profile_is_saved_without_unsealing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.key");
+        let mut identity = Identity::generate("").unwrap();
+        identity.save(&path, Some("secret")).unwrap();
+
+        identity.set_profile("  Alice ", "0200:1234::0001").unwrap();
+        identity.save_profile(&path).unwrap();
+
+        assert!(Identity::requires_passphrase(&path).unwrap());
+        let loaded = Identity::load(&path, Some("secret")).unwrap();
+        assert_eq!(loaded.display_name, "Alice");
+        assert_eq!(loaded.yggdrasil_address.as_deref(), Some("200:1234::1"));
+
+        // Someone else's profile can't overwrite the file
+        assert!(matches!(Identity::generate("Eve").unwrap().save_profile(&path), Err(IdentityError::InvalidKeyFile(_))));
+    }
+
+    #[test]
+    fn test_profile_address_is_validated() {
+        let mut identity = Identity::generate("Alice").unwrap();
+
+        assert!(matches!(identity.set_profile("Alice", "2001:db8::1"), Err(IdentityError::InvalidAddress(_))));
+        assert!(matches!(identity.set_profile("Alice", "[200::1]:7331"), Err(IdentityError::InvalidAddress(_))));
+        assert_eq!(identity.yggdrasil_address, None);
+
+        identity.set_profile("Alice", "200::1").unwrap();
+        identity.set_profile("Alice", " ").unwrap();
+        assert_eq!(identity.yggdrasil_address, None);
+    }
+
+    #[test]
+    fn test_passphrase_can_be_set_changed_and_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.key");
+        let identity = Identity::load_or_generate(&path, None).unwrap();
+
+        assert_eq!(Identity::change_passphrase(&path, None, Some("one")).unwrap(), identity);
+        assert!(Identity::requires_passphrase(&path).unwrap());
+
+        assert_eq!(Identity::change_passphrase(&path, Some("wrong"), Some("two")), Err(IdentityError::WrongPassphrase));
+        Identity::change_passphrase(&path, Some("one"), Some("two")).unwrap();
+        assert_eq!(Identity::load(&path, Some("two")).unwrap(), identity);
+
+        Identity::change_passphrase(&path, Some("two"), None).unwrap();
+        assert!(!Identity::requires_passphrase(&path).unwrap());
+    }
+
+    #[test]
+    fn test_restore_replaces_stored_identity() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.key");
+        Identity::load_or_generate(&path, None).unwrap();
+        let other = Identity::generate("Bob").unwrap();
+        let exported = other.export(Some("secret")).unwrap();
+
+        assert!(Identity::restore(&path, &exported, Some("guess")).is_err());
+        assert_ne!(Identity::load(&path, None).unwrap(), other);
+
+        assert_eq!(Identity::restore(&path, &exported, Some("secret")).unwrap(), other);
+        assert_eq!(Identity::load(&path, Some("secret")).unwrap(), other);
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let identity = Identity::generate("Bob").unwrap();
+
+        let plain = identity.export(None).unwrap();
+        assert_eq!(Identity::import(&plain, None).unwrap(), identity);
+
+        let sealed = identity.export(Some("secret")).unwrap();
+        assert_eq!(Identity::import(&sealed, Some("secret")).unwrap(), identity);
+        assert_eq!(Identity::import(&sealed, Some("guess")), Err(IdentityError::WrongPassphrase));
+        assert!(Identity::import("hello", None).is_err());
+    }
+
+    #[test]
+    fn test_fingerprint_is_stable_and_grouped() {
+        let key = [1u8; 32];
+
+        assert_eq!(fingerprint(&key), fingerprint(&key));
+        assert_ne!(fingerprint(&key), fingerprint(&[2u8; 32]));
+        assert_eq!(fingerprint(&key).split(' ').count(), 10);
+    }
+
+    #[test]
+    fn test_pin_check_distinguishes_first_contact_match_and_mismatch() {
+        let key = [7u8; 32];
+        let pinned = BASE64.encode(key);
+
+        assert_eq!(check_pin(None, &key), PinCheck::FirstContact);
+        assert_eq!(check_pin(Some(&pinned), &key), PinCheck::Matches);
+        assert_eq!(check_pin(Some(&pinned), &[8u8; 32]), PinCheck::Mismatch);
+        assert_eq!(pinned_fingerprint(&pinned), Some(fingerprint(&key)));
+    }
+
+    #[test]
+    fn test_first_key_must_match_card_fingerprint() {
+        let key = [7u8; 32];
+        let card = fingerprint(&key);
+
+        assert!(matches_card(None, &key));
+        assert!(matches_card(Some(&card), &key));
+        assert!(!matches_card(Some(&card), &[8u8; 32]));
+    }
+}
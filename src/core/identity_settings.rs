@@ -0,0 +1,448 @@
+use crate::core::identity::{Identity, IdentityError};
+use crate::core::messenger::YggdrasilMessenger;
+use crate::database::db_paths;
+use dioxus::prelude::*;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Identity Settings
+///
+/// The part of the settings page about who we are. The profile (display name
+/// and own Yggdrasil address) is stored in the key file and pushed into the
+/// running messenger, so new sessions announce the address and our contact
+/// card can be exported. The key file can be sealed with a passphrase, which
+/// can be changed or removed again, and the identity can be exported for
+/// backup or imported from another device.
+///
+/// Importing replaces the keys, so the messenger is stopped and the app's
+/// `Signal<Option<Identity>>` context is set to the imported identity, which
+/// restarts messaging with it.
+#[component]
+pub fn IdentitySettings() -> Element {
+    let messenger = try_use_context::<Arc<YggdrasilMessenger>>();
+    let running_identity = try_use_context::<Signal<Option<Identity>>>();
+
+    let current = messenger.as_ref().map(|m| m.identity());
+    let mut display_name = use_signal(|| current.as_ref().map(|i| i.display_name.clone()).unwrap_or_default());
+    let mut own_address = use_signal(|| current.as_ref().and_then(|i| i.yggdrasil_address.clone()).unwrap_or_default());
+    let fingerprint = current.as_ref().map(|i| i.fingerprint());
+
+    let mut sealed = use_resource(|| async { run_blocking(|path| Identity::requires_passphrase(&path)).await });
+    let mut current_passphrase = use_signal(String::new);
+    let mut new_passphrase = use_signal(String::new);
+    let mut confirm_passphrase = use_signal(String::new);
+
+    let mut export_passphrase = use_signal(String::new);
+    let mut exported = use_signal(|| None::<String>);
+    let mut import_data = use_signal(String::new);
+    let mut import_passphrase = use_signal(String::new);
+    let mut confirm_import = use_signal(|| false);
+
+    let mut profile_error = use_signal(|| None::<String>);
+    let mut status = use_signal(|| None::<String>);
+    let mut busy = use_signal(|| false);
+
+    let profile_messenger = messenger.clone();
+    let on_save_profile = move |evt: FormEvent| {
+        evt.prevent_default();
+        let Some(messenger) = profile_messenger.clone() else {
+            return;
+        };
+        if *busy.peek() {
+            return;
+        }
+        busy.set(true);
+        status.set(None);
+
+        let (name, address) = (display_name.read().clone(), own_address.read().clone());
+        spawn(async move {
+            let saved = match db_paths::get_identity_path() {
+                Ok(path) => save_profile(&messenger, path, &name, &address).await,
+                Err(e) => Err(IdentityError::Io(e.to_string())),
+            };
+            match saved {
+                Ok(identity) => {
+                    own_address.set(identity.yggdrasil_address.unwrap_or_default());
+                    profile_error.set(None);
+                    status.set(Some("Profile saved".to_string()));
+                }
+                Err(e) => profile_error.set(Some(e.to_string())),
+            }
+            busy.set(false);
+        });
+    };
+
+    let on_change_passphrase = move |evt: FormEvent| {
+        evt.prevent_default();
+        if *busy.peek() {
+            return;
+        }
+        let new = match check_new_passphrase(&new_passphrase.read(), &confirm_passphrase.read()) {
+            Ok(new) => new,
+            Err(e) => {
+                status.set(Some(e));
+                return;
+            }
+        };
+        let current = non_empty(&current_passphrase.read());
+        busy.set(true);
+        status.set(None);
+
+        spawn(async move {
+            let removed = new.is_none();
+            match run_blocking(move |path| Identity::change_passphrase(&path, current.as_deref(), new.as_deref())).await {
+                Ok(_) => {
+                    current_passphrase.set(String::new());
+                    new_passphrase.set(String::new());
+                    confirm_passphrase.set(String::new());
+                    sealed.restart();
+                    status.set(Some(
+                        if removed { "Passphrase removed" } else { "Passphrase saved" }.to_string(),
+                    ));
+                }
+                Err(e) => status.set(Some(e.to_string())),
+            }
+            busy.set(false);
+        });
+    };
+
+    let export_messenger = messenger.clone();
+    let on_export = move |evt: FormEvent| {
+        evt.prevent_default();
+        let Some(messenger) = export_messenger.as_deref() else {
+            return;
+        };
+        let passphrase = non_empty(&export_passphrase.read());
+        match messenger.identity().export(passphrase.as_deref()) {
+            Ok(text) => {
+                exported.set(Some(text));
+                status.set(None);
+            }
+            Err(e) => status.set(Some(e.to_string())),
+        }
+    };
+
+    let import_messenger = messenger.clone();
+    let on_import = move |_| {
+        if *busy.peek() {
+            return;
+        }
+        busy.set(true);
+        status.set(None);
+
+        let data = import_data.read().clone();
+        let passphrase = non_empty(&import_passphrase.read());
+        let messenger = import_messenger.clone();
+        spawn(async move {
+            match run_blocking(move |path| Identity::restore(&path, &data, passphrase.as_deref())).await {
+                Ok(identity) => {
+                    if let Some(messenger) = messenger
+                        && let Err(e) = messenger.disconnect().await
+                    {
+                        tracing::warn!("Failed to stop messaging before switching identity: {}", e);
+                    }
+                    import_data.set(String::new());
+                    import_passphrase.set(String::new());
+                    confirm_import.set(false);
+                    match running_identity {
+                        Some(mut running) => running.set(Some(identity)),
+                        None => status.set(Some("Identity imported; restart to use it".to_string())),
+                    }
+                }
+                Err(e) => {
+                    confirm_import.set(false);
+                    status.set(Some(e.to_string()));
+                }
+            }
+            busy.set(false);
+        });
+    };
+
+    let is_sealed = matches!(&*sealed.read(), Some(Ok(true)));
+
+    rsx! {
+        form {
+            class: "settings-form identity-form",
+            onsubmit: on_save_profile,
+
+            fieldset {
+                legend { "Profile" }
+
+                if let Some(fingerprint) = fingerprint.as_ref() {
+                    p { class: "card-fingerprint", "Your key fingerprint: " code { "{fingerprint}" } }
+                }
+
+                div {
+                    class: "form-field",
+                    label { r#for: "own-display-name", "Display name" }
+                    input {
+                        id: "own-display-name",
+                        r#type: "text",
+                        value: "{display_name}",
+                        oninput: move |e| display_name.set(e.value()),
+                    }
+                }
+
+                div {
+                    class: "form-field",
+                    label { r#for: "own-address", "Your Yggdrasil address" }
+                    input {
+                        id: "own-address",
+                        r#type: "text",
+                        placeholder: "200:1234:5678::1",
+                        value: "{own_address}",
+                        aria_invalid: profile_error.read().is_some(),
+                        oninput: move |e| own_address.set(e.value()),
+                    }
+                    if let Some(message) = profile_error.read().as_ref() {
+                        p { class: "field-error", role: "alert", "{message}" }
+                    }
+                }
+
+                button {
+                    class: "primary-button",
+                    r#type: "submit",
+                    disabled: busy() || messenger.is_none(),
+                    "Save Profile"
+                }
+            }
+        }
+
+        form {
+            class: "settings-form identity-form",
+            onsubmit: on_change_passphrase,
+
+            fieldset {
+                legend { "Passphrase" }
+
+                p {
+                    if is_sealed {
+                        "Your key is sealed with a passphrase, which is asked for at every start."
+                    } else {
+                        "Your key is stored without a passphrase."
+                    }
+                }
+
+                if is_sealed {
+                    div {
+                        class: "form-field",
+                        label { r#for: "current-passphrase", "Current passphrase" }
+                        input {
+                            id: "current-passphrase",
+                            r#type: "password",
+                            value: "{current_passphrase}",
+                            oninput: move |e| current_passphrase.set(e.value()),
+                        }
+                    }
+                }
+
+                div {
+                    class: "form-field",
+                    label { r#for: "new-passphrase", "New passphrase (leave empty to remove)" }
+                    input {
+                        id: "new-passphrase",
+                        r#type: "password",
+                        value: "{new_passphrase}",
+                        oninput: move |e| new_passphrase.set(e.value()),
+                    }
+                }
+
+                div {
+                    class: "form-field",
+                    label { r#for: "confirm-passphrase", "Repeat new passphrase" }
+                    input {
+                        id: "confirm-passphrase",
+                        r#type: "password",
+                        value: "{confirm_passphrase}",
+                        oninput: move |e| confirm_passphrase.set(e.value()),
+                    }
+                }
+
+                button {
+                    r#type: "submit",
+                    disabled: busy() || (!is_sealed && new_passphrase.read().is_empty()),
+                    if is_sealed && new_passphrase.read().is_empty() { "Remove Passphrase" } else { "Save Passphrase" }
+                }
+            }
+        }
+
+        form {
+            class: "settings-form identity-form",
+            onsubmit: on_export,
+
+            fieldset {
+                legend { "Back up or move your identity" }
+
+                div {
+                    class: "form-field",
+                    label { r#for: "export-passphrase", "Protect the export with a passphrase" }
+                    input {
+                        id: "export-passphrase",
+                        r#type: "password",
+                        value: "{export_passphrase}",
+                        oninput: move |e| export_passphrase.set(e.value()),
+                    }
+                    if export_passphrase.read().is_empty() {
+                        p { class: "field-hint", "Without a passphrase anyone with the export can act as you." }
+                    }
+                }
+
+                button {
+                    r#type: "submit",
+                    disabled: messenger.is_none(),
+                    "Export"
+                }
+
+                if let Some(text) = exported.read().as_ref() {
+                    textarea {
+                        class: "identity-export",
+                        aria_label: "Exported identity",
+                        readonly: true,
+                        rows: "4",
+                        value: "{text}",
+                    }
+                }
+
+                div {
+                    class: "form-field",
+                    label { r#for: "import-data", "Import an exported identity" }
+                    textarea {
+                        id: "import-data",
+                        rows: "4",
+                        placeholder: "syggrel-identity:...",
+                        value: "{import_data}",
+                        oninput: move |e| import_data.set(e.value()),
+                    }
+                }
+
+                div {
+                    class: "form-field",
+                    label { r#for: "import-passphrase", "Passphrase of the export" }
+                    input {
+                        id: "import-passphrase",
+                        r#type: "password",
+                        value: "{import_passphrase}",
+                        oninput: move |e| import_passphrase.set(e.value()),
+                    }
+                }
+
+                button {
+                    class: "danger-button",
+                    r#type: "button",
+                    disabled: busy() || import_data.read().trim().is_empty(),
+                    onclick: move |_| confirm_import.set(true),
+                    "Import"
+                }
+            }
+        }
+
+        if let Some(message) = status.read().as_ref() {
+            p { class: "form-status", role: "status", "{message}" }
+        }
+
+        if confirm_import() {
+            div {
+                class: "confirm-dialog",
+                role: "alertdialog",
+                aria_modal: "true",
+                aria_labelledby: "confirm-import-title",
+
+                h2 { id: "confirm-import-title", "Replace your identity?" }
+                p { "Your current key is overwritten and messaging restarts as the imported identity. Contacts who pinned your current key will refuse the new one. Export your current identity first if you may need it again." }
+                div {
+                    class: "form-actions",
+                    button {
+                        class: "danger-button",
+                        r#type: "button",
+                        disabled: busy(),
+                        onclick: on_import,
+                        "Replace"
+                    }
+                    button {
+                        r#type: "button",
+                        onclick: move |_| confirm_import.set(false),
+                        "Cancel"
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Validates and stores a new profile, then hands it to the running messenger
+///
+/// Returns the identity as saved, with the address in canonical form.
+async fn save_profile(
+    messenger: &YggdrasilMessenger,
+    path: PathBuf,
+    display_name: &str,
+    address: &str,
+) -> Result<Identity, IdentityError> {
+    let mut identity = messenger.identity();
+    identity.set_profile(display_name, address)?;
+
+    let saved = identity.clone();
+    tokio::task::spawn_blocking(move || saved.save_profile(&path))
+        .await
+        .map_err(|e| IdentityError::Io(e.to_string()))??;
+
+    messenger.set_display_name(identity.display_name.clone());
+    messenger.set_local_address(identity.yggdrasil_address.clone());
+    Ok(identity)
+}
+
+/// The new passphrase to seal with, `None` to remove it, or why it can't be used
+fn check_new_passphrase(new: &str, confirm: &str) -> Result<Option<String>, String> {
+    if new != confirm {
+        return Err("The new passphrases don't match".to_string());
+    }
+    Ok(non_empty(new))
+}
+
+fn non_empty(passphrase: &str) -> Option<String> {
+    (!passphrase.is_empty()).then(|| passphrase.to_string())
+}
+
+/// Runs a key file operation off the UI thread; deriving passphrase keys is slow
+async fn run_blocking<T, F>(operation: F) -> Result<T, IdentityError>
+where
+    T: Send + 'static,
+    F: FnOnce(PathBuf) -> Result<T, IdentityError> + Send + 'static,
+{
+    let path = db_paths::get_identity_path().map_err(|e| IdentityError::Io(e.to_string()))?;
+    tokio::task::spawn_blocking(move || operation(path))
+        .await
+        .map_err(|e| IdentityError::Io(e.to_string()))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_saved_profile_reaches_key_file_and_messenger() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.key");
+        let identity = Identity::generate("").unwrap();
+        identity.save(&path, Some("secret")).unwrap();
+        let messenger = YggdrasilMessenger::new(identity);
+
+        let saved = save_profile(&messenger, path.clone(), " Alice ", "0200:1234::0001").await.unwrap();
+
+        assert_eq!(saved.yggdrasil_address.as_deref(), Some("200:1234::1"));
+        assert_eq!(messenger.identity(), saved);
+        assert_eq!(Identity::load(&path, Some("secret")).unwrap(), saved);
+
+        // A bad address changes nothing
+        let result = save_profile(&messenger, path.clone(), "Mallory", "[200:1234::1]:7331").await;
+        assert!(matches!(result, Err(IdentityError::InvalidAddress(_))));
+        assert_eq!(messenger.identity().display_name, "Alice");
+    }
+
+    #[test]
+    fn test_new_passphrase_must_be_repeated() {
+        assert_eq!(check_new_passphrase("a", "a"), Ok(Some("a".to_string())));
+        assert_eq!(check_new_passphrase("", ""), Ok(None));
+        assert!(check_new_passphrase("a", "b").is_err());
+    }
+}
@@ -0,0 +1,290 @@
+/// Inbound Listener for Syggrel Chat
+///
+/// Accepts TCP connections from peers so that two instances of the app can
+/// talk to each other directly. Two deployment modes are supported:
+///
+/// - TUN mode: the listener binds on the node's Yggdrasil address (or `[::]`)
+///   and the remote address of each connection is the peer's Yggdrasil address.
+/// - yggstack mode: yggstack forwards a port of the node to a local port
+///   (`-remote-tcp <port>:127.0.0.1:<port>`), so every connection appears to
+///   come from loopback. The peer is then identified by the static key it
+///   proves in the handshake: the active contact that has pinned that key, or
+///   else the one whose contact card carries its fingerprint. A contact with
+///   neither can only be reached by dialing it first.
+///
+/// Every connection first completes a Noise handshake (see `core::noise`).
+/// Connections that cannot be matched to an active contact, or whose key
+/// differs from the contact's pinned key, are dropped.
+use crate::core::connection_manager::Direction;
+use crate::core::identity;
+use crate::core::noise;
+use crate::core::outbox;
+use crate::core::session::{self, PeerInfo};
+use crate::core::supervisor::{self, SessionContext, SessionEvent};
+use crate::database::address::{self, YggdrasilAddress};
+use crate::database::{self, schema};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use tokio::io::DuplexStream;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tracing::{debug, info, warn};
+
+/// Default port peers connect to
+pub const DEFAULT_LISTEN_PORT: u16 = 7331;
+
+/// Where the inbound listener binds
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListenerConfig {
+    pub bind_addr: SocketAddr,
+}
+
+impl ListenerConfig {
+    /// Listen on all interfaces, which covers both TUN and yggstack mode
+    pub fn any(port: u16) -> Self {
+        Self {
+            bind_addr: SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port),
+        }
+    }
+}
+
+impl Default for ListenerConfig {
+    /// Listen on all IPv6 interfaces, including the Yggdrasil TUN interface
+    fn default() -> Self {
+        Self::any(DEFAULT_LISTEN_PORT)
+    }
+}
+
+/// Accepts connections until the task is aborted
+///
+/// Handshakes in progress run on tasks owned by the loop, so stopping the
+/// listener drops them as well. An established session belongs to the
+/// connection manager and outlives the listener, e.g. when its port changes.
+/// Inbound sessions are watched and reported like outbound ones, but never
+/// redialed: the peer is expected to connect again.
+pub async fn accept_loop(listener: TcpListener, ctx: SessionContext) {
+    let mut handshakes = JoinSet::new();
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                // Identify each peer on its own task so a silent peer can't stall the loop
+                Ok((stream, peer_addr)) => {
+                    handshakes.spawn(accept_connection(ctx.clone(), stream, peer_addr));
+                }
+                Err(e) => warn!("Failed to accept inbound connection: {}", e),
+            },
+            Some(_) = handshakes.join_next() => {}    // Reap finished handshakes
+        }
+    }
+}
+
+/// Authenticates an accepted connection and hands its session over
+async fn accept_connection(ctx: SessionContext, stream: TcpStream, peer_addr: SocketAddr) {
+    let (stream, contact, we_dial_first) = match accept_secure(&ctx, stream, peer_addr).await {
+        Ok(accepted) => accepted,
+        Err(reason) => {
+            debug!("Rejected inbound connection from {}: {}", peer_addr, reason);
+            return;
+        }
+    };
+
+    info!("Accepted inbound session from {} ({})", contact.display_name, peer_addr);
+    let peer = PeerInfo {
+        address: contact.yggdrasil_address,
+        contact_id: contact.id,
+    };
+    let handle = session::spawn_session(
+        stream,
+        peer.clone(),
+        ctx.buffer.clone(),
+        ctx.events.clone(),
+        ctx.presence.clone(),
+        *ctx.heartbeat.read().unwrap(),
+    );
+    let _ = handle.tx.send(supervisor::hello(&ctx));
+    let Some(task) = ctx.connections.install(
+        &peer.address,
+        peer.contact_id,
+        handle,
+        Direction::Inbound,
+        we_dial_first,
+    ) else {
+        debug!("{} dialed us while we dialed it, keeping our session", peer.address);
+        return;
+    };
+    ctx.emit(SessionEvent::Connected { address: peer.address.clone() });
+    ctx.presence.record_activity(&peer);
+
+    // Watched for as long as the session runs, which ends with its task
+    tokio::spawn(async move {
+        outbox::flush_pending(&ctx.connections, &peer).await;
+        tokio::spawn(outbox::resend_unacked(ctx.connections.clone(), peer.clone(), task.abort_handle()));
+        supervisor::watch_session(&ctx, &peer.address, task).await;
+    });
+}
+
+/// Runs the handshake on an accepted connection and authenticates the contact
+///
+/// Also returns whether our static key is the lower one, which decides
+/// between crossed dials.
+async fn accept_secure(
+    ctx: &SessionContext,
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+) -> Result<(DuplexStream, schema::Model, bool), String> {
+    let identity = ctx.identity.read().unwrap().clone();
+    let channel = noise::respond(stream, &identity).await.map_err(|e| e.to_string())?;
+    let remote_key = channel.remote_public_key().to_vec();
+
+    let contact = identify_peer(peer_addr, &remote_key).await?;
+    identity::verify_peer_key(contact.id, &remote_key).await?;
+
+    let we_dial_first = identity.public_key() < remote_key.as_slice();
+    Ok((channel.into_stream(), contact, we_dial_first))
+}
+
+/// Matches an accepted connection to a known contact
+async fn identify_peer(peer_addr: SocketAddr, remote_key: &[u8]) -> Result<schema::Model, String> {
+    let db = database::get_db().ok_or_else(|| "Database not initialized".to_string())?;
+
+    let contacts = schema::Entity::find()
+        .filter(schema::Column::IsActive.eq(true))
+        .all(&*db)
+        .await
+        .map_err(|e| format!("Contact lookup failed: {}", e))?;
+
+    // Direct connection over the Yggdrasil TUN interface: trust the source address
+    if is_yggdrasil_ip(&peer_addr.ip()) {
+        let ip = peer_addr.ip();
+        return contacts
+            .into_iter()
+            .find(|c| address_ip(&c.yggdrasil_address) == Some(ip))
+            .ok_or_else(|| format!("{} is not a known contact", ip));
+    }
+
+    // Forwarded connection: all we know about the peer is the key it proved
+    match_key(contacts, remote_key)
+}
+
+/// Picks the contact a forwarded peer's handshake key belongs to
+///
+/// A pinned key decides. Otherwise the fingerprint from a contact card may
+/// stand in, but only if a single unpinned contact carries it.
+fn match_key(contacts: Vec<schema::Model>, remote_key: &[u8]) -> Result<schema::Model, String> {
+    let key = BASE64.encode(remote_key);
+    let (pinned, unpinned): (Vec<_>, Vec<_>) = contacts.into_iter().partition(|c| c.public_key.is_some());
+
+    if let Some(contact) = pinned.into_iter().find(|c| c.public_key.as_deref() == Some(key.as_str())) {
+        return Ok(contact);
+    }
+
+    let mut by_card = unpinned
+        .into_iter()
+        .filter(|c| c.card_fingerprint.is_some() && identity::matches_card(c.card_fingerprint.as_deref(), remote_key));
+    match (by_card.next(), by_card.next()) {
+        (Some(contact), None) => Ok(contact),
+        (Some(_), Some(_)) => Err(format!("key {} matches the card of more than one contact", key)),
+        (None, _) => Err(format!("forwarded peer with key {} is not a known contact", key)),
+    }
+}
+
+/// Extracts the IP from a stored address, which may be `addr` or `[addr]:port`
+fn address_ip(address: &str) -> Option<IpAddr> {
+    YggdrasilAddress::parse(address).ok().map(|addr| IpAddr::V6(addr.ip))
+}
+
+/// Whether `ip` lies in the Yggdrasil range 200::/7
+fn is_yggdrasil_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V6(v6) => address::is_yggdrasil_ip(v6),
+        IpAddr::V4(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::identity::Identity;
+    use crate::core::testing;
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+
+    fn contact(id: i32, public_key: Option<&[u8]>, card_key: Option<&[u8]>) -> schema::Model {
+        schema::Model {
+            id,
+            yggdrasil_address: format!("200::{}", id),
+            public_key: public_key.map(|k| BASE64.encode(k)),
+            socks5_proxy: String::new(),
+            display_name: format!("Contact {}", id),
+            is_active: true,
+            last_seen: None,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+            is_hidden_peer: false,
+            notes: None,
+            card_fingerprint: card_key.map(identity::fingerprint),
+        }
+    }
+
+    #[test]
+    fn test_forwarded_peer_is_matched_by_pinned_key_then_card() {
+        let contacts = vec![contact(1, Some(b"alice"), None), contact(2, None, Some(b"bob"))];
+
+        assert_eq!(match_key(contacts.clone(), b"alice").unwrap().id, 1);
+        assert_eq!(match_key(contacts.clone(), b"bob").unwrap().id, 2);
+        assert!(match_key(contacts, b"mallory").is_err());
+    }
+
+    #[test]
+    fn test_card_match_must_be_unpinned_and_unambiguous() {
+        // Once pinned, only the pinned key counts
+        let pinned = vec![contact(1, Some(b"alice"), Some(b"bob"))];
+        assert!(match_key(pinned, b"bob").is_err());
+
+        let twice = vec![contact(1, None, Some(b"bob")), contact(2, None, Some(b"bob"))];
+        assert!(match_key(twice, b"bob").is_err());
+
+        // A contact without a card fingerprint is not matched by any key
+        assert!(match_key(vec![contact(1, None, None)], b"bob").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_connection_is_identified_by_key() {
+        testing::init_db().await;
+        let peer = Identity::generate("Carol").unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let contact = testing::save_contact("200:5::a", listener.local_addr().unwrap(), &peer).await;
+
+        // Seen from a real socket, a forwarded peer comes from loopback
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (_, peer_addr) = listener.accept().await.unwrap();
+        assert!(peer_addr.ip().is_loopback());
+
+        assert_eq!(identify_peer(peer_addr, peer.public_key()).await.unwrap().id, contact.id.unwrap());
+        let stranger = Identity::generate("Mallory").unwrap();
+        assert!(identify_peer(peer_addr, stranger.public_key()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stopping_the_loop_drops_pending_handshakes() {
+        testing::init_db().await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ctx = SessionContext::new(Identity::generate("Dave").unwrap());
+        let accepting = tokio::spawn(accept_loop(listener, ctx));
+
+        // A peer that connects and never starts the handshake
+        let mut silent = TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        accepting.abort();
+
+        let mut buf = [0u8; 1];
+        let read = tokio::time::timeout(Duration::from_secs(5), silent.read(&mut buf))
+            .await
+            .expect("connection outlived the listener");
+        assert!(matches!(read, Ok(0) | Err(_)));
+    }
+}
@@ -1,139 +1,392 @@
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::net::TcpStream;
-use tokio::sync::{mpsc, Mutex};
-use tokio_socks::tcp::Socks5Stream;
+use crate::core::buffer::{BufferLimits, ReceivedMessage};
+use crate::core::chat_data::ChatId;
+use crate::core::connection_manager::{ConnectionManager, PeerState};
+use crate::core::events::MessengerEvent;
+use crate::core::heartbeat::HeartbeatConfig;
+use crate::core::identity::Identity;
+use crate::core::listener::{self, ListenerConfig};
+use crate::core::outbox;
+use crate::core::presence::PresenceTracker;
+use crate::core::protocol::Envelope;
+use crate::core::receipts;
+use crate::core::retry::RetryPolicy;
+use crate::core::session::PeerInfo;
+use crate::core::supervisor::{self, SessionContext};
+use crate::database;
+use crate::database::contact_repository::ContactRepository;
+use crate::database::message_schema;
+use crate::database::models::Contact;
+use crate::database::outbox_repository::OutboxRepository;
+use crate::database::proxy_url::ProxyConfig;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
 
 pub struct YggdrasilMessenger {
-    buffer: MessageBuffer,
-    connection_handle: Option<JoinHandle<tokio::io::Result<()>>>,
-    message_tx: Option<mpsc::UnboundedSender<String>>,
+    ctx: SessionContext,
+    supervisors: Mutex<HashMap<String, JoinHandle<()>>>,
+    listener_handle: Mutex<Option<(SocketAddr, JoinHandle<()>)>>,    // Bound address and accept task
+    read_receipts: AtomicBool,
 }
 
 impl YggdrasilMessenger {
-    pub fn new() -> Self {
+    /// Creates a messenger authenticating sessions with `identity`
+    ///
+    /// The identity's own Yggdrasil address, if set, is announced to peers.
+    pub fn new(identity: Identity) -> Self {
         Self {
-            buffer: MessageBuffer::new(),
-            connection_handle: None,
-            message_tx: None,
+            ctx: SessionContext::new(identity),
+            supervisors: Mutex::new(HashMap::new()),
+            listener_handle: Mutex::new(None),
+            read_receipts: AtomicBool::new(true),
         }
     }
 
-    pub async fn connect_via_socks5(
-        &mut self,
-        proxy_addr: &str,
-        target_addr: &str,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let proxy_addr = proxy_addr.parse()
-            .map_err(|e| format!("Invalid proxy address '{}': {}", proxy_addr, e))?;
-        let target_addr = target_addr.parse()
-            .map_err(|e| format!("Invalid target address '{}': {}", target_addr, e))?;
+    /// Enables or disables sending read receipts when a chat is opened
+    pub fn set_read_receipts(&self, enabled: bool) {
+        self.read_receipts.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Who we are to peers: keys, display name and own address
+    pub fn identity(&self) -> Identity {
+        self.ctx.identity.read().unwrap().clone()
+    }
+
+    /// Sets the proxy used by hidden peers that don't configure their own
+    ///
+    /// Takes effect on the next dial; established sessions are kept.
+    pub fn set_default_proxy(&self, proxy: Option<ProxyConfig>) {
+        *self.ctx.default_proxy.write().unwrap() = proxy;
+    }
+
+    /// Sets how often sessions ping their peer and how long a silent peer is kept
+    ///
+    /// Takes effect for sessions started afterwards.
+    pub fn set_heartbeat(&self, config: HeartbeatConfig) {
+        *self.ctx.heartbeat.write().unwrap() = config;
+    }
+
+    /// Sets our own Yggdrasil address, announced to peers on connect
+    ///
+    /// Peers listening behind a yggstack port forward only see loopback
+    /// connections and rely on this announcement to identify us. Takes
+    /// effect for sessions started afterwards.
+    pub fn set_local_address(&self, address: Option<String>) {
+        self.ctx.identity.write().unwrap().yggdrasil_address = address;
+    }
+
+    /// Sets the display name put on our contact card
+    pub fn set_display_name(&self, display_name: impl Into<String>) {
+        self.ctx.identity.write().unwrap().display_name = display_name.into();
+    }
+
+    /// Subscribes to messenger events: received messages, delivery and read
+    /// receipts, and session status changes
+    ///
+    /// Every subscriber sees every event published after it subscribed, so
+    /// the UI, notifications and other consumers can all listen at once.
+    pub fn subscribe(&self) -> broadcast::Receiver<MessengerEvent> {
+        self.ctx.events.subscribe()
+    }
+
+    /// Dials a contact in the background unless it is connected or being dialed
+    ///
+    /// Hidden peers go through their own SOCKS5 proxy or the default one set
+    /// with `set_default_proxy`; other peers are dialed directly over TUN.
+    /// The stream is end-to-end encrypted with a Noise handshake; a peer whose
+    /// key differs from the one pinned on the contact is refused.
+    ///
+    /// A failed dial or a dropped session is published as a `SessionEvent`
+    /// and redialed with backoff. Redialing stops once the contact dials us
+    /// instead. Unsaved and inactive contacts are skipped.
+    pub fn ensure_connected(&self, contact: &Contact) {
+        if contact.id.is_none() || !contact.is_active {
+            return;
+        }
+        let address = &contact.yggdrasil_address;
+        if self.ctx.connections.state(address) != PeerState::Disconnected {
+            return;
+        }
+
+        let mut supervisors = self.supervisors.lock().unwrap();
+        if supervisors.get(address).is_some_and(|s| !s.is_finished()) {
+            return;    // Still backing off between redials
+        }
+
+        let handle = tokio::spawn(supervisor::dial_and_supervise(
+            self.ctx.clone(),
+            contact.clone(),
+            RetryPolicy::reconnect(),
+        ));
+        supervisors.insert(address.clone(), handle);
+    }
+
+    /// Dials every active contact in the background, e.g. at startup
+    ///
+    /// Returns how many contacts were considered.
+    pub async fn connect_all(&self) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let db = database::get_db().ok_or("Database not initialized")?;
+        let contacts = ContactRepository::new(db).list("", false).await?;
 
-        let stream = Socks5Stream::connect(proxy_addr, target_addr)
+        for contact in &contacts {
+            self.ensure_connected(contact);
+        }
+
+        Ok(contacts.len())
+    }
+
+    /// Dials every contact that has messages waiting in the outbox
+    ///
+    /// Picks up contacts whose redialing gave up, and those whose session
+    /// the peer opened and then dropped, which we don't redial by ourselves.
+    pub async fn redial_pending(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let db = database::get_db().ok_or("Database not initialized")?;
+        let contact_ids = OutboxRepository::new(db.clone()).contacts_with_pending().await?;
+        let contacts = ContactRepository::new(db);
+
+        for contact_id in contact_ids {
+            if let Some(contact) = contacts.find(contact_id).await? {
+                self.ensure_connected(&contact);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts accepting inbound connections from known contacts
+    ///
+    /// Returns the address actually bound, which differs from the configured
+    /// one when port 0 is requested.
+    pub async fn start_listener(
+        &self,
+        config: ListenerConfig,
+    ) -> Result<SocketAddr, Box<dyn std::error::Error + Send + Sync>> {
+        let tcp_listener = TcpListener::bind(config.bind_addr)
             .await
-            .map_err(|e| format!("SOCKS5 connection failed: {}", e))?;
-
-        // Create channel for receiving messages from the connection
-        let (tx, mut rx) = mpsc::unbounded_channel::<String>();
-        self.message_tx = Some(tx);
-
-        let buffer = self.buffer.buffer.clone();
-
-        // Clone the buffer for the background task
-        let handle = tokio::spawn(async move {
-            // Split stream for concurrent read/write if needed
-            let (mut reader, mut writer) = tokio::io::split(stream);
-
-            // Task for receiving messages
-            let recv_task = tokio::spawn(async move {
-                let mut buf_reader = BufReader::new(reader);
-                let mut line = String::new();
-                let buffer = buffer.clone();
-
-                loop {
-                    line.clear();
-                    match buf_reader.read_line(&mut line).await {
-                        Ok(0) => break, // EOF
-                        Ok(_) => {
-                            let msg = line.trim_end_matches(['\r', '\n']).to_string(); // Clean the message
-                            if let Err(e) = {
-                                let mut buffer_guard = buffer.lock().await;
-                                buffer_guard.add_message(msg);
-                                Ok(())
-                            } {
-                                eprintln!("Buffer error: {}", e);
-                                break;
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("Read error: {}", e);
-                            break;
-                        }
-                    }
-                }
-            });
-            // Task for sending messages via network
-            let send_task = tokio::spawn(async move {
-                while let Ok(msg) = rx.recv().await {
-                    if let Err(e) = writer.write_all(msg.as_bytes()).await {
-                        eprintln!("Write error: {}", e);
-                        break;
-                    }
-                    // Add newline delimiter to separate messages
-                    if let Err(e) = writer.write_all(b"\n").await {
-                        eprintln!("Write error: {}", e);
-                        break;
-                    }
-                    // Ensure data is sent immediately
-                    if let Err(e) = writer.flush().await {
-                        eprintln!("Flush error: {}", e);
-                        break;
-                    }
-                }
-            });
+            .map_err(|e| format!("Failed to listen on {}: {}", config.bind_addr, e))?;
+        let bound_addr = tcp_listener.local_addr()?;
+
+        let handle = tokio::spawn(listener::accept_loop(tcp_listener, self.ctx.clone()));
+
+        if let Some((_, old)) = self.listener_handle.lock().unwrap().replace((bound_addr, handle)) {
+            old.abort();
+        }
+
+        Ok(bound_addr)
+    }
 
-            // Wait for either task to complete
-            tokio::select! {
-                _ = recv_task => {},
-                _ = send_task => {},
+    /// Rebinds a running listener to `port`, keeping its interface
+    ///
+    /// Does nothing if no listener is running or it already uses the port.
+    /// If the new port can't be bound, the old listener keeps running.
+    pub async fn set_listen_port(&self, port: u16) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let current = self.listener_handle.lock().unwrap().as_ref().map(|(addr, _)| *addr);
+
+        match current {
+            Some(addr) if addr.port() != port => {
+                let bound = self.start_listener(ListenerConfig { bind_addr: SocketAddr::new(addr.ip(), port) }).await?;
+                log::info!("Listener moved from {} to {}", addr, bound);
+                Ok(())
             }
+            _ => Ok(()),
+        }
+    }
+
+    /// Changes how many received messages are kept in memory for each chat
+    ///
+    /// Chats now over the limits lose their oldest messages, which is
+    /// published as `MessengerEvent::BufferOverflow`.
+    pub async fn set_buffer_limits(&self, max_messages: usize, max_bytes: usize) {
+        let overflows = self.ctx.buffer.set_limits(BufferLimits { max_messages, max_bytes }).await;
+        for overflow in overflows {
+            self.ctx.events.publish(MessengerEvent::BufferOverflow(overflow));
+        }
+    }
+
+    /// Sends a text message to a contact through the durable outbox
+    ///
+    /// The message is stored before sending, so it is not lost when the
+    /// contact is offline: it stays `Pending`, the contact is dialed, and it
+    /// goes out once a session with the contact comes up. Returns the stored
+    /// message row.
+    pub async fn send_message(
+        &self,
+        contact: &Contact,
+        msg: String,
+    ) -> Result<message_schema::Model, Box<dyn std::error::Error + Send + Sync>> {
+        let contact_id = contact.id
+            .ok_or_else(|| format!("Contact '{}' has not been saved yet", contact.display_name))?;
+        let peer = PeerInfo {
+            address: contact.yggdrasil_address.clone(),
+            contact_id,
+        };
 
-            Ok(())
+        let message = outbox::send_durable(&self.ctx.connections, &peer, msg).await?;
+        self.ctx.presence.record_own_activity();
+        self.ensure_connected(contact);
+        self.ctx.events.publish(MessengerEvent::MessageSent {
+            peer_address: peer.address,
+            contact_id,
+            body: message.body.clone(),
+            sent_at: message.sent_at,
         });
 
-        self.connection_handle = Some(handle);
-        Ok(())
+        Ok(message)
+    }
+
+    /// Queues a contact's failed messages again and sends them if connected
+    ///
+    /// An offline contact is dialed. Returns how many messages were requeued.
+    pub async fn retry_failed(&self, contact: &Contact) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let contact_id = contact.id
+            .ok_or_else(|| format!("Contact '{}' has not been saved yet", contact.display_name))?;
+        let peer = PeerInfo {
+            address: contact.yggdrasil_address.clone(),
+            contact_id,
+        };
+
+        let requeued = outbox::retry_failed(&self.ctx.connections, &peer).await?;
+        if requeued > 0 {
+            self.ensure_connected(contact);
+        }
+
+        Ok(requeued)
     }
 
-    pub async fn send_message(&self, msg: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        // Send through the network connection
-        if let Some(ref tx) = self.message_tx {
-            tx.send(msg).map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
-            Ok(())
-        } else {
-            Err("Not connected".into())
+    /// Marks a contact's chat as read, e.g. when the user opens it
+    ///
+    /// Sends a read receipt for the newly read messages if receipts are
+    /// enabled and the contact is connected. Receipts are best-effort and
+    /// not queued for offline contacts. Returns how many messages were read.
+    pub async fn mark_chat_read(&self, contact: &Contact) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let contact_id = contact.id
+            .ok_or_else(|| format!("Contact '{}' has not been saved yet", contact.display_name))?;
+
+        let read = receipts::mark_chat_read(contact_id).await?;
+        let count = read.len();
+
+        if count > 0 {
+            self.ctx.events.publish(MessengerEvent::ChatRead {
+                peer_address: contact.yggdrasil_address.clone(),
+                contact_id,
+            });
+        }
+
+        if count > 0
+            && self.read_receipts.load(Ordering::SeqCst)
+            && let Err(e) = self.ctx.connections.send(&contact.yggdrasil_address, Envelope::read_receipt(read))
+        {
+            log::debug!("Read receipt for {} not sent: {}", contact.yggdrasil_address, e);
         }
+
+        Ok(count)
+    }
+
+    /// Takes up to `count` received messages from one contact
+    ///
+    /// Each message is handed out once, and messages from other contacts stay
+    /// buffered for their own consumers; to be told about every message as it
+    /// arrives, use `subscribe` instead.
+    pub async fn receive_messages_for(&self, contact_id: i32, count: usize) -> Vec<ReceivedMessage> {
+        self.ctx.buffer.take_messages_for(&ChatId::from(contact_id), count).await
+    }
+
+    /// Latest heartbeat round-trip time to a connected peer
+    pub fn round_trip_time(&self, peer_address: &str) -> Option<Duration> {
+        self.ctx.connections.rtt(peer_address)
+    }
+
+    /// Online, away or offline state of every peer, and the status we advertise
+    pub fn presence(&self) -> &PresenceTracker {
+        &self.ctx.presence
     }
 
-    pub async fn receive_messages(&self, count: usize) -> Vec<String> {
-        self.buffer.take_messages(count).await 
+    /// Shared handle to the per-peer session registry
+    pub fn connections(&self) -> &ConnectionManager {
+        &self.ctx.connections
     }
 
-    pub fn queue_network_message(&self, msg: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        if let Some(ref tx) = self.message_tx {
-            tx.send(msg).map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
-        } else {
-            Err("Not connected".into())
+    /// Disconnects a single peer and stops redialing it
+    pub async fn disconnect_peer(&self, peer_address: &str) {
+        if let Some(supervisor) = self.supervisors.lock().unwrap().remove(peer_address) {
+            supervisor.abort();
+        }
+        if self.ctx.connections.disconnect(peer_address) {
+            supervisor::report_disconnected(&self.ctx, peer_address, supervisor::CLOSED_LOCALLY);
         }
     }
 
     // Disconnect and resource cleanup method
-    pub async fn disconnect(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        if let Some(handle) = self.connection_handle.take() {
-            handle.abort();
-            let _ = tokio::time::timeout(std::time::Duration::from_secs(1), handle).await;
+    pub async fn disconnect(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for (_, supervisor) in self.supervisors.lock().unwrap().drain() {
+            supervisor.abort();
+        }
+
+        for address in self.ctx.connections.disconnect_all() {
+            supervisor::report_disconnected(&self.ctx, &address, supervisor::CLOSED_LOCALLY);
+        }
+
+        if let Some((_, listener)) = self.listener_handle.lock().unwrap().take() {
+            listener.abort();
         }
 
-        self.message_tx = None;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testing;
+    use crate::database::outbox_schema::DeliveryStatus;
+
+    #[tokio::test]
+    async fn test_queued_message_is_delivered_once_the_peer_can_be_dialed() {
+        testing::init_db().await;
+        let alice_identity = Identity::generate("Alice").unwrap();
+        let bob_identity = Identity::generate("Bob").unwrap();
+        let alice = YggdrasilMessenger::new(alice_identity.clone());
+        let bob = YggdrasilMessenger::new(bob_identity.clone());
+
+        // Bob is offline: his proxy refuses connections until he listens
+        let bob_port = testing::free_port();
+        let to_bob = testing::spawn_forwarder(SocketAddr::from(([127, 0, 0, 1], bob_port))).await;
+        let to_alice = testing::spawn_forwarder(SocketAddr::from(([127, 0, 0, 1], testing::free_port()))).await;
+        let bob_contact = testing::save_contact("200:7::b", to_bob, &bob_identity).await;
+        testing::save_contact("200:7::a", to_alice, &alice_identity).await;
+
+        let mut alice_events = alice.subscribe();
+        let mut bob_events = bob.subscribe();
+
+        let outbox = OutboxRepository::new(database::get_db().unwrap());
+        let message = alice.send_message(&bob_contact, "hello bob".to_string()).await.unwrap();
+        assert_eq!(outbox.statuses(&[message.id]).await.unwrap()[&message.id], DeliveryStatus::Pending);
+
+        // Alice keeps redialing until Bob comes online, then the queue is flushed
+        bob.start_listener(ListenerConfig { bind_addr: SocketAddr::from(([127, 0, 0, 1], bob_port)) }).await.unwrap();
+
+        let received = testing::wait_for(&mut bob_events, |event| match event {
+            MessengerEvent::MessageReceived(message) => Some(message),
+            _ => None,
+        })
+            .await;
+        assert_eq!(received.body, "hello bob");
+        assert_eq!(received.message_id.as_str(), message.envelope_id.as_deref().unwrap());
+
+        testing::wait_for(&mut alice_events, |event| match event {
+            MessengerEvent::MessageDelivered { .. } => Some(()),
+            _ => None,
+        })
+            .await;
+        assert_eq!(outbox.statuses(&[message.id]).await.unwrap()[&message.id], DeliveryStatus::Sent);
+        assert_eq!(alice.ctx.connections.state("200:7::b"), PeerState::Connected);
+
+        alice.disconnect().await.unwrap();
+        bob.disconnect().await.unwrap();
+    }
+}
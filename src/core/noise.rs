@@ -0,0 +1,314 @@
+/// End-to-end Encryption for Syggrel Chat peer sessions
+///
+/// Yggdrasil only encrypts between nodes, so the SOCKS5 leg and the local
+/// yggstack process see session traffic in the clear and nothing proves the
+/// peer owns the key we expect. Every session therefore starts with a Noise XX
+/// handshake authenticated by both sides' long-term identity keys, and all
+/// frames after it travel inside Noise transport messages.
+///
+/// XX is used rather than IK because it works on first contact, before we
+/// know the peer's key; the key it reveals is then pinned on the contact.
+///
+/// On the wire every Noise message is prefixed with its length:
+///
+/// ```text
+/// +----------------+------------------------------+
+/// | message length | Noise message                |
+/// |  2 bytes (BE)  | handshake or ciphertext      |
+/// +----------------+------------------------------+
+/// ```
+///
+/// Frames larger than one Noise message are split across several; the
+/// receiver reassembles them as a plain byte stream.
+use crate::core::identity::Identity;
+use snow::{HandshakeState, StatelessTransportState};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio::time::{timeout, Duration};
+use tracing::debug;
+
+/// Noise protocol used for every session
+pub const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+/// Largest Noise message allowed by the spec
+const MAX_NOISE_MSG_LEN: usize = 65535;
+
+/// Authentication tag appended to every encrypted message
+const TAG_LEN: usize = 16;
+
+/// Largest plaintext carried by one transport message
+const MAX_CHUNK_LEN: usize = MAX_NOISE_MSG_LEN - TAG_LEN;
+
+/// How long the peer gets to complete the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Errors produced while establishing or using an encrypted channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NoiseError {
+    Io(String),
+    Handshake(String),
+    Timeout,
+    Decrypt,
+}
+
+impl std::fmt::Display for NoiseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NoiseError::Io(msg) => write!(f, "I/O error: {}", msg),
+            NoiseError::Handshake(msg) => write!(f, "Noise handshake failed: {}", msg),
+            NoiseError::Timeout => write!(f, "Peer did not complete the handshake in time"),
+            NoiseError::Decrypt => write!(f, "Failed to decrypt message from peer"),
+        }
+    }
+}
+
+impl std::error::Error for NoiseError {}
+
+impl From<std::io::Error> for NoiseError {
+    fn from(err: std::io::Error) -> Self {
+        NoiseError::Io(err.to_string())
+    }
+}
+
+impl From<snow::Error> for NoiseError {
+    fn from(err: snow::Error) -> Self {
+        NoiseError::Handshake(err.to_string())
+    }
+}
+
+/// Encrypted channel produced by a completed handshake
+pub struct SecureChannel<S> {
+    stream: S,
+    transport: StatelessTransportState,
+}
+
+impl<S> SecureChannel<S>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    /// Static public key the peer proved ownership of during the handshake
+    pub fn remote_public_key(&self) -> &[u8] {
+        self.transport.get_remote_static().unwrap_or_default()
+    }
+
+    /// Turns the channel into a plaintext stream for the session tasks
+    ///
+    /// A background task encrypts whatever is written to the returned stream
+    /// and decrypts what arrives from the peer. It ends, closing the returned
+    /// stream, when either side closes or a message fails to decrypt.
+    pub fn into_stream(self) -> DuplexStream {
+        let (local, plain) = tokio::io::duplex(2 * MAX_NOISE_MSG_LEN);
+
+        tokio::spawn(async move {
+            if let Err(e) = pump(self.stream, plain, self.transport).await {
+                debug!("Encrypted channel closed: {}", e);
+            }
+        });
+
+        local
+    }
+}
+
+/// Runs the handshake as the dialing side
+pub async fn initiate<S>(mut stream: S, identity: &Identity) -> Result<SecureChannel<S>, NoiseError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut state = builder(identity)?.build_initiator()?;
+
+    timeout(HANDSHAKE_TIMEOUT, async {
+        send_handshake(&mut stream, &mut state).await?;     // -> e
+        receive_handshake(&mut stream, &mut state).await?;  // <- e, ee, s, es
+        send_handshake(&mut stream, &mut state).await       // -> s, se
+    })
+        .await
+        .map_err(|_| NoiseError::Timeout)??;
+
+    finish(stream, state)
+}
+
+/// Runs the handshake as the accepting side
+pub async fn respond<S>(mut stream: S, identity: &Identity) -> Result<SecureChannel<S>, NoiseError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut state = builder(identity)?.build_responder()?;
+
+    timeout(HANDSHAKE_TIMEOUT, async {
+        receive_handshake(&mut stream, &mut state).await?;
+        send_handshake(&mut stream, &mut state).await?;
+        receive_handshake(&mut stream, &mut state).await
+    })
+        .await
+        .map_err(|_| NoiseError::Timeout)??;
+
+    finish(stream, state)
+}
+
+fn builder(identity: &Identity) -> Result<snow::Builder<'_>, NoiseError> {
+    let params = NOISE_PARAMS
+        .parse()
+        .map_err(|e| NoiseError::Handshake(format!("{:?}", e)))?;
+    Ok(snow::Builder::new(params).local_private_key(identity.private_key()))
+}
+
+fn finish<S>(stream: S, state: HandshakeState) -> Result<SecureChannel<S>, NoiseError> {
+    if !state.is_handshake_finished() {
+        return Err(NoiseError::Handshake("handshake incomplete".to_string()));
+    }
+
+    Ok(SecureChannel {
+        stream,
+        transport: state.into_stateless_transport_mode()?,
+    })
+}
+
+async fn send_handshake<S>(stream: &mut S, state: &mut HandshakeState) -> Result<(), NoiseError>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut message = vec![0u8; MAX_NOISE_MSG_LEN];
+    let len = state.write_message(&[], &mut message)?;
+    write_message(stream, &message[..len]).await
+}
+
+async fn receive_handshake<S>(stream: &mut S, state: &mut HandshakeState) -> Result<(), NoiseError>
+where
+    S: AsyncRead + Unpin,
+{
+    let message = read_message(stream)
+        .await?
+        .ok_or_else(|| NoiseError::Handshake("peer closed the connection".to_string()))?;
+
+    let mut payload = vec![0u8; MAX_NOISE_MSG_LEN];
+    state.read_message(&message, &mut payload)?;
+    Ok(())
+}
+
+async fn write_message<W>(writer: &mut W, message: &[u8]) -> Result<(), NoiseError>
+where
+    W: AsyncWrite + Unpin,
+{
+    writer.write_all(&(message.len() as u16).to_be_bytes()).await?;
+    writer.write_all(message).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Reads one length-prefixed Noise message, `None` on a clean EOF
+async fn read_message<R>(reader: &mut R) -> Result<Option<Vec<u8>>, NoiseError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = [0u8; 2];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let mut message = vec![0u8; u16::from_be_bytes(header) as usize];
+    reader.read_exact(&mut message).await?;
+    Ok(Some(message))
+}
+
+/// Moves bytes between the plaintext side and the encrypted network stream
+async fn pump<S>(
+    stream: S,
+    plain: DuplexStream,
+    transport: StatelessTransportState,
+) -> Result<(), NoiseError>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (mut net_reader, mut net_writer) = tokio::io::split(stream);
+    let (mut plain_reader, mut plain_writer) = tokio::io::split(plain);
+
+    // Each direction counts its own nonces, as messages arrive in order over TCP
+    let outgoing = async {
+        let mut nonce = 0u64;
+        let mut chunk = vec![0u8; MAX_CHUNK_LEN];
+        let mut message = vec![0u8; MAX_NOISE_MSG_LEN];
+        loop {
+            let n = plain_reader.read(&mut chunk).await?;
+            if n == 0 {
+                return Ok(());    // Session closed its side
+            }
+            let len = transport
+                .write_message(nonce, &chunk[..n], &mut message)
+                .map_err(|e| NoiseError::Io(format!("Encryption failed: {}", e)))?;
+            nonce += 1;
+            write_message(&mut net_writer, &message[..len]).await?;
+        }
+    };
+
+    let incoming = async {
+        let mut nonce = 0u64;
+        let mut chunk = vec![0u8; MAX_NOISE_MSG_LEN];
+        while let Some(message) = read_message(&mut net_reader).await? {
+            let n = transport
+                .read_message(nonce, &message, &mut chunk)
+                .map_err(|_| NoiseError::Decrypt)?;
+            nonce += 1;
+            plain_writer.write_all(&chunk[..n]).await?;
+        }
+        Ok(())    // Peer closed the connection
+    };
+
+    tokio::select! {
+        result = outgoing => result,
+        result = incoming => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::protocol::{self, Envelope};
+
+    async fn connected_pair() -> (SecureChannel<DuplexStream>, SecureChannel<DuplexStream>, Identity, Identity) {
+        let alice = Identity::generate("test").unwrap();
+        let bob = Identity::generate("test").unwrap();
+        let (a, b) = tokio::io::duplex(4096);
+
+        let (initiator, responder) = tokio::join!(initiate(a, &alice), respond(b, &bob));
+        (initiator.unwrap(), responder.unwrap(), alice, bob)
+    }
+
+    #[tokio::test]
+    async fn test_handshake_reveals_both_static_keys() {
+        let (initiator, responder, alice, bob) = connected_pair().await;
+
+        assert_eq!(initiator.remote_public_key(), bob.public_key());
+        assert_eq!(responder.remote_public_key(), alice.public_key());
+    }
+
+    #[tokio::test]
+    async fn test_frames_round_trip_through_encrypted_streams() {
+        let (initiator, responder, _, _) = connected_pair().await;
+        let mut a = initiator.into_stream();
+        let mut b = responder.into_stream();
+
+        // Larger than one Noise message, so it must be split and reassembled
+        let envelope = Envelope::text("x".repeat(3 * MAX_NOISE_MSG_LEN));
+        protocol::write_frame(&mut a, &envelope).await.unwrap();
+        assert_eq!(protocol::read_frame(&mut b).await.unwrap(), Some(envelope));
+
+        let reply = Envelope::text("reply");
+        protocol::write_frame(&mut b, &reply).await.unwrap();
+        assert_eq!(protocol::read_frame(&mut a).await.unwrap(), Some(reply));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_fails_against_plaintext_peer() {
+        let alice = Identity::generate("test").unwrap();
+        let (a, mut b) = tokio::io::duplex(4096);
+
+        let plaintext_peer = async move {
+            let _ = read_message(&mut b).await;
+            protocol::write_frame(&mut b, &Envelope::text("hello")).await.unwrap();
+        };
+
+        let (result, _) = tokio::join!(initiate(a, &alice), plaintext_peer);
+        assert!(result.is_err());
+    }
+}
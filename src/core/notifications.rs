@@ -0,0 +1,242 @@
+/// New Message Notifications for Syggrel Chat
+///
+/// While notifications are enabled in the settings, every incoming message
+/// shows a notice with the sender's name and the start of the message, unless
+/// its chat is already open. With the sound setting on, a short tone plays
+/// along with it, from a WAV generated once and handed to an `audio` element.
+/// The notice opens the chat when clicked and goes away after
+/// `NOTICE_DURATION`.
+///
+/// `NotificationLayout` wraps every route, so notices show on any page.
+use crate::core::chat_data::ChatId;
+use crate::core::events::MessengerEvent;
+use crate::core::messenger::YggdrasilMessenger;
+use crate::core::routes::Route;
+use crate::core::settings::{self, Settings};
+use crate::database;
+use crate::database::contact_repository::ContactRepository;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use dioxus::prelude::*;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+/// How long a notice stays up
+pub const NOTICE_DURATION: Duration = Duration::from_secs(6);
+
+/// Longest message preview shown, in characters
+const PREVIEW_CHARS: usize = 80;
+
+/// Sample rate of the notification tone
+const TONE_RATE: u32 = 8000;
+
+/// Pitch and length of the notification tone
+const TONE_HZ: f32 = 880.0;
+const TONE_SAMPLES: u32 = TONE_RATE * 15 / 100;
+
+/// A new message to tell the user about
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notice {
+    pub chat: ChatId,
+    pub contact_id: i32,
+    pub preview: String,
+    pub sound: bool,
+}
+
+/// The notice an event calls for, if any
+///
+/// Only received messages are announced, never while notifications are
+/// disabled or for the chat that is open.
+pub fn notice_for(settings: &Settings, event: &MessengerEvent, open_chat: Option<&ChatId>) -> Option<Notice> {
+    let MessengerEvent::MessageReceived(message) = event else {
+        return None;
+    };
+    let chat = ChatId::from(message.contact_id);
+    if !settings.notifications_enabled || open_chat == Some(&chat) {
+        return None;
+    }
+
+    Some(Notice {
+        chat,
+        contact_id: message.contact_id,
+        preview: preview(&message.body),
+        sound: settings.notification_sound,
+    })
+}
+
+fn preview(body: &str) -> String {
+    let body = body.trim();
+    match body.char_indices().nth(PREVIEW_CHARS) {
+        Some((end, _)) => format!("{}…", &body[..end]),
+        None => body.to_string(),
+    }
+}
+
+/// Route layout that announces incoming messages over the current page
+#[component]
+pub fn NotificationLayout() -> Element {
+    let app_settings = settings::use_settings();
+    let messenger = try_use_context::<Arc<YggdrasilMessenger>>();
+    let route = use_route::<Route>();
+
+    let mut open_chat = use_signal(|| None::<ChatId>);
+    let mut shown = use_signal(|| None::<(Notice, String)>);    // Notice and sender name
+    let mut shown_at = use_signal(|| 0u64);    // Bumped per notice, so an old timer can't hide a newer one
+
+    let current = match route {
+        Route::Conversation { id } => Some(id),
+        _ => None,
+    };
+    use_effect(use_reactive!(|current| open_chat.set(current)));
+
+    use_future(move || {
+        let messenger = messenger.clone();
+        async move {
+            let Some(messenger) = messenger else { return };
+            let mut events = messenger.subscribe();
+
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => continue,    // Missed messages aren't worth a late notice
+                    Err(RecvError::Closed) => return,
+                };
+                let Some(notice) = notice_for(&app_settings.peek(), &event, open_chat.peek().as_ref()) else {
+                    continue;
+                };
+
+                let name = sender_name(notice.contact_id).await;
+                let serial = *shown_at.peek() + 1;
+                shown_at.set(serial);
+                shown.set(Some((notice, name)));
+
+                spawn(async move {
+                    tokio::time::sleep(NOTICE_DURATION).await;
+                    if *shown_at.peek() == serial {
+                        shown.set(None);
+                    }
+                });
+            }
+        }
+    });
+
+    rsx! {
+        Outlet::<Route> {}
+
+        if let Some((notice, name)) = shown() {
+            div {
+                key: "{shown_at}",    // A new element per notice, so the tone plays again
+                class: "message-notice",
+                role: "status",
+                aria_live: "polite",
+                if notice.sound {
+                    audio { autoplay: true, src: tone_uri() }
+                }
+                Link {
+                    to: Route::Conversation { id: notice.chat.clone() },
+                    onclick: move |_| shown.set(None),
+                    strong { class: "notice-sender", "{name}" }
+                    p { class: "notice-preview", "{notice.preview}" }
+                }
+            }
+        }
+    }
+}
+
+/// The notification tone as a `data:` URI
+fn tone_uri() -> &'static str {
+    static URI: OnceLock<String> = OnceLock::new();
+    URI.get_or_init(|| format!("data:audio/wav;base64,{}", BASE64.encode(tone_wav())))
+}
+
+/// A short sine beep as an 8-bit mono WAV file
+fn tone_wav() -> Vec<u8> {
+    let mut wav = Vec::with_capacity(44 + TONE_SAMPLES as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + TONE_SAMPLES).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());    // Format chunk size
+    wav.extend_from_slice(&1u16.to_le_bytes());    // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes());    // Mono
+    wav.extend_from_slice(&TONE_RATE.to_le_bytes());
+    wav.extend_from_slice(&TONE_RATE.to_le_bytes());    // Bytes per second
+    wav.extend_from_slice(&1u16.to_le_bytes());    // Bytes per frame
+    wav.extend_from_slice(&8u16.to_le_bytes());    // Bits per sample
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&TONE_SAMPLES.to_le_bytes());
+
+    for i in 0..TONE_SAMPLES {
+        let phase = std::f32::consts::TAU * TONE_HZ * i as f32 / TONE_RATE as f32;
+        wav.push((128.0 + 48.0 * phase.sin()) as u8);
+    }
+    wav
+}
+
+/// Display name of the contact, or a stand-in if it can't be loaded
+async fn sender_name(contact_id: i32) -> String {
+    let found = match database::get_db() {
+        Some(db) => ContactRepository::new(db).find(contact_id).await.ok().flatten(),
+        None => None,
+    };
+    found.map(|contact| contact.display_name).unwrap_or_else(|| "New message".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::ReceivedMessage;
+    use crate::core::protocol::MessageId;
+
+    fn received(contact_id: i32, body: &str) -> MessengerEvent {
+        MessengerEvent::MessageReceived(ReceivedMessage {
+            peer_address: "200::1".to_string(),
+            contact_id,
+            message_id: MessageId::new(),
+            sent_at: chrono::Utc::now(),
+            body: body.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_notice_follows_settings_and_open_chat() {
+        let settings = Settings::default();
+        let event = received(1, "  hello  ");
+
+        let notice = notice_for(&settings, &event, None).unwrap();
+        assert_eq!(notice.chat, ChatId::from(1));
+        assert_eq!(notice.preview, "hello");
+        assert!(notice.sound);
+
+        // Not for the chat being read, nor with notifications off
+        assert_eq!(notice_for(&settings, &event, Some(&ChatId::from(1))), None);
+        assert!(notice_for(&settings, &event, Some(&ChatId::from(2))).is_some());
+        let disabled = Settings { notifications_enabled: false, ..Settings::default() };
+        assert_eq!(notice_for(&disabled, &event, None), None);
+
+        let quiet = Settings { notification_sound: false, ..Settings::default() };
+        assert!(!notice_for(&quiet, &event, None).unwrap().sound);
+
+        let delivered = MessengerEvent::ChatRead { peer_address: "200::1".to_string(), contact_id: 1 };
+        assert_eq!(notice_for(&settings, &delivered, None), None);
+    }
+
+    #[test]
+    fn test_tone_is_a_complete_wav() {
+        let wav = tone_wav();
+
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(wav.len(), 44 + TONE_SAMPLES as usize);
+        assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()) as usize, wav.len() - 8);
+        assert!(tone_uri().starts_with("data:audio/wav;base64,UklGR"));
+    }
+
+    #[test]
+    fn test_long_messages_are_cut_on_a_char_boundary() {
+        let long = "é".repeat(PREVIEW_CHARS + 5);
+        let notice = notice_for(&Settings::default(), &received(1, &long), None).unwrap();
+
+        assert_eq!(notice.preview.chars().count(), PREVIEW_CHARS + 1);
+        assert!(notice.preview.ends_with('…'));
+    }
+}
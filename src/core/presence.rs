@@ -0,0 +1,306 @@
+/// Peer Presence Tracking for Syggrel Chat
+///
+/// Works out whether each contact is online, away or offline. A contact with
+/// a session is online unless it advertised itself as away in a presence
+/// frame; a contact without one is offline. Every change is published as
+/// `MessengerEvent::PresenceChanged`.
+///
+/// Our own status is advertised the same way: we count as away once the user
+/// hasn't sent a message or opened a chat for `AWAY_AFTER`, and every session
+/// sends a presence frame when that changes (see `own_status`).
+///
+/// Every sign of life from a peer, heartbeats included, is also written to
+/// `contacts.last_seen`, at most once per `LAST_SEEN_DEBOUNCE` per contact so
+/// a chatty peer doesn't turn into a database write per frame. The moment a
+/// session ends is always written.
+use crate::core::chat_data::ChatId;
+use crate::core::events::{EventBus, MessengerEvent};
+use crate::core::protocol::PresenceStatus;
+use crate::core::session::PeerInfo;
+use crate::database;
+use crate::database::contact_repository::ContactRepository;
+use dioxus::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Once};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// How long the user may go without sending a message or opening a chat before we advertise ourselves as away
+pub const AWAY_AFTER: Duration = Duration::from_secs(120);
+
+/// Minimum time between two `last_seen` writes for the same contact
+pub const LAST_SEEN_DEBOUNCE: Duration = Duration::from_secs(60);
+
+/// How often our own idle time is checked
+const SWEEP_INTERVAL: Duration = Duration::from_secs(15);
+
+struct PeerPresence {
+    contact_id: i32,
+    status: PresenceStatus,    // Last status published
+    connected: bool,
+    advertised: PresenceStatus,    // What the peer last told us; Online until told otherwise
+    last_seen_written: Option<Instant>,
+}
+
+impl PeerPresence {
+    fn new(contact_id: i32) -> Self {
+        Self {
+            contact_id,
+            status: PresenceStatus::Offline,
+            connected: false,
+            advertised: PresenceStatus::Online,
+            last_seen_written: None,
+        }
+    }
+
+    fn current_status(&self) -> PresenceStatus {
+        if self.connected { self.advertised } else { PresenceStatus::Offline }
+    }
+}
+
+/// Presence of every peer seen since start, and our own, shared by all sessions
+///
+/// Cloning is cheap and every clone refers to the same state.
+#[derive(Clone)]
+pub struct PresenceTracker {
+    peers: Arc<Mutex<HashMap<String, PeerPresence>>>,    // Keyed by Yggdrasil address
+    events: EventBus,
+    last_active: Arc<Mutex<Instant>>,    // Last time the user sent a message or opened a chat
+    own: watch::Sender<PresenceStatus>,    // What we advertise to peers
+    sweeper: Arc<Once>,    // Started with the first activity, needs a runtime
+}
+
+impl PresenceTracker {
+    pub fn new(events: EventBus) -> Self {
+        Self {
+            peers: Arc::new(Mutex::new(HashMap::new())),
+            events,
+            last_active: Arc::new(Mutex::new(Instant::now())),
+            own: watch::Sender::new(PresenceStatus::Online),
+            sweeper: Arc::new(Once::new()),
+        }
+    }
+
+    /// Records a sign of life from a connected peer: a connect or any received frame
+    pub fn record_activity(&self, peer: &PeerInfo) {
+        if self.touch(peer, Instant::now()) {
+            write_last_seen(peer.contact_id);
+        }
+        self.start_sweeper();
+    }
+
+    /// Records the status a peer advertised in a presence frame
+    pub fn record_advertised(&self, peer: &PeerInfo, status: PresenceStatus) {
+        self.peers
+            .lock()
+            .unwrap()
+            .entry(peer.address.clone())
+            .or_insert_with(|| PeerPresence::new(peer.contact_id))
+            .advertised = status;
+        self.record_activity(peer);
+    }
+
+    /// Records that the session with `address` has ended
+    pub fn record_disconnect(&self, address: &str) {
+        let contact_id = {
+            let mut peers = self.peers.lock().unwrap();
+            let Some(peer) = peers.get_mut(address) else { return };
+            peer.connected = false;
+            peer.advertised = PresenceStatus::Online;    // Reset for the next session
+            refresh(&self.events, address, peer);
+            peer.contact_id
+        };
+        write_last_seen(contact_id);
+    }
+
+    /// Current presence of one peer
+    pub fn status(&self, address: &str) -> PresenceStatus {
+        self.peers
+            .lock()
+            .unwrap()
+            .get(address)
+            .map(|p| p.status)
+            .unwrap_or(PresenceStatus::Offline)
+    }
+
+    /// Current presence of every known peer, keyed by the chat it belongs to
+    pub fn chat_statuses(&self) -> HashMap<ChatId, PresenceStatus> {
+        self.peers
+            .lock()
+            .unwrap()
+            .values()
+            .map(|p| (ChatId::from(p.contact_id), p.status))
+            .collect()
+    }
+
+    /// Records that the user sent a message or opened a chat, which ends our idle time
+    pub fn record_own_activity(&self) {
+        *self.last_active.lock().unwrap() = Instant::now();
+        self.own.send_if_modified(|status| std::mem::replace(status, PresenceStatus::Online) != PresenceStatus::Online);
+    }
+
+    /// The status we advertise, updated as the user goes idle and comes back
+    ///
+    /// Sessions send it when they start and again on every change.
+    pub fn own_status(&self) -> watch::Receiver<PresenceStatus> {
+        self.own.subscribe()
+    }
+
+    // Marks the peer connected, returning whether `last_seen` is due a write at `now`
+    fn touch(&self, peer: &PeerInfo, now: Instant) -> bool {
+        let mut peers = self.peers.lock().unwrap();
+        let entry = peers
+            .entry(peer.address.clone())
+            .or_insert_with(|| PeerPresence::new(peer.contact_id));
+        entry.connected = true;
+        refresh(&self.events, &peer.address, entry);
+
+        let due = entry
+            .last_seen_written
+            .is_none_or(|written| now.duration_since(written) >= LAST_SEEN_DEBOUNCE);
+        if due {
+            entry.last_seen_written = Some(now);
+        }
+        due
+    }
+
+    fn start_sweeper(&self) {
+        self.sweeper.call_once(|| {
+            // Holds only a weak reference, so it ends with the last tracker clone
+            let last_active = Arc::downgrade(&self.last_active);
+            let own = self.own.clone();
+
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(SWEEP_INTERVAL);
+                loop {
+                    ticker.tick().await;
+                    let Some(last_active) = last_active.upgrade() else { return };
+                    check_idle(&last_active, &own, Instant::now());
+                }
+            });
+        });
+    }
+}
+
+/// Switches us to away once the user has been idle for `AWAY_AFTER` at `now`
+fn check_idle(last_active: &Mutex<Instant>, own: &watch::Sender<PresenceStatus>, now: Instant) {
+    if now.duration_since(*last_active.lock().unwrap()) >= AWAY_AFTER {
+        own.send_if_modified(|status| std::mem::replace(status, PresenceStatus::Away) != PresenceStatus::Away);
+    }
+}
+
+/// Publishes the peer's status if it changed
+fn refresh(events: &EventBus, address: &str, peer: &mut PeerPresence) {
+    let status = peer.current_status();
+    if status != peer.status {
+        peer.status = status;
+        events.publish(MessengerEvent::PresenceChanged {
+            peer_address: address.to_string(),
+            contact_id: peer.contact_id,
+            status,
+        });
+    }
+}
+
+/// Stores the current time as the contact's `last_seen`, in the background
+fn write_last_seen(contact_id: i32) {
+    let Some(db) = database::get_db() else { return };
+
+    tokio::spawn(async move {
+        if let Err(e) = ContactRepository::new(db).record_last_seen(contact_id, chrono::Utc::now()).await {
+            tracing::warn!("Failed to update last_seen for contact {}: {}", contact_id, e);
+        }
+    });
+}
+
+/// Small coloured dot showing a contact's presence
+#[component]
+pub fn PresenceDot(status: PresenceStatus) -> Element {
+    let (class, label) = match status {
+        PresenceStatus::Online => ("online", "Online"),
+        PresenceStatus::Away => ("away", "Away"),
+        PresenceStatus::Offline => ("offline", "Offline"),
+    };
+
+    rsx! {
+        span {
+            class: "presence-dot {class}",
+            role: "img",
+            aria_label: label,
+            title: label,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::broadcast;
+
+    fn peer() -> PeerInfo {
+        PeerInfo { address: "200::1".to_string(), contact_id: 1 }
+    }
+
+    fn next_status(events: &mut broadcast::Receiver<MessengerEvent>) -> Option<PresenceStatus> {
+        match events.try_recv() {
+            Ok(MessengerEvent::PresenceChanged { status, .. }) => Some(status),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_advertise_and_disconnect_change_presence() {
+        let bus = EventBus::new();
+        let mut events = bus.subscribe();
+        let tracker = PresenceTracker::new(bus);
+
+        tracker.record_activity(&peer());
+        assert_eq!(next_status(&mut events), Some(PresenceStatus::Online));
+
+        // Heartbeats and other frames keep a connected peer online, however long it stays quiet
+        tracker.record_activity(&peer());
+        assert_eq!(next_status(&mut events), None);
+
+        tracker.record_advertised(&peer(), PresenceStatus::Away);
+        assert_eq!(next_status(&mut events), Some(PresenceStatus::Away));
+        tracker.record_activity(&peer());
+        assert_eq!(tracker.status("200::1"), PresenceStatus::Away);
+
+        tracker.record_advertised(&peer(), PresenceStatus::Online);
+        assert_eq!(next_status(&mut events), Some(PresenceStatus::Online));
+
+        tracker.record_disconnect("200::1");
+        assert_eq!(next_status(&mut events), Some(PresenceStatus::Offline));
+        assert_eq!(tracker.chat_statuses()[&ChatId::from(1)], PresenceStatus::Offline);
+    }
+
+    #[tokio::test]
+    async fn test_own_status_goes_away_when_idle() {
+        let tracker = PresenceTracker::new(EventBus::new());
+        let mut own = tracker.own_status();
+        let start = *tracker.last_active.lock().unwrap();
+
+        check_idle(&tracker.last_active, &tracker.own, start + AWAY_AFTER - Duration::from_secs(1));
+        assert!(!own.has_changed().unwrap());
+
+        check_idle(&tracker.last_active, &tracker.own, start + AWAY_AFTER);
+        assert_eq!(*own.borrow_and_update(), PresenceStatus::Away);
+
+        tracker.record_own_activity();
+        assert_eq!(*own.borrow_and_update(), PresenceStatus::Online);
+        tracker.record_own_activity();
+        assert!(!own.has_changed().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_last_seen_writes_are_debounced() {
+        let tracker = PresenceTracker::new(EventBus::new());
+        let start = Instant::now();
+
+        assert!(tracker.touch(&peer(), start));
+        assert!(!tracker.touch(&peer(), start + Duration::from_secs(1)));
+        assert!(!tracker.touch(&peer(), start + LAST_SEEN_DEBOUNCE - Duration::from_secs(1)));
+        assert!(tracker.touch(&peer(), start + LAST_SEEN_DEBOUNCE));
+    }
+}
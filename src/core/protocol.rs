@@ -0,0 +1,354 @@
+/// Wire Protocol for Syggrel Chat peer sessions
+///
+/// Every message exchanged between peers is a length-prefixed frame:
+///
+/// ```text
+/// +---------+----------------+---------------------------+
+/// | version | payload length | payload (JSON Envelope)   |
+/// |  1 byte |  4 bytes (BE)  | `payload length` bytes    |
+/// +---------+----------------+---------------------------+
+/// ```
+///
+/// The explicit length means payloads may contain any bytes, including
+/// newlines, and the receiver can reject oversized frames from the header
+/// alone before allocating anything. The version byte lets future releases
+/// change the payload encoding while still rejecting frames they cannot read.
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Current frame format version
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Size of the fixed frame header (version byte + u32 length)
+pub const FRAME_HEADER_LEN: usize = 5;
+
+/// Largest payload accepted from a peer (1MB)
+pub const MAX_FRAME_LEN: usize = 1024 * 1024;
+
+/// Errors produced while encoding or decoding frames
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    Io(String),
+    UnsupportedVersion(u8),
+    FrameTooLarge(usize),
+    Truncated,
+    Malformed(String),
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolError::Io(msg) => write!(f, "I/O error: {}", msg),
+            ProtocolError::UnsupportedVersion(v) => write!(f, "Unsupported protocol version {}", v),
+            ProtocolError::FrameTooLarge(len) => {
+                write!(f, "Frame of {} bytes exceeds the {} byte limit", len, MAX_FRAME_LEN)
+            }
+            ProtocolError::Truncated => write!(f, "Connection closed in the middle of a frame"),
+            ProtocolError::Malformed(msg) => write!(f, "Malformed frame: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<std::io::Error> for ProtocolError {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::UnexpectedEof => ProtocolError::Truncated,
+            _ => ProtocolError::Io(err.to_string()),
+        }
+    }
+}
+
+/// Globally unique identifier of a single envelope
+///
+/// Generated by the sender and echoed back in acknowledgements, so it must
+/// survive the round trip unchanged.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MessageId(String);
+
+impl MessageId {
+    pub fn new() -> Self {
+        Self(uuid::Uuid::new_v4().to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A freshly generated id, same as `MessageId::new`
+impl Default for MessageId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Wraps an id received from a peer or loaded from the database
+impl From<String> for MessageId {
+    fn from(id: String) -> Self {
+        Self(id)
+    }
+}
+
+impl std::fmt::Display for MessageId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Presence status advertised by a peer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    Online,
+    Away,
+    Offline,
+}
+
+/// Payload carried by an envelope
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FrameKind {
+    Text {
+        body: String,
+    },
+    Ack {
+        message_id: MessageId,
+    },
+    Read {
+        message_ids: Vec<MessageId>,
+    },
+    Typing {
+        active: bool,
+    },
+    Presence {
+        status: PresenceStatus,
+    },
+    Ping,    // Heartbeat; the peer answers with a pong
+    Pong {
+        ping_id: MessageId,    // Envelope id of the ping being answered
+    },
+    FileChunk {
+        transfer_id: String,
+        index: u32,
+        total: u32,
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+    },
+}
+
+/// Envelope wrapping every frame payload with its metadata
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub id: MessageId,
+    pub sent_at: chrono::DateTime<chrono::Utc>,
+    pub sender: Option<String>,    // Sender's Yggdrasil address, if advertised
+    pub kind: FrameKind,
+}
+
+impl Envelope {
+    pub fn new(kind: FrameKind) -> Self {
+        Self {
+            id: MessageId::new(),
+            sent_at: chrono::Utc::now(),
+            sender: None,
+            kind,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn text(body: impl Into<String>) -> Self {
+        Self::new(FrameKind::Text { body: body.into() })
+    }
+
+    pub fn ack(message_id: MessageId) -> Self {
+        Self::new(FrameKind::Ack { message_id })
+    }
+
+    pub fn read_receipt(message_ids: Vec<MessageId>) -> Self {
+        Self::new(FrameKind::Read { message_ids })
+    }
+
+    pub fn ping() -> Self {
+        Self::new(FrameKind::Ping)
+    }
+
+    pub fn pong(ping_id: MessageId) -> Self {
+        Self::new(FrameKind::Pong { ping_id })
+    }
+
+    pub fn with_sender(mut self, sender: impl Into<String>) -> Self {
+        self.sender = Some(sender.into());
+        self
+    }
+}
+
+/// Serializes an envelope into a complete frame (header + payload)
+pub fn encode_frame(envelope: &Envelope) -> Result<Vec<u8>, ProtocolError> {
+    let payload = serde_json::to_vec(envelope)
+        .map_err(|e| ProtocolError::Malformed(e.to_string()))?;
+
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(ProtocolError::FrameTooLarge(payload.len()));
+    }
+
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.push(PROTOCOL_VERSION);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// Validates a frame header and returns the payload length it announces
+fn parse_header(header: &[u8]) -> Result<usize, ProtocolError> {
+    if header[0] != PROTOCOL_VERSION {
+        return Err(ProtocolError::UnsupportedVersion(header[0]));
+    }
+
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(ProtocolError::FrameTooLarge(len));
+    }
+
+    Ok(len)
+}
+
+fn decode_payload(payload: &[u8]) -> Result<Envelope, ProtocolError> {
+    serde_json::from_slice(payload).map_err(|e| ProtocolError::Malformed(e.to_string()))
+}
+
+/// Reads one frame from an async reader
+///
+/// Returns `Ok(None)` on a clean EOF at a frame boundary and
+/// `ProtocolError::Truncated` if the stream ends inside a frame.
+pub async fn read_frame<R>(reader: &mut R) -> Result<Option<Envelope>, ProtocolError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    let mut filled = 0;
+
+    while filled < FRAME_HEADER_LEN {
+        let n = reader.read(&mut header[filled..]).await?;
+        if n == 0 {
+            return if filled == 0 { Ok(None) } else { Err(ProtocolError::Truncated) };
+        }
+        filled += n;
+    }
+
+    let len = parse_header(&header)?;
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload).await?;
+
+    decode_payload(&payload).map(Some)
+}
+
+/// Writes one frame to an async writer and flushes it
+pub async fn write_frame<W>(writer: &mut W, envelope: &Envelope) -> Result<(), ProtocolError>
+where
+    W: AsyncWrite + Unpin,
+{
+    let frame = encode_frame(envelope)?;
+    writer.write_all(&frame).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Serde helper encoding binary file chunks as base64 strings inside JSON
+mod base64_bytes {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_round_trip_preserves_newlines() {
+        let envelope = Envelope::text("first line\nsecond line\r\n").with_sender("200::1");
+        let frame = encode_frame(&envelope).unwrap();
+        let mut reader = &frame[..];
+
+        assert_eq!(read_frame(&mut reader).await.unwrap(), Some(envelope));
+        assert!(reader.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_splits_back_to_back_frames() {
+        let first = Envelope::text("one");
+        let second = Envelope::new(FrameKind::FileChunk {
+            transfer_id: "t1".to_string(),
+            index: 0,
+            total: 1,
+            data: vec![0, 1, 2, 255],
+        });
+
+        let mut bytes = encode_frame(&first).unwrap();
+        bytes.extend(encode_frame(&second).unwrap());
+        let mut reader = &bytes[..];
+
+        assert_eq!(read_frame(&mut reader).await.unwrap(), Some(first));
+        assert_eq!(read_frame(&mut reader).await.unwrap(), Some(second));
+        assert_eq!(read_frame(&mut reader).await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_oversized_envelope_not_encoded() {
+        let envelope = Envelope::text("x".repeat(MAX_FRAME_LEN + 1));
+        assert!(matches!(encode_frame(&envelope), Err(ProtocolError::FrameTooLarge(_))));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_version_rejected() {
+        let mut frame = encode_frame(&Envelope::text("hi")).unwrap();
+        frame[0] = PROTOCOL_VERSION + 1;
+        let mut reader = &frame[..];
+
+        assert_eq!(read_frame(&mut reader).await, Err(ProtocolError::UnsupportedVersion(PROTOCOL_VERSION + 1)));
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_across_partial_reads() {
+        let envelope = Envelope::text("hello over a tiny pipe");
+        let frame = encode_frame(&envelope).unwrap();
+
+        // A 3-byte pipe forces the reader to assemble the frame from many reads
+        let (mut client, mut server) = tokio::io::duplex(3);
+        let writer = tokio::spawn(async move {
+            client.write_all(&frame).await.unwrap();
+        });
+
+        assert_eq!(read_frame(&mut server).await.unwrap(), Some(envelope));
+        writer.await.unwrap();
+        assert_eq!(read_frame(&mut server).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_reports_truncation() {
+        let frame = encode_frame(&Envelope::text("cut short")).unwrap();
+        let mut reader = &frame[..frame.len() - 2];
+
+        assert_eq!(read_frame(&mut reader).await, Err(ProtocolError::Truncated));
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_rejects_oversized_header() {
+        let mut bytes = vec![PROTOCOL_VERSION];
+        bytes.extend_from_slice(&u32::MAX.to_be_bytes());
+        let mut reader = &bytes[..];
+
+        assert_eq!(read_frame(&mut reader).await, Err(ProtocolError::FrameTooLarge(u32::MAX as usize)));
+    }
+}
@@ -0,0 +1,134 @@
+/// QR Codes for Contact Cards
+///
+/// Renders a contact card (see `core::contact_card`) as a QR code SVG for the
+/// UI, and reads one back from a PNG or JPEG photo or screenshot. Everything
+/// is pure Rust, so it works the same on desktop and Android.
+///
+/// The card is put in the QR code as an upper-case `SYGGREL:` URI: upper-case
+/// letters, digits and `:` fit the QR alphanumeric mode, which makes the code
+/// noticeably smaller than the same text in byte mode.
+use crate::core::contact_card::{CardError, ContactCard};
+use qrcode::render::svg;
+use qrcode::{EcLevel, QrCode};
+
+/// Smallest rendered size in pixels, quiet zone included
+const MIN_SVG_SIZE: u32 = 240;
+
+/// Errors produced while rendering or reading a QR code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QrError {
+    Encode(String),
+    Image(String),    // Not a readable PNG/JPEG
+    NotFound,    // No QR code in the image
+    Decode(String),    // QR code found but unreadable
+    Card(CardError),    // QR code readable but not a contact card
+}
+
+impl std::fmt::Display for QrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QrError::Encode(msg) => write!(f, "Failed to create QR code: {}", msg),
+            QrError::Image(msg) => write!(f, "Failed to read image: {}", msg),
+            QrError::NotFound => write!(f, "No QR code found in the image"),
+            QrError::Decode(msg) => write!(f, "Failed to read QR code: {}", msg),
+            QrError::Card(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for QrError {}
+
+impl From<CardError> for QrError {
+    fn from(err: CardError) -> Self {
+        QrError::Card(err)
+    }
+}
+
+/// Renders `card` as a standalone SVG document
+pub fn card_svg(card: &ContactCard) -> Result<String, QrError> {
+    let code = QrCode::with_error_correction_level(card.to_uri().to_uppercase(), EcLevel::M)
+        .map_err(|e| QrError::Encode(e.to_string()))?;
+
+    Ok(code
+        .render::<svg::Color>()
+        .min_dimensions(MIN_SVG_SIZE, MIN_SVG_SIZE)
+        .dark_color(svg::Color("#000000"))
+        .light_color(svg::Color("#ffffff"))
+        .build())
+}
+
+/// Finds a contact card QR code in a PNG or JPEG image
+///
+/// If the image holds several QR codes, the first one that is a valid
+/// contact card wins.
+pub fn decode_card_image(bytes: &[u8]) -> Result<ContactCard, QrError> {
+    let image = image::load_from_memory(bytes)
+        .map_err(|e| QrError::Image(e.to_string()))?
+        .to_luma8();
+
+    let mut prepared = rqrr::PreparedImage::prepare_from_greyscale(
+        image.width() as usize,
+        image.height() as usize,
+        |x, y| image.get_pixel(x as u32, y as u32).0[0],
+    );
+
+    let mut error = QrError::NotFound;
+    for grid in prepared.detect_grids() {
+        match grid.decode() {
+            Ok((_, text)) => match ContactCard::decode(&text) {
+                Ok(card) => return Ok(card),
+                Err(e) => error = QrError::Card(e),
+            },
+            Err(e) => error = QrError::Decode(e.to_string()),
+        }
+    }
+    Err(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::address::YggdrasilAddress;
+
+    /// Rasterizes `code` the way a screenshot of it would look
+    fn png_of(code: &QrCode) -> Vec<u8> {
+        const SCALE: u32 = 4;
+        const QUIET_ZONE: u32 = 4;
+
+        let width = code.width() as u32;
+        let colors = code.to_colors();
+        let size = (width + 2 * QUIET_ZONE) * SCALE;
+        let image = image::GrayImage::from_fn(size, size, |x, y| {
+            let (x, y) = (x / SCALE, y / SCALE);
+            let dark = (QUIET_ZONE..width + QUIET_ZONE).contains(&x)
+                && (QUIET_ZONE..width + QUIET_ZONE).contains(&y)
+                && colors[((y - QUIET_ZONE) * width + (x - QUIET_ZONE)) as usize] == qrcode::Color::Dark;
+            image::Luma([if dark { 0 } else { 255 }])
+        });
+
+        let mut png = Vec::new();
+        image.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        png
+    }
+
+    #[test]
+    fn test_card_survives_qr_round_trip() {
+        let card = ContactCard {
+            address: YggdrasilAddress { ip: "200:1234::1".parse().unwrap(), port: Some(9000) },
+            display_name: "Alice".to_string(),
+            fingerprint: Some([7u8; 20]),
+        };
+        assert!(card_svg(&card).unwrap().starts_with("<?xml"));
+
+        let code = QrCode::with_error_correction_level(card.to_uri().to_uppercase(), EcLevel::M).unwrap();
+        assert_eq!(decode_card_image(&png_of(&code)).unwrap(), card);
+    }
+
+    #[test]
+    fn test_other_qr_codes_and_images_are_rejected() {
+        let code = QrCode::new("https://example.org").unwrap();
+
+        assert!(matches!(decode_card_image(&png_of(&code)), Err(QrError::Card(_))));
+        assert!(matches!(decode_card_image(b"not an image"), Err(QrError::Image(_))));
+    }
+}
@@ -0,0 +1,80 @@
+/// Delivery and Read Receipts for Syggrel Chat
+///
+/// Receiver side: an incoming text message is stored in the `messages` table
+/// first and only then acknowledged, so an ack always means the message
+/// survived on the peer's disk. Opening a chat marks its messages read and,
+/// when read receipts are enabled, tells the sender which ones.
+///
+/// Sender side: acks fill in `delivered_at` and complete the outbox entry,
+/// read receipts fill in `read_at` on our outgoing messages.
+use crate::core::outbox;
+use crate::core::protocol::MessageId;
+use crate::core::session::PeerInfo;
+use crate::database;
+use crate::database::error::DatabaseError;
+use crate::database::message_repository::{MessageRepository, NewMessage};
+use tracing::{debug, warn};
+
+fn repository() -> Result<MessageRepository, DatabaseError> {
+    database::get_db()
+        .map(MessageRepository::new)
+        .ok_or_else(|| DatabaseError::Connection("Database not initialized".to_string()))
+}
+
+/// Persists an incoming text message
+///
+/// Returns `false` if the envelope was already stored, which happens when
+/// the sender retries after our earlier ack was lost.
+pub async fn store_incoming(
+    peer: &PeerInfo,
+    envelope_id: &MessageId,
+    sent_at: chrono::DateTime<chrono::Utc>,
+    body: &str,
+) -> Result<bool, DatabaseError> {
+    let repo = repository()?;
+
+    if repo.find_by_envelope(peer.contact_id, envelope_id.as_str()).await?.is_some() {
+        return Ok(false);
+    }
+
+    repo.insert(NewMessage {
+        contact_id: peer.contact_id,
+        is_outgoing: false,
+        body: body.to_string(),
+        sent_at,
+        received_at: Some(chrono::Utc::now()),
+        envelope_id: Some(envelope_id.to_string()),
+    })
+        .await?;
+
+    Ok(true)
+}
+
+/// Applies a delivery ack from `peer` for one of our messages
+pub async fn record_delivery(peer: &PeerInfo, message_id: &MessageId) {
+    outbox::acknowledge(peer.contact_id, message_id).await;
+
+    let result = async { repository()?.mark_delivered(peer.contact_id, message_id.as_str()).await }.await;
+    if let Err(e) = result {
+        warn!("Failed to record delivery of {} to {}: {}", message_id, peer.address, e);
+    }
+}
+
+/// Applies a read receipt from `peer` for some of our messages
+pub async fn record_read(peer: &PeerInfo, message_ids: &[MessageId]) {
+    let ids: Vec<String> = message_ids.iter().map(|id| id.to_string()).collect();
+
+    let result = async { repository()?.mark_read_by_peer(peer.contact_id, &ids).await }.await;
+    match result {
+        Ok(count) => debug!("{} read {} of our messages", peer.address, count),
+        Err(e) => warn!("Failed to record read receipt from {}: {}", peer.address, e),
+    }
+}
+
+/// Marks all unread messages from a contact as read locally
+///
+/// Returns the envelope ids that were newly read, for the read receipt.
+pub async fn mark_chat_read(contact_id: i32) -> Result<Vec<MessageId>, DatabaseError> {
+    let ids = repository()?.mark_incoming_read(contact_id).await?;
+    Ok(ids.into_iter().map(MessageId::from).collect())
+}
@@ -0,0 +1,102 @@
+/// Retry Policy for Syggrel Chat
+///
+/// Exponential backoff shared by every retrying operation in the app:
+/// chat loading in `ChatDataProvider` and peer reconnection in the session
+/// supervisor. The delay for attempt `n` (0-based) is
+/// `min(base_delay * 2^n, max_delay)`, optionally randomized by `jitter` so
+/// many peers dropped at once don't all redial in lockstep.
+use rand::Rng;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub max_attempts: Option<u32>,    // None retries forever
+    pub jitter: f64,    // Fraction of the delay that is randomized (0.0 - 1.0)
+}
+
+impl RetryPolicy {
+    /// Policy used for chat loading: 100ms, 200ms, 400ms, capped at 2 seconds
+    pub fn chat_loading() -> Self {
+        Self {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(2),
+            max_attempts: Some(3),
+            jitter: 0.0,
+        }
+    }
+
+    /// Policy used to redial dropped peers: 1s doubling up to 5 minutes, forever
+    pub fn reconnect() -> Self {
+        Self {
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(300),
+            max_attempts: None,
+            jitter: 0.5,
+        }
+    }
+
+    /// Whether another attempt is allowed after `attempt` failures
+    pub fn allows(&self, attempt: u32) -> bool {
+        self.max_attempts.is_none_or(|max| attempt < max)
+    }
+
+    /// Delay before retrying after failed attempt number `attempt` (0-based)
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        let delay = self.base_delay.saturating_mul(factor).min(self.max_delay);
+
+        if self.jitter <= 0.0 {
+            return delay;
+        }
+
+        // Keep (1 - jitter) of the delay and randomize the rest
+        let jitter = self.jitter.min(1.0);
+        let fixed = delay.mul_f64(1.0 - jitter);
+        let random = delay.mul_f64(rand::thread_rng().gen_range(0.0..=jitter));
+        fixed + random
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attempts_are_capped_only_when_configured() {
+        let loading = RetryPolicy::chat_loading();
+        assert!(loading.allows(0));
+        assert!(loading.allows(2));
+        assert!(!loading.allows(3));
+
+        assert!(RetryPolicy::reconnect().allows(u32::MAX));
+    }
+
+    #[test]
+    fn test_delay_doubles_and_is_clamped() {
+        let policy = RetryPolicy::chat_loading();
+        assert_eq!(policy.delay_for(0), Duration::from_millis(100));
+        assert_eq!(policy.delay_for(2), Duration::from_millis(400));
+        assert_eq!(policy.delay_for(5), Duration::from_secs(2));
+
+        // Shifting past the width of the factor must clamp, not overflow
+        for attempt in [31, 32, 64, u32::MAX] {
+            assert_eq!(policy.delay_for(attempt), policy.max_delay);
+        }
+    }
+
+    #[test]
+    fn test_jitter_stays_within_bounds() {
+        let policy = RetryPolicy::reconnect();
+        let nominal = Duration::from_secs(8);
+
+        for _ in 0..200 {
+            let delay = policy.delay_for(3);
+            assert!(delay >= nominal.mul_f64(1.0 - policy.jitter) && delay <= nominal);
+        }
+        for _ in 0..200 {
+            assert!(policy.delay_for(40) <= policy.max_delay);
+        }
+    }
+}
@@ -1,9 +1,27 @@
+use crate::core::add_contact::AddContact;
+use crate::core::chat_data::ChatId;
+use crate::core::contacts_page::ContactsPage;
+use crate::core::conversation::Conversation;
+use crate::core::notifications::NotificationLayout;
+use crate::core::settings_page::SettingsPage;
+use crate::ui::pages::home::Home;
+use crate::ui::pages::menu::Menu;
+use dioxus::prelude::*;
+
 #[derive(Clone, Routable, Debug, PartialEq)]
+#[rustfmt::skip]
 pub enum Route {
-    #[route("/")]
-    Home {},
-    #[route("/menu")]
-    Menu {},
-    #[route("/settings")]
-    Settings {},
-}
\ No newline at end of file
+    #[layout(NotificationLayout)]
+        #[route("/")]
+        Home {},
+        #[route("/menu")]
+        Menu {},
+        #[route("/settings", SettingsPage)]
+        Settings {},
+        #[route("/contacts", ContactsPage)]
+        Contacts {},
+        #[route("/contacts/new")]
+        AddContact {},
+        #[route("/chat/:id")]
+        Conversation { id: ChatId },
+}
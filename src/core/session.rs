@@ -0,0 +1,211 @@
+/// Peer Session Tasks for Syggrel Chat
+///
+/// A session is a pair of background tasks driving one established stream:
+/// a receive task that decodes frames into the message buffer and the event
+/// bus, and a send task that writes queued envelopes. Both outbound (dialed) and inbound (listener)
+/// connections are handed to `spawn_session`, so they behave identically once
+/// the stream exists. A heartbeat runs alongside them and ends the session
+/// when the peer goes silent (see `core::heartbeat`).
+use crate::core::buffer::{MessageBuffer, ReceivedMessage};
+use crate::core::events::{EventBus, MessengerEvent};
+use crate::core::heartbeat::{Heartbeat, HeartbeatConfig, Tick};
+use crate::core::presence::PresenceTracker;
+use crate::core::protocol::{self, Envelope, FrameKind};
+use crate::core::receipts;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Handle to a running session
+///
+/// The task only ends on its own, with an error describing why, when the peer
+/// went away, stopped answering heartbeats or the stream failed. Sessions
+/// closed locally are aborted instead.
+pub struct SessionHandle {
+    pub task: JoinHandle<tokio::io::Error>,
+    pub tx: mpsc::UnboundedSender<Envelope>,
+    pub rtt: watch::Receiver<Option<Duration>>,    // Latest heartbeat round-trip time
+}
+
+/// Identity of the contact on the other end of a session
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerInfo {
+    pub address: String,    // Contact's stored Yggdrasil address (connection manager key)
+    pub contact_id: i32,
+}
+
+/// Applies a received envelope to local state
+///
+/// `reply` is the session's outgoing queue, used to acknowledge text messages
+/// and answer pings.
+/// A text message is only acknowledged once it has been persisted; if that
+/// fails no ack is sent and the peer's outbox delivers it again later.
+/// Events are published after the change has been stored. Every frame,
+/// heartbeats included, counts as a sign of life for the peer's presence.
+async fn handle_frame(
+    buffer: &MessageBuffer,
+    events: &EventBus,
+    presence: &PresenceTracker,
+    peer: &PeerInfo,
+    reply: &mpsc::UnboundedSender<Envelope>,
+    envelope: Envelope,
+) {
+    match envelope.kind {
+        FrameKind::Presence { status } => presence.record_advertised(peer, status),
+        _ => presence.record_activity(peer),
+    }
+
+    match envelope.kind {
+        FrameKind::Text { body } => {
+            match receipts::store_incoming(peer, &envelope.id, envelope.sent_at, &body).await {
+                Ok(is_new) => {
+                    let _ = reply.send(Envelope::ack(envelope.id.clone()));
+                    // A redelivered message was already handed to consumers
+                    if is_new {
+                        let message = ReceivedMessage {
+                            peer_address: peer.address.clone(),
+                            contact_id: peer.contact_id,
+                            message_id: envelope.id,
+                            sent_at: envelope.sent_at,
+                            body,
+                        };
+                        if let Some(overflow) = buffer.push_message(message.clone()).await {
+                            log::debug!("Buffer for {} full, dropped {} unread", peer.address, overflow.dropped);
+                            events.publish(MessengerEvent::BufferOverflow(overflow));
+                        }
+                        events.publish(MessengerEvent::MessageReceived(message));
+                    }
+                }
+                Err(e) => log::warn!("Not acknowledging {} from {}: {}", envelope.id, peer.address, e),
+            }
+        }
+        FrameKind::Ack { message_id } => {
+            receipts::record_delivery(peer, &message_id).await;
+            events.publish(MessengerEvent::MessageDelivered {
+                peer_address: peer.address.clone(),
+                contact_id: peer.contact_id,
+                message_id,
+            });
+        }
+        FrameKind::Read { message_ids } => {
+            receipts::record_read(peer, &message_ids).await;
+            events.publish(MessengerEvent::MessagesRead {
+                peer_address: peer.address.clone(),
+                contact_id: peer.contact_id,
+                message_ids,
+            });
+        }
+        FrameKind::Presence { .. } => {}    // Recorded above
+        FrameKind::Ping => {
+            let _ = reply.send(Envelope::pong(envelope.id));
+        }
+        FrameKind::Pong { .. } => {}    // Timed by the session's heartbeat
+        other => log::debug!("Ignoring unhandled frame {:?}", other),
+    }
+}
+
+/// Spawns the read/write tasks for an established stream
+pub fn spawn_session<S>(
+    stream: S,
+    peer: PeerInfo,
+    buffer: MessageBuffer,
+    events: EventBus,
+    presence: PresenceTracker,
+    heartbeat: HeartbeatConfig,
+) -> SessionHandle
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    // Create channel for outgoing envelopes to the connection
+    let (tx, mut rx) = mpsc::unbounded_channel::<Envelope>();
+    let reply = tx.clone();
+    let ping_tx = tx.clone();
+    let status_tx = tx.clone();
+    let mut own_status = presence.own_status();
+    let (rtt_tx, rtt) = watch::channel(None);
+
+    let task = tokio::spawn(async move {
+        // Split stream for concurrent read/write
+        let (mut reader, mut writer) = tokio::io::split(stream);
+
+        let liveness = Arc::new(Mutex::new(Heartbeat::new(heartbeat, Instant::now())));
+        let recv_liveness = liveness.clone();
+        let address = peer.address.clone();
+
+        // Receive and send halves run as futures of this task, so aborting the
+        // session handle tears down both directions at once
+        let recv_task = async move {
+            loop {
+                match protocol::read_frame(&mut reader).await {
+                    Ok(None) => {
+                        return io::Error::new(io::ErrorKind::UnexpectedEof, "peer closed the connection");
+                    }
+                    Ok(Some(envelope)) => {
+                        let round_trip = recv_liveness.lock().unwrap().received(&envelope, Instant::now());
+                        if let Some(round_trip) = round_trip {
+                            log::trace!("Round trip to {}: {:?}", peer.address, round_trip);
+                            rtt_tx.send_replace(Some(round_trip));
+                        }
+                        handle_frame(&buffer, &events, &presence, &peer, &reply, envelope).await
+                    }
+                    Err(e) => return io::Error::other(format!("Read error: {}", e)),
+                }
+            }
+        };
+        // Sending frames via network; the queue never closes while the other
+        // halves run, since they hold senders of their own
+        let send_task = async move {
+            while let Some(envelope) = rx.recv().await {
+                // write_frame flushes so every message is sent immediately
+                if let Err(e) = protocol::write_frame(&mut writer, &envelope).await {
+                    return io::Error::other(format!("Write error: {}", e));
+                }
+            }
+            unreachable!("session queue closed while the session was running")
+        };
+        // Pinging the peer and giving up on it once it has gone silent
+        let heartbeat_task = async move {
+            let mut ticker = tokio::time::interval_at(Instant::now() + heartbeat.interval, heartbeat.interval);
+            loop {
+                ticker.tick().await;
+                let tick = liveness.lock().unwrap().tick(Instant::now());
+                match tick {
+                    Tick::Ping(ping) => {
+                        let _ = ping_tx.send(ping);
+                    }
+                    Tick::Stale(silent_for) => {
+                        log::warn!("No frames from {} for {}s, dropping the session", address, silent_for.as_secs());
+                        return io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!("peer stopped responding ({}s without a frame)", silent_for.as_secs()),
+                        );
+                    }
+                }
+            }
+        };
+
+        // Telling the peer when we go idle or come back; the status at the
+        // start is announced by whoever set up the session
+        let advertise_task = async move {
+            while own_status.changed().await.is_ok() {
+                let status = *own_status.borrow_and_update();
+                let _ = status_tx.send(Envelope::new(FrameKind::Presence { status }));
+            }
+            std::future::pending().await    // The tracker outlives its sessions
+        };
+
+        // Whichever ends first decides how the session ended
+        tokio::select! {
+            result = recv_task => result,
+            result = send_task => result,
+            result = heartbeat_task => result,
+            result = advertise_task => result,
+        }
+    });
+
+    SessionHandle { task, tx, rtt }
+}
@@ -0,0 +1,482 @@
+/// Application Settings for Syggrel Chat
+///
+/// `Settings` holds the user's preferences. It is persisted as key/value rows
+/// (see `to_pairs` and `from_pairs`) by `SettingsRepository` and shared with
+/// the UI as a `Signal<Settings>` context, so every component sees changes as
+/// soon as they are saved.
+///
+/// Runtime effects that live outside the UI (the messenger's proxy, buffer,
+/// listener and heartbeat, and the log level) are applied with `apply_runtime`.
+use crate::core::buffer;
+use crate::core::heartbeat::{self, HeartbeatConfig};
+use crate::core::listener::DEFAULT_LISTEN_PORT;
+use crate::core::messenger::YggdrasilMessenger;
+use crate::database::models::ValidationError;
+use crate::database::proxy_url::ProxyConfig;
+use crate::database::{self, settings_repository::SettingsRepository};
+use dioxus::prelude::*;
+use std::fmt;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{info, warn};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt as tracing_fmt, reload, Registry};
+
+/// Smallest allowed buffer size, so a typo can't make the buffer useless
+pub const MIN_BUFFER_BYTES: usize = 64 * 1024;
+
+/// Upper bound on buffered messages
+pub const MAX_BUFFER_MESSAGES: usize = 100_000;
+
+/// Color scheme of the UI
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Theme {
+    #[default]
+    System,    // Follow the OS preference
+    Light,
+    Dark,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 3] = [Theme::System, Theme::Light, Theme::Dark];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Theme::System => "system",
+            Theme::Light => "light",
+            Theme::Dark => "dark",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|theme| theme.as_str() == value)
+    }
+}
+
+/// Verbosity of the application log
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub const ALL: [LogLevel; 5] = [LogLevel::Error, LogLevel::Warn, LogLevel::Info, LogLevel::Debug, LogLevel::Trace];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|level| level.as_str() == value)
+    }
+
+    fn filter(&self) -> LevelFilter {
+        match self {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+/// Errors produced by settings validation and storage
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingsError {
+    InvalidProxy(ValidationError),
+    InvalidListenPort,
+    InvalidBufferLimit(String),
+    InvalidHeartbeat(String),
+    Storage(String),
+    Apply(String),    // Saved, but could not take effect
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::InvalidProxy(err) => write!(f, "{}", err),
+            SettingsError::InvalidListenPort => write!(f, "Listen port must be between 1 and 65535"),
+            SettingsError::InvalidBufferLimit(msg) => write!(f, "{}", msg),
+            SettingsError::InvalidHeartbeat(msg) => write!(f, "{}", msg),
+            SettingsError::Storage(msg) => write!(f, "Failed to save settings: {}", msg),
+            SettingsError::Apply(msg) => write!(f, "Settings saved but not applied: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+/// User preferences
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    pub default_proxy: String,    // Proxy for hidden peers without their own, empty for none
+    pub listen_port: u16,    // Port the inbound listener binds
+    pub buffer_max_messages: usize,    // Received messages kept in memory, per chat
+    pub buffer_max_bytes: usize,    // Memory held by received messages, per chat
+    pub heartbeat_interval_secs: u64,    // Time between pings to each peer
+    pub heartbeat_timeout_secs: u64,    // Silence after which a session is dropped
+    pub theme: Theme,
+    pub notifications_enabled: bool,    // Notify on incoming messages
+    pub notification_sound: bool,    // Play a sound with each notification
+    pub read_receipts: bool,    // Tell contacts when their messages have been read
+    pub log_level: LogLevel,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            default_proxy: String::new(),
+            listen_port: DEFAULT_LISTEN_PORT,
+            buffer_max_messages: buffer::MAX_MESSAGES,
+            buffer_max_bytes: buffer::MAX_TOTAL_BYTES,
+            heartbeat_interval_secs: heartbeat::DEFAULT_INTERVAL.as_secs(),
+            heartbeat_timeout_secs: heartbeat::DEFAULT_TIMEOUT.as_secs(),
+            theme: Theme::default(),
+            notifications_enabled: true,
+            notification_sound: true,
+            read_receipts: true,
+            log_level: LogLevel::default(),
+        }
+    }
+}
+
+// Keys under which each field is stored
+const KEY_DEFAULT_PROXY: &str = "default_proxy";
+const KEY_LISTEN_PORT: &str = "listen_port";
+const KEY_BUFFER_MAX_MESSAGES: &str = "buffer.max_messages";
+const KEY_BUFFER_MAX_BYTES: &str = "buffer.max_bytes";
+const KEY_HEARTBEAT_INTERVAL: &str = "heartbeat.interval_secs";
+const KEY_HEARTBEAT_TIMEOUT: &str = "heartbeat.timeout_secs";
+const KEY_THEME: &str = "theme";
+const KEY_NOTIFICATIONS_ENABLED: &str = "notifications.enabled";
+const KEY_NOTIFICATION_SOUND: &str = "notifications.sound";
+const KEY_READ_RECEIPTS: &str = "privacy.read_receipts";
+const KEY_LOG_LEVEL: &str = "log_level";
+
+impl Settings {
+    /// Checks every field, returning the first problem found
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        if !self.default_proxy.trim().is_empty() {
+            ProxyConfig::parse(&self.default_proxy).map_err(SettingsError::InvalidProxy)?;
+        }
+        if self.listen_port == 0 {
+            return Err(SettingsError::InvalidListenPort);
+        }
+        if !(1..=MAX_BUFFER_MESSAGES).contains(&self.buffer_max_messages) {
+            return Err(SettingsError::InvalidBufferLimit(format!(
+                "Buffer must hold between 1 and {} messages",
+                MAX_BUFFER_MESSAGES
+            )));
+        }
+        if self.buffer_max_bytes < MIN_BUFFER_BYTES {
+            return Err(SettingsError::InvalidBufferLimit(format!(
+                "Buffer size must be at least {} KB",
+                MIN_BUFFER_BYTES / 1024
+            )));
+        }
+        if self.heartbeat_interval_secs < heartbeat::MIN_INTERVAL.as_secs() {
+            return Err(SettingsError::InvalidHeartbeat(format!(
+                "Heartbeat interval must be at least {} seconds",
+                heartbeat::MIN_INTERVAL.as_secs()
+            )));
+        }
+        // One lost pong shouldn't be enough to drop a session
+        if self.heartbeat_timeout_secs < self.heartbeat_interval_secs.saturating_mul(2) {
+            return Err(SettingsError::InvalidHeartbeat(
+                "Heartbeat timeout must be at least twice the interval".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Heartbeat timing for new sessions
+    pub fn heartbeat_config(&self) -> HeartbeatConfig {
+        HeartbeatConfig {
+            interval: Duration::from_secs(self.heartbeat_interval_secs),
+            timeout: Duration::from_secs(self.heartbeat_timeout_secs),
+        }
+    }
+
+    /// The default proxy, if one is set
+    pub fn default_proxy_config(&self) -> Option<ProxyConfig> {
+        match self.default_proxy.trim() {
+            "" => None,
+            proxy => ProxyConfig::parse(proxy).ok(),
+        }
+    }
+
+    /// Key/value form, as stored in the `settings` table
+    pub fn to_pairs(&self) -> Vec<(&'static str, String)> {
+        let default_proxy = self.default_proxy_config().map(|p| p.to_url()).unwrap_or_default();
+
+        vec![
+            (KEY_DEFAULT_PROXY, default_proxy),
+            (KEY_LISTEN_PORT, self.listen_port.to_string()),
+            (KEY_BUFFER_MAX_MESSAGES, self.buffer_max_messages.to_string()),
+            (KEY_BUFFER_MAX_BYTES, self.buffer_max_bytes.to_string()),
+            (KEY_HEARTBEAT_INTERVAL, self.heartbeat_interval_secs.to_string()),
+            (KEY_HEARTBEAT_TIMEOUT, self.heartbeat_timeout_secs.to_string()),
+            (KEY_THEME, self.theme.as_str().to_string()),
+            (KEY_NOTIFICATIONS_ENABLED, self.notifications_enabled.to_string()),
+            (KEY_NOTIFICATION_SOUND, self.notification_sound.to_string()),
+            (KEY_READ_RECEIPTS, self.read_receipts.to_string()),
+            (KEY_LOG_LEVEL, self.log_level.as_str().to_string()),
+        ]
+    }
+
+    /// Builds settings from stored pairs
+    ///
+    /// Missing keys keep their defaults. Unknown keys and unreadable values
+    /// are skipped with a warning rather than failing, so a bad row can't
+    /// keep the application from starting. Values that read fine but fail
+    /// `validate` (e.g. a zero heartbeat interval, which would stop every
+    /// session from starting) reset their group of fields to the defaults.
+    pub fn from_pairs<I>(pairs: I) -> Self
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut settings = Settings::default();
+
+        for (key, value) in pairs {
+            let applied = match key.as_str() {
+                KEY_DEFAULT_PROXY => {
+                    settings.default_proxy = value.clone();
+                    true
+                }
+                KEY_LISTEN_PORT => set(&mut settings.listen_port, value.parse().ok().filter(|p| *p != 0)),
+                KEY_BUFFER_MAX_MESSAGES => set(&mut settings.buffer_max_messages, value.parse().ok()),
+                KEY_BUFFER_MAX_BYTES => set(&mut settings.buffer_max_bytes, value.parse().ok()),
+                KEY_HEARTBEAT_INTERVAL => set(&mut settings.heartbeat_interval_secs, value.parse().ok()),
+                KEY_HEARTBEAT_TIMEOUT => set(&mut settings.heartbeat_timeout_secs, value.parse().ok()),
+                KEY_THEME => set(&mut settings.theme, Theme::parse(&value)),
+                KEY_NOTIFICATIONS_ENABLED => set(&mut settings.notifications_enabled, value.parse().ok()),
+                KEY_NOTIFICATION_SOUND => set(&mut settings.notification_sound, value.parse().ok()),
+                KEY_READ_RECEIPTS => set(&mut settings.read_receipts, value.parse().ok()),
+                KEY_LOG_LEVEL => set(&mut settings.log_level, LogLevel::parse(&value)),
+                _ => {
+                    warn!("Ignoring unknown setting '{}'", key);
+                    continue;
+                }
+            };
+
+            if !applied {
+                warn!("Ignoring invalid value '{}' for setting '{}'", value, key);
+            }
+        }
+
+        // Defaults always validate, so this ends once every bad group is reset
+        let defaults = Settings::default();
+        while let Err(e) = settings.validate() {
+            warn!("Ignoring stored settings: {}", e);
+            match e {
+                SettingsError::InvalidProxy(_) => settings.default_proxy = defaults.default_proxy.clone(),
+                SettingsError::InvalidListenPort => settings.listen_port = defaults.listen_port,
+                SettingsError::InvalidBufferLimit(_) => {
+                    settings.buffer_max_messages = defaults.buffer_max_messages;
+                    settings.buffer_max_bytes = defaults.buffer_max_bytes;
+                }
+                SettingsError::InvalidHeartbeat(_) => {
+                    settings.heartbeat_interval_secs = defaults.heartbeat_interval_secs;
+                    settings.heartbeat_timeout_secs = defaults.heartbeat_timeout_secs;
+                }
+                SettingsError::Storage(_) | SettingsError::Apply(_) => break,    // Not produced by validate
+            }
+        }
+
+        settings
+    }
+
+    /// Applies the settings that take effect outside the UI
+    ///
+    /// The default proxy is used from the next dial on, heartbeat timing from
+    /// the next session on, buffer limits and read receipts apply immediately,
+    /// and the listener is rebound if its port changed.
+    pub async fn apply_runtime(&self, messenger: Option<&YggdrasilMessenger>) -> Result<(), SettingsError> {
+        set_log_level(self.log_level);
+
+        if let Some(messenger) = messenger {
+            messenger.set_default_proxy(self.default_proxy_config());
+            messenger.set_heartbeat(self.heartbeat_config());
+            messenger.set_read_receipts(self.read_receipts);
+            messenger.set_buffer_limits(self.buffer_max_messages, self.buffer_max_bytes).await;
+            messenger
+                .set_listen_port(self.listen_port)
+                .await
+                .map_err(|e| SettingsError::Apply(e.to_string()))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn set<T>(field: &mut T, value: Option<T>) -> bool {
+    match value {
+        Some(value) => {
+            *field = value;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Loads the stored settings, falling back to defaults
+pub async fn load() -> Settings {
+    let Some(db) = database::get_db() else {
+        warn!("Database not initialized; using default settings");
+        return Settings::default();
+    };
+
+    match SettingsRepository::new(db).load().await {
+        Ok(pairs) => Settings::from_pairs(pairs),
+        Err(e) => {
+            warn!("{}; using default settings", e);
+            Settings::default()
+        }
+    }
+}
+
+/// Validates and stores settings
+pub async fn save(settings: &Settings) -> Result<(), SettingsError> {
+    settings.validate()?;
+
+    let db = database::get_db().ok_or_else(|| SettingsError::Storage("Database not initialized".to_string()))?;
+
+    SettingsRepository::new(db)
+        .save(settings.to_pairs())
+        .await
+        .map_err(|e| SettingsError::Storage(e.to_string()))
+}
+
+static LOG_LEVEL: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+
+/// Installs the global logger at `level`; later changes go through `set_log_level`
+pub fn init_logging(level: LogLevel) {
+    let (filter, handle) = reload::Layer::new(level.filter());
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_fmt::layer())
+        .init();
+
+    let _ = LOG_LEVEL.set(handle);
+}
+
+/// Changes the log level of the running application
+pub fn set_log_level(level: LogLevel) {
+    if let Some(handle) = LOG_LEVEL.get() {
+        match handle.modify(|filter| *filter = level.filter()) {
+            Ok(()) => info!("Log level set to {}", level.as_str()),
+            Err(e) => warn!("Failed to change log level: {}", e),
+        }
+    }
+}
+
+/// Provides the settings context to the component tree
+///
+/// Starts with defaults and swaps in the stored settings once loaded.
+/// Call once, from the root component.
+pub fn use_settings_provider() -> Signal<Settings> {
+    let mut settings = use_context_provider(|| Signal::new(Settings::default()));
+
+    use_hook(|| {
+        spawn(async move {
+            let stored = load().await;
+            set_log_level(stored.log_level);
+            settings.set(stored);
+        });
+    });
+
+    settings
+}
+
+/// The current settings, updated live when they are saved
+pub fn use_settings() -> Signal<Settings> {
+    use_context::<Signal<Settings>>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pairs_round_trip() {
+        let settings = Settings {
+            default_proxy: "socks5://127.0.0.1:9050".to_string(),
+            listen_port: 7400,
+            buffer_max_messages: 50,
+            buffer_max_bytes: 1024 * 1024,
+            heartbeat_interval_secs: 10,
+            heartbeat_timeout_secs: 45,
+            theme: Theme::Dark,
+            notifications_enabled: false,
+            notification_sound: false,
+            read_receipts: false,
+            log_level: LogLevel::Debug,
+        };
+
+        let pairs = settings.to_pairs().into_iter().map(|(k, v)| (k.to_string(), v));
+        assert_eq!(Settings::from_pairs(pairs), settings);
+    }
+
+    #[test]
+    fn test_bad_values_fall_back_to_defaults() {
+        let pairs = vec![
+            ("listen_port".to_string(), "0".to_string()),
+            ("theme".to_string(), "neon".to_string()),
+            ("no_such_key".to_string(), "1".to_string()),
+            ("notifications.sound".to_string(), "maybe".to_string()),
+            ("log_level".to_string(), "debug".to_string()),
+        ];
+
+        let settings = Settings::from_pairs(pairs);
+        assert_eq!(settings.listen_port, DEFAULT_LISTEN_PORT);
+        assert_eq!(settings.theme, Theme::System);
+        assert!(settings.notification_sound);
+        assert_eq!(settings.log_level, LogLevel::Debug);
+    }
+
+    #[test]
+    fn test_stored_values_that_fail_validation_fall_back_to_defaults() {
+        let pairs = |entries: &[(&str, &str)]| {
+            entries.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<Vec<_>>()
+        };
+
+        let zero = Settings::from_pairs(pairs(&[("heartbeat.interval_secs", "0"), ("buffer.max_messages", "50")]));
+        assert_eq!(zero.heartbeat_config(), Settings::default().heartbeat_config());
+        assert_eq!(zero.buffer_max_messages, 50);
+
+        let short = Settings::from_pairs(pairs(&[("heartbeat.interval_secs", "20"), ("heartbeat.timeout_secs", "10")]));
+        assert_eq!(short.heartbeat_config(), Settings::default().heartbeat_config());
+
+        let buffer = Settings::from_pairs(pairs(&[("buffer.max_bytes", "1"), ("heartbeat.timeout_secs", "120")]));
+        assert_eq!(buffer.buffer_max_bytes, Settings::default().buffer_max_bytes);
+        assert_eq!(buffer.heartbeat_timeout_secs, 120);
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_fields() {
+        let proxy = Settings { default_proxy: "http://127.0.0.1:8080".to_string(), ..Settings::default() };
+        let port = Settings { listen_port: 0, ..Settings::default() };
+        let buffer = Settings { buffer_max_messages: 0, ..Settings::default() };
+        let heartbeat = Settings { heartbeat_interval_secs: 30, heartbeat_timeout_secs: 40, ..Settings::default() };
+
+        assert!(Settings::default().validate().is_ok());
+        assert!(matches!(proxy.validate(), Err(SettingsError::InvalidProxy(_))));
+        assert_eq!(port.validate(), Err(SettingsError::InvalidListenPort));
+        assert!(matches!(buffer.validate(), Err(SettingsError::InvalidBufferLimit(_))));
+        assert!(matches!(heartbeat.validate(), Err(SettingsError::InvalidHeartbeat(_))));
+    }
+}
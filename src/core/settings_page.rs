@@ -0,0 +1,401 @@
+use crate::core::identity_settings::IdentitySettings;
+use crate::core::messenger::YggdrasilMessenger;
+use crate::core::routes::Route;
+use crate::core::settings::{self, LogLevel, Settings, SettingsError, Theme};
+use crate::database::proxy_url;
+use dioxus::prelude::*;
+use std::sync::Arc;
+
+const BYTES_PER_MB: usize = 1024 * 1024;
+
+/// Settings Page
+///
+/// Edits a draft copy of the settings from the `Signal<Settings>` context.
+/// Saving validates the draft, stores it, and only then publishes it to the
+/// context, so the rest of the UI (e.g. the theme) updates at once. Settings
+/// that live outside the UI are applied to the messenger, when one is
+/// provided, through `Settings::apply_runtime`. The default proxy is shown
+/// masked like on the contacts page; a password left masked keeps the stored
+/// one. The profile, passphrase and identity backup have their own forms
+/// below (see `IdentitySettings`).
+#[component]
+pub fn SettingsPage() -> Element {
+    let mut settings = settings::use_settings();
+    let messenger = try_use_context::<Arc<YggdrasilMessenger>>();
+
+    let mut draft = use_signal(|| settings.peek().clone());
+    let mut default_proxy = use_signal(String::new);    // Proxy field text, starts out masked
+    // Numbers are edited as text so a half-typed value doesn't get rejected
+    let mut listen_port = use_signal(String::new);
+    let mut buffer_messages = use_signal(String::new);
+    let mut buffer_size_mb = use_signal(String::new);
+    let mut heartbeat_interval = use_signal(String::new);
+    let mut heartbeat_timeout = use_signal(String::new);
+
+    let mut field_error = use_signal(|| None::<SettingsError>);
+    let mut status = use_signal(|| None::<String>);
+    let mut saving = use_signal(|| false);
+
+    // Reset the form whenever the stored settings change (initial load, save)
+    use_effect(move || {
+        let current = settings();
+        default_proxy.set(current.default_proxy_config().map(|p| p.to_string()).unwrap_or_default());
+        listen_port.set(current.listen_port.to_string());
+        buffer_messages.set(current.buffer_max_messages.to_string());
+        buffer_size_mb.set((current.buffer_max_bytes / BYTES_PER_MB).max(1).to_string());
+        heartbeat_interval.set(current.heartbeat_interval_secs.to_string());
+        heartbeat_timeout.set(current.heartbeat_timeout_secs.to_string());
+        draft.set(current);
+    });
+
+    let on_submit = move |evt: FormEvent| {
+        evt.prevent_default();
+        if *saving.read() {
+            return;
+        }
+
+        let updated = match parse_form(
+            &draft.read(),
+            &default_proxy.read(),
+            &listen_port.read(),
+            &buffer_messages.read(),
+            &buffer_size_mb.read(),
+            &heartbeat_interval.read(),
+            &heartbeat_timeout.read(),
+        ) {
+            Ok(updated) => updated,
+            Err(e) => {
+                field_error.set(Some(e));
+                return;
+            }
+        };
+        if let Err(e) = updated.validate() {
+            field_error.set(Some(e));
+            return;
+        }
+
+        field_error.set(None);
+        status.set(None);
+        saving.set(true);
+
+        let messenger = messenger.clone();
+        spawn(async move {
+            match settings::save(&updated).await {
+                Ok(()) => {
+                    let applied = updated.apply_runtime(messenger.as_deref()).await;
+                    settings.set(updated);
+                    status.set(Some(match applied {
+                        Ok(()) => "Settings saved".to_string(),
+                        Err(e) => e.to_string(),
+                    }));
+                }
+                Err(e) => status.set(Some(e.to_string())),
+            }
+            saving.set(false);
+        });
+    };
+
+    let error_for = move |field: Field| -> Option<String> {
+        field_error
+            .read()
+            .as_ref()
+            .filter(|e| Field::of(e) == Some(field))
+            .map(|e| e.to_string())
+    };
+
+    rsx! {
+        div {
+            class: "settings-container",
+
+            header {
+                class: "top-bar",
+                Link {
+                    to: Route::Home {},
+                    class: "nav-button back-button",
+                    aria_label: "Back to chats",
+                    "←"
+                }
+                h1 { "Settings" }
+            }
+
+            form {
+                class: "settings-form",
+                onsubmit: on_submit,
+
+                fieldset {
+                    legend { "Network" }
+
+                    div {
+                        class: "form-field",
+                        label { r#for: "default-proxy", "Default SOCKS5 proxy (for hidden peers)" }
+                        input {
+                            id: "default-proxy",
+                            r#type: "text",
+                            placeholder: "socks5://127.0.0.1:1080",
+                            value: "{default_proxy}",
+                            aria_invalid: error_for(Field::Proxy).is_some(),
+                            oninput: move |e| default_proxy.set(e.value()),
+                        }
+                        if let Some(message) = error_for(Field::Proxy) {
+                            p { class: "field-error", role: "alert", "{message}" }
+                        }
+                    }
+
+                    div {
+                        class: "form-field",
+                        label { r#for: "listen-port", "Listen port" }
+                        input {
+                            id: "listen-port",
+                            r#type: "number",
+                            min: "1",
+                            max: "65535",
+                            value: "{listen_port}",
+                            aria_invalid: error_for(Field::ListenPort).is_some(),
+                            oninput: move |e| listen_port.set(e.value()),
+                        }
+                        if let Some(message) = error_for(Field::ListenPort) {
+                            p { class: "field-error", role: "alert", "{message}" }
+                        }
+                    }
+
+                    div {
+                        class: "form-field",
+                        label { r#for: "heartbeat-interval", "Ping peers every (seconds)" }
+                        input {
+                            id: "heartbeat-interval",
+                            r#type: "number",
+                            min: "5",
+                            value: "{heartbeat_interval}",
+                            aria_invalid: error_for(Field::Heartbeat).is_some(),
+                            oninput: move |e| heartbeat_interval.set(e.value()),
+                        }
+                    }
+
+                    div {
+                        class: "form-field",
+                        label { r#for: "heartbeat-timeout", "Drop silent peers after (seconds)" }
+                        input {
+                            id: "heartbeat-timeout",
+                            r#type: "number",
+                            min: "10",
+                            value: "{heartbeat_timeout}",
+                            aria_invalid: error_for(Field::Heartbeat).is_some(),
+                            oninput: move |e| heartbeat_timeout.set(e.value()),
+                        }
+                    }
+
+                    if let Some(message) = error_for(Field::Heartbeat) {
+                        p { class: "field-error", role: "alert", "{message}" }
+                    }
+                }
+
+                fieldset {
+                    legend { "Message buffer" }
+
+                    div {
+                        class: "form-field",
+                        label { r#for: "buffer-messages", "Messages kept in memory per chat" }
+                        input {
+                            id: "buffer-messages",
+                            r#type: "number",
+                            min: "1",
+                            value: "{buffer_messages}",
+                            aria_invalid: error_for(Field::Buffer).is_some(),
+                            oninput: move |e| buffer_messages.set(e.value()),
+                        }
+                    }
+
+                    div {
+                        class: "form-field",
+                        label { r#for: "buffer-size", "Memory limit per chat (MB)" }
+                        input {
+                            id: "buffer-size",
+                            r#type: "number",
+                            min: "1",
+                            value: "{buffer_size_mb}",
+                            aria_invalid: error_for(Field::Buffer).is_some(),
+                            oninput: move |e| buffer_size_mb.set(e.value()),
+                        }
+                    }
+
+                    if let Some(message) = error_for(Field::Buffer) {
+                        p { class: "field-error", role: "alert", "{message}" }
+                    }
+                }
+
+                fieldset {
+                    legend { "Appearance" }
+
+                    div {
+                        class: "form-field",
+                        label { r#for: "theme", "Theme" }
+                        select {
+                            id: "theme",
+                            onchange: move |e| {
+                                if let Some(theme) = Theme::parse(&e.value()) {
+                                    draft.write().theme = theme;
+                                }
+                            },
+                            for theme in Theme::ALL {
+                                option {
+                                    value: theme.as_str(),
+                                    selected: draft.read().theme == theme,
+                                    {theme_label(theme)}
+                                }
+                            }
+                        }
+                    }
+                }
+
+                fieldset {
+                    legend { "Notifications" }
+
+                    div {
+                        class: "form-field checkbox-field",
+                        input {
+                            id: "notifications-enabled",
+                            r#type: "checkbox",
+                            checked: draft.read().notifications_enabled,
+                            onchange: move |e| draft.write().notifications_enabled = e.checked(),
+                        }
+                        label { r#for: "notifications-enabled", "Notify on new messages" }
+                    }
+
+                    div {
+                        class: "form-field checkbox-field",
+                        input {
+                            id: "notification-sound",
+                            r#type: "checkbox",
+                            checked: draft.read().notification_sound,
+                            disabled: !draft.read().notifications_enabled,
+                            onchange: move |e| draft.write().notification_sound = e.checked(),
+                        }
+                        label { r#for: "notification-sound", "Play a sound" }
+                    }
+                }
+
+                fieldset {
+                    legend { "Privacy" }
+
+                    div {
+                        class: "form-field checkbox-field",
+                        input {
+                            id: "read-receipts",
+                            r#type: "checkbox",
+                            checked: draft.read().read_receipts,
+                            onchange: move |e| draft.write().read_receipts = e.checked(),
+                        }
+                        label { r#for: "read-receipts", "Send read receipts" }
+                    }
+                }
+
+                fieldset {
+                    legend { "Diagnostics" }
+
+                    div {
+                        class: "form-field",
+                        label { r#for: "log-level", "Log level" }
+                        select {
+                            id: "log-level",
+                            onchange: move |e| {
+                                if let Some(level) = LogLevel::parse(&e.value()) {
+                                    draft.write().log_level = level;
+                                }
+                            },
+                            for level in LogLevel::ALL {
+                                option {
+                                    value: level.as_str(),
+                                    selected: draft.read().log_level == level,
+                                    "{level.as_str()}"
+                                }
+                            }
+                        }
+                    }
+                }
+
+                if let Some(message) = status.read().as_ref() {
+                    p { class: "form-status", role: "status", "{message}" }
+                }
+
+                button {
+                    class: "primary-button",
+                    r#type: "submit",
+                    disabled: *saving.read(),
+                    if *saving.read() { "Saving..." } else { "Save Settings" }
+                }
+            }
+
+            IdentitySettings {}
+        }
+    }
+}
+
+/// Form field an error is displayed under
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Proxy,
+    ListenPort,
+    Buffer,
+    Heartbeat,
+}
+
+impl Field {
+    fn of(error: &SettingsError) -> Option<Self> {
+        match error {
+            SettingsError::InvalidProxy(_) => Some(Field::Proxy),
+            SettingsError::InvalidListenPort => Some(Field::ListenPort),
+            SettingsError::InvalidBufferLimit(_) => Some(Field::Buffer),
+            SettingsError::InvalidHeartbeat(_) => Some(Field::Heartbeat),
+            _ => None,
+        }
+    }
+}
+
+/// Combines the draft with the proxy and numeric text fields
+fn parse_form(
+    draft: &Settings,
+    default_proxy: &str,
+    listen_port: &str,
+    buffer_messages: &str,
+    buffer_size_mb: &str,
+    heartbeat_interval: &str,
+    heartbeat_timeout: &str,
+) -> Result<Settings, SettingsError> {
+    let listen_port = listen_port.trim().parse().map_err(|_| SettingsError::InvalidListenPort)?;
+    let buffer_max_messages = buffer_messages
+        .trim()
+        .parse()
+        .map_err(|_| SettingsError::InvalidBufferLimit("Message count must be a whole number".to_string()))?;
+    let buffer_max_bytes = buffer_size_mb
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|mb| mb.checked_mul(BYTES_PER_MB))
+        .ok_or_else(|| SettingsError::InvalidBufferLimit("Memory limit must be a whole number of MB".to_string()))?;
+    let heartbeat_interval_secs = heartbeat_interval
+        .trim()
+        .parse()
+        .map_err(|_| SettingsError::InvalidHeartbeat("Ping interval must be a whole number of seconds".to_string()))?;
+    let heartbeat_timeout_secs = heartbeat_timeout
+        .trim()
+        .parse()
+        .map_err(|_| SettingsError::InvalidHeartbeat("Timeout must be a whole number of seconds".to_string()))?;
+
+    Ok(Settings {
+        default_proxy: proxy_url::unmask(default_proxy, &draft.default_proxy),
+        listen_port,
+        buffer_max_messages,
+        buffer_max_bytes,
+        heartbeat_interval_secs,
+        heartbeat_timeout_secs,
+        ..draft.clone()
+    })
+}
+
+fn theme_label(theme: Theme) -> &'static str {
+    match theme {
+        Theme::System => "System",
+        Theme::Light => "Light",
+        Theme::Dark => "Dark",
+    }
+}
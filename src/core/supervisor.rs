@@ -0,0 +1,427 @@
+/// Session Supervision for Syggrel Chat
+///
+/// Watches peer sessions for the moment they end and publishes it as a
+/// `SessionEvent` on the event bus. Outbound sessions are additionally redialed with jittered
+/// exponential backoff (`RetryPolicy::reconnect()`) until they come back or
+/// the policy gives up. A session that was ended locally (disconnect, or
+/// replaced by a newer session to the same peer) is never redialed, and
+/// redialing stops as soon as the peer has dialed us instead.
+use crate::core::buffer::MessageBuffer;
+use crate::core::connection_manager::{ConnectionManager, Direction, PeerState};
+use crate::core::events::{EventBus, MessengerEvent};
+use crate::core::heartbeat::HeartbeatConfig;
+use crate::core::identity::{self, Identity};
+use crate::core::listener::DEFAULT_LISTEN_PORT;
+use crate::core::noise;
+use crate::core::presence::PresenceTracker;
+use crate::core::outbox;
+use crate::core::proxy::{self, DialRoute};
+use crate::core::protocol::{Envelope, FrameKind};
+use crate::core::retry::RetryPolicy;
+use crate::core::session::{self, PeerInfo};
+use crate::database::address::YggdrasilAddress;
+use crate::database::models::Contact;
+use crate::database::proxy_url::ProxyConfig;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// How long a connect, direct or through a SOCKS5 proxy, may take before the dial fails
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Status change of a peer session
+#[derive(Clone, Debug, PartialEq)]
+pub enum SessionEvent {
+    Connected { address: String },
+    Disconnected { address: String, reason: String },
+    ReconnectScheduled { address: String, attempt: u32, delay: Duration },
+    GaveUp { address: String, attempts: u32 },
+}
+
+/// Everything a session needs from the messenger, cheap to clone into tasks
+#[derive(Clone)]
+pub struct SessionContext {
+    pub buffer: MessageBuffer,
+    pub connections: ConnectionManager,
+    pub events: EventBus,
+    pub presence: PresenceTracker,
+    pub identity: Arc<RwLock<Identity>>,    // Profile may change while running; keys never do
+    pub default_proxy: Arc<RwLock<Option<ProxyConfig>>>,    // Read at every dial, so changes apply on reconnect
+    pub heartbeat: Arc<RwLock<HeartbeatConfig>>,    // Read at every session start
+}
+
+impl SessionContext {
+    /// Creates the shared state for sessions authenticated with `identity`
+    pub fn new(identity: Identity) -> Self {
+        let events = EventBus::new();
+        Self {
+            buffer: MessageBuffer::new(),
+            connections: ConnectionManager::new(),
+            presence: PresenceTracker::new(events.clone()),
+            events,
+            identity: Arc::new(RwLock::new(identity)),
+            default_proxy: Arc::new(RwLock::new(None)),
+            heartbeat: Arc::new(RwLock::new(HeartbeatConfig::default())),
+        }
+    }
+
+    pub fn emit(&self, event: SessionEvent) {
+        self.events.publish(MessengerEvent::Session(event));
+    }
+}
+
+/// Dials a contact and registers the session
+///
+/// The contact is reached through its own proxy, the default proxy or
+/// directly over TUN, as decided by `proxy::dial_route`. The session is
+/// only registered once the Noise handshake succeeded and the peer's key
+/// matches the one pinned on the contact (or was just pinned). Returns the
+/// session task for supervision.
+///
+/// Fails without registering anything if the peer dialed us at the same
+/// time and its session is the one both sides keep.
+pub async fn dial(
+    ctx: &SessionContext,
+    contact: &Contact,
+) -> Result<JoinHandle<tokio::io::Error>, String> {
+    let contact_id = contact.id
+        .ok_or_else(|| format!("Contact '{}' has not been saved yet", contact.display_name))?;
+
+    let route = proxy::dial_route(contact, ctx.default_proxy.read().unwrap().as_ref())?;
+    let target_addr = target_socket_addr(&contact.yggdrasil_address)
+        .ok_or_else(|| format!("Invalid target address '{}'", contact.yggdrasil_address))?;
+
+    ctx.connections.mark_connecting(&contact.yggdrasil_address, contact_id);
+
+    let secured = async {
+        match route {
+            DialRoute::Direct => {
+                let stream = timeout(CONNECT_TIMEOUT, TcpStream::connect(target_addr))
+                    .await
+                    .map_err(|_| format!("Direct connection to {} timed out", target_addr))?
+                    .map_err(|e| format!("Direct connection to {} failed: {}", target_addr, e))?;
+                secure(ctx, stream, contact_id).await
+            }
+            DialRoute::Proxy(proxy) => {
+                let stream = timeout(CONNECT_TIMEOUT, proxy.connect(target_addr))
+                    .await
+                    .map_err(|_| format!("SOCKS5 connection via {} timed out", proxy))?
+                    .map_err(|e| format!("SOCKS5 connection via {} failed: {}", proxy, e))?;
+                secure(ctx, stream, contact_id).await
+            }
+        }
+    }
+        .await;
+
+    let (stream, we_dial_first) = match secured {
+        Ok(secured) => secured,
+        Err(e) => {
+            ctx.connections.mark_disconnected(&contact.yggdrasil_address);
+            return Err(e);
+        }
+    };
+
+    let peer = PeerInfo {
+        address: contact.yggdrasil_address.clone(),
+        contact_id,
+    };
+    let handle = session::spawn_session(
+        stream,
+        peer.clone(),
+        ctx.buffer.clone(),
+        ctx.events.clone(),
+        ctx.presence.clone(),
+        *ctx.heartbeat.read().unwrap(),
+    );
+
+    let _ = handle.tx.send(hello(ctx));
+
+    let Some(task) = ctx.connections.install(
+        &contact.yggdrasil_address,
+        contact_id,
+        handle,
+        Direction::Outbound,
+        we_dial_first,
+    ) else {
+        return Err(format!("{} dialed us at the same time, keeping its session", contact.yggdrasil_address));
+    };
+    ctx.emit(SessionEvent::Connected { address: contact.yggdrasil_address.clone() });
+    ctx.presence.record_activity(&peer);
+
+    // Deliver anything queued while the peer was offline
+    outbox::flush_pending(&ctx.connections, &peer).await;
+    tokio::spawn(outbox::resend_unacked(ctx.connections.clone(), peer.clone(), task.abort_handle()));
+
+    Ok(task)
+}
+
+/// Presence frame opening every session: our status and, if known, the
+/// address we are reachable at
+pub fn hello(ctx: &SessionContext) -> Envelope {
+    let status = *ctx.presence.own_status().borrow();
+    let hello = Envelope::new(FrameKind::Presence { status });
+    match ctx.identity.read().unwrap().yggdrasil_address.clone() {
+        Some(address) => hello.with_sender(address),
+        None => hello,
+    }
+}
+
+/// Runs the Noise handshake and checks the peer's key against the contact
+///
+/// Also returns whether our static key is the lower one, which decides
+/// between crossed dials.
+async fn secure<S>(ctx: &SessionContext, stream: S, contact_id: i32) -> Result<(DuplexStream, bool), String>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let identity = ctx.identity.read().unwrap().clone();
+    let channel = noise::initiate(stream, &identity).await.map_err(|e| e.to_string())?;
+    identity::verify_peer_key(contact_id, channel.remote_public_key()).await?;
+    let we_dial_first = identity.public_key() < channel.remote_public_key();
+    Ok((channel.into_stream(), we_dial_first))
+}
+
+/// Waits for a session to end and reports it
+///
+/// Returns the reason if the peer side dropped, or `None` if the session was
+/// ended locally or already replaced by a newer one and must not be redialed.
+pub async fn watch_session(
+    ctx: &SessionContext,
+    address: &str,
+    task: JoinHandle<tokio::io::Error>,
+) -> Option<String> {
+    let reason = match task.await {
+        Ok(e) => e.to_string(),
+        Err(e) if e.is_cancelled() => return None,    // Replaced or disconnected; state already updated
+        Err(e) => format!("Session task failed: {}", e),
+    };
+
+    if !ctx.connections.mark_disconnected(address) {
+        debug!("Session with {} ended after being replaced: {}", address, reason);
+        return None;
+    }
+    report_disconnected(ctx, address, &reason);
+
+    Some(reason)
+}
+
+/// Reason given for sessions ended on this side
+pub const CLOSED_LOCALLY: &str = "closed locally";
+
+/// Publishes the end of a session and marks the peer offline
+///
+/// Sessions torn down by the messenger are aborted, so `watch_session` never
+/// sees them end and the messenger reports them through this instead.
+pub fn report_disconnected(ctx: &SessionContext, address: &str, reason: &str) {
+    ctx.emit(SessionEvent::Disconnected { address: address.to_string(), reason: reason.to_string() });
+    ctx.presence.record_disconnect(address);
+}
+
+/// Keeps an outbound session to `contact` alive until it is ended locally
+pub async fn supervise(
+    ctx: SessionContext,
+    contact: Contact,
+    policy: RetryPolicy,
+    mut task: JoinHandle<tokio::io::Error>,
+) {
+    let address = contact.yggdrasil_address.clone();
+
+    loop {
+        let Some(reason) = watch_session(&ctx, &address, task).await else {
+            return;
+        };
+        info!("Session with {} dropped: {}", address, reason);
+
+        task = match dial_with_backoff(&ctx, &contact, &policy, true).await {
+            Some(task) => task,
+            None => return,
+        };
+    }
+}
+
+/// Dials `contact` until a session is up, then supervises it
+///
+/// Failed dials are retried like a dropped session. Used for contacts that
+/// should be reachable but aren't connected yet, e.g. at startup.
+pub async fn dial_and_supervise(ctx: SessionContext, contact: Contact, policy: RetryPolicy) {
+    if let Some(task) = dial_with_backoff(&ctx, &contact, &policy, false).await {
+        supervise(ctx, contact, policy, task).await;
+    }
+}
+
+/// Dials `contact` until it succeeds, backing off between attempts
+///
+/// With `wait_first` the backoff delay also comes before the first attempt.
+/// Returns `None` once the policy gives up, or as soon as a session with
+/// the contact is up without us, which the peer keeps alive from its side.
+async fn dial_with_backoff(
+    ctx: &SessionContext,
+    contact: &Contact,
+    policy: &RetryPolicy,
+    wait_first: bool,
+) -> Option<JoinHandle<tokio::io::Error>> {
+    let address = &contact.yggdrasil_address;
+    let mut attempt = 0;
+    let mut wait = wait_first;
+
+    loop {
+        if wait {
+            if !policy.allows(attempt) {
+                warn!("Giving up on {} after {} reconnect attempts", address, attempt);
+                ctx.emit(SessionEvent::GaveUp { address: address.clone(), attempts: attempt });
+                return None;
+            }
+
+            let delay = policy.delay_for(attempt);
+            ctx.emit(SessionEvent::ReconnectScheduled {
+                address: address.clone(),
+                attempt: attempt + 1,
+                delay,
+            });
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+        wait = true;
+
+        if ctx.connections.state(address) == PeerState::Connected {
+            debug!("{} connected to us meanwhile, not dialing", address);
+            return None;
+        }
+
+        match dial(ctx, contact).await {
+            Ok(task) => return Some(task),
+            Err(e) => warn!("Dialing {} failed: {}", address, e),
+        }
+    }
+}
+
+/// Resolves a stored contact address to a dialable socket address
+///
+/// Accepts either `[addr]:port` or a bare address, which is dialed on the
+/// default listen port.
+fn target_socket_addr(address: &str) -> Option<SocketAddr> {
+    YggdrasilAddress::parse(address)
+        .ok()
+        .map(|addr| addr.socket_addr(DEFAULT_LISTEN_PORT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::listener;
+    use crate::core::testing;
+    use tokio::net::TcpListener;
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy {
+            base_delay: Duration::from_millis(50),
+            max_delay: Duration::from_millis(200),
+            max_attempts: Some(50),
+            jitter: 0.0,
+        }
+    }
+
+    fn session_event(event: MessengerEvent) -> Option<SessionEvent> {
+        match event {
+            MessengerEvent::Session(event) => Some(event),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dial_runs_the_handshake_with_the_accept_loop() {
+        testing::init_db().await;
+        let alice_identity = Identity::generate("Alice").unwrap();
+        let bob_identity = Identity::generate("Bob").unwrap();
+        let alice = SessionContext::new(alice_identity.clone());
+        let bob = SessionContext::new(bob_identity.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = testing::spawn_forwarder(listener.local_addr().unwrap()).await;
+        tokio::spawn(listener::accept_loop(listener, bob.clone()));
+        testing::save_contact("200:4::a", proxy, &alice_identity).await;
+        let mut bob_events = bob.events.subscribe();
+
+        // Whoever answers through the proxy must prove the key pinned on the contact
+        let impostor = testing::save_contact("200:4::c", proxy, &Identity::generate("Mallory").unwrap()).await;
+        let refused = dial(&alice, &impostor).await.unwrap_err();
+        assert!(refused.contains("does not match the pinned key"), "{}", refused);
+        assert_eq!(alice.connections.state("200:4::c"), PeerState::Disconnected);
+
+        let bob_contact = testing::save_contact("200:4::b", proxy, &bob_identity).await;
+        dial(&alice, &bob_contact).await.unwrap();
+        alice.connections.send("200:4::b", Envelope::text("over noise")).unwrap();
+
+        let received = testing::wait_for(&mut bob_events, |event| match event {
+            MessengerEvent::MessageReceived(message) => Some(message),
+            _ => None,
+        })
+            .await;
+        assert_eq!((received.peer_address.as_str(), received.body.as_str()), ("200:4::a", "over noise"));
+        alice.connections.disconnect_all();
+        bob.connections.disconnect_all();
+    }
+
+    #[tokio::test]
+    async fn test_supervise_redials_a_listener_that_drops_and_comes_back() {
+        testing::init_db().await;
+        let alice_identity = Identity::generate("Alice").unwrap();
+        let bob_identity = Identity::generate("Bob").unwrap();
+        let alice = SessionContext::new(alice_identity.clone());
+        let bob = SessionContext::new(bob_identity.clone());
+
+        let bob_addr = SocketAddr::from(([127, 0, 0, 1], testing::free_port()));
+        let proxy = testing::spawn_forwarder(bob_addr).await;
+        let bob_contact = testing::save_contact("200:6::b", proxy, &bob_identity).await;
+        testing::save_contact("200:6::a", proxy, &alice_identity).await;
+
+        let listening = tokio::spawn(listener::accept_loop(TcpListener::bind(bob_addr).await.unwrap(), bob.clone()));
+        let mut events = alice.events.subscribe();
+        let mut bob_events = bob.events.subscribe();
+
+        let task = dial(&alice, &bob_contact).await.unwrap();
+        tokio::spawn(supervise(alice.clone(), bob_contact.clone(), fast_policy(), task));
+        assert_eq!(
+            testing::wait_for(&mut events, session_event).await,
+            SessionEvent::Connected { address: "200:6::b".to_string() }
+        );
+        assert_eq!(
+            testing::wait_for(&mut bob_events, session_event).await,
+            SessionEvent::Connected { address: "200:6::a".to_string() }
+        );
+
+        // Bob goes away: nothing listens any more and his end of the session closes
+        listening.abort();
+        let _ = listening.await;
+        bob.connections.disconnect_all();
+
+        testing::wait_for(&mut events, |event| match session_event(event) {
+            Some(SessionEvent::Disconnected { .. }) => Some(()),
+            _ => None,
+        })
+            .await;
+        // The first redial is refused, so a second one gets scheduled
+        testing::wait_for(&mut events, |event| match session_event(event) {
+            Some(SessionEvent::ReconnectScheduled { attempt: 2, .. }) => Some(()),
+            _ => None,
+        })
+            .await;
+        assert_ne!(alice.connections.state("200:6::b"), PeerState::Connected);
+
+        // Once Bob listens again the supervisor gets the session back
+        tokio::spawn(listener::accept_loop(TcpListener::bind(bob_addr).await.unwrap(), bob.clone()));
+        testing::wait_for(&mut events, |event| match session_event(event) {
+            Some(SessionEvent::Connected { address }) => Some(address),
+            _ => None,
+        })
+            .await;
+        assert_eq!(alice.connections.state("200:6::b"), PeerState::Connected);
+        alice.connections.disconnect_all();
+        bob.connections.disconnect_all();
+    }
+}
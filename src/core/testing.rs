@@ -0,0 +1,174 @@
+/// Shared Test Fixtures for Syggrel Chat
+///
+/// Sessions reach the database through the global connection, so tests that
+/// run real sessions share one migrated database in a temporary file. Each
+/// such test uses its own contact addresses and freshly generated identities,
+/// so their rows never collide.
+///
+/// Peers are wired together over loopback the way yggstack does it: the
+/// dialing side goes through a SOCKS5 proxy and the listener sees a
+/// connection from 127.0.0.1, identified only by its handshake key.
+use crate::core::events::MessengerEvent;
+use crate::core::identity::Identity;
+use crate::database::{self, contact_repository::ContactRepository, migrations::run_migrations, models::Contact};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use sea_orm::{Database, DatabaseConnection};
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tempfile::{NamedTempFile, TempPath};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, OnceCell};
+
+static DB_FILE: OnceLock<TempPath> = OnceLock::new();
+static DB_READY: OnceCell<()> = OnceCell::const_new();
+
+/// Opens the global database once for the whole test run
+pub async fn init_db() {
+    DB_READY
+        .get_or_init(|| async {
+            let path = DB_FILE.get_or_init(|| tempfile::NamedTempFile::new().unwrap().into_temp_path());
+            database::init_db(&path.to_string_lossy()).await.unwrap();
+        })
+        .await;
+}
+
+/// Connects to a database of its own in `file`, with every migration applied
+///
+/// For repository tests that must not share rows with the global database.
+pub async fn open_migrated_db(file: &NamedTempFile) -> Arc<DatabaseConnection> {
+    let db_url = format!("sqlite:{}?mode=rwc", file.path().to_string_lossy());
+    let db = Database::connect(&db_url).await.unwrap();
+    run_migrations(&db).await.unwrap();
+    Arc::new(db)
+}
+
+/// Saves a contact for `peer`, pinned to its key and reached through `proxy`
+pub async fn save_contact(address: &str, proxy: SocketAddr, peer: &Identity) -> Contact {
+    let mut contact = Contact::new(address, format!("socks5://{}", proxy), peer.display_name.clone(), true);
+    contact.public_key = Some(BASE64.encode(peer.public_key()));
+    ContactRepository::new(database::get_db().unwrap()).create(contact).await.unwrap()
+}
+
+/// Waits for the first event `pick` returns something for, failing after 10 seconds
+pub async fn wait_for<T>(
+    events: &mut broadcast::Receiver<MessengerEvent>,
+    mut pick: impl FnMut(MessengerEvent) -> Option<T>,
+) -> T {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            if let Some(found) = pick(events.recv().await.unwrap()) {
+                return found;
+            }
+        }
+    })
+        .await
+        .expect("event was not published in time")
+}
+
+/// A loopback port nothing listens on yet
+pub fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// SOCKS5 proxy standing in for yggstack
+///
+/// Every CONNECT, whatever its destination, is forwarded to `target`. While
+/// nothing listens there the CONNECT is refused, like an unreachable peer.
+pub async fn spawn_forwarder(target: SocketAddr) -> SocketAddr {
+    spawn_socks5(Upstream::Forward(target), None).await
+}
+
+/// SOCKS5 proxy that echoes back whatever is sent through it
+///
+/// With `credentials` set, clients must log in with that username and
+/// password (RFC 1929) and are turned away otherwise.
+pub async fn spawn_echo_proxy(credentials: Option<(&'static str, &'static str)>) -> SocketAddr {
+    spawn_socks5(Upstream::Echo, credentials).await
+}
+
+/// Where a stand-in proxy sends accepted connections
+#[derive(Clone, Copy)]
+enum Upstream {
+    Forward(SocketAddr),
+    Echo,
+}
+
+async fn spawn_socks5(upstream: Upstream, credentials: Option<(&'static str, &'static str)>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((conn, _)) = listener.accept().await {
+            tokio::spawn(forward(conn, upstream, credentials));
+        }
+    });
+
+    addr
+}
+
+async fn forward(
+    mut conn: TcpStream,
+    upstream: Upstream,
+    credentials: Option<(&'static str, &'static str)>,
+) -> std::io::Result<()> {
+    // Greeting: VER, NMETHODS, METHODS; username/password if we expect it
+    let mut head = [0u8; 2];
+    conn.read_exact(&mut head).await?;
+    let mut methods = vec![0u8; head[1] as usize];
+    conn.read_exact(&mut methods).await?;
+    let wanted = if credentials.is_some() { 0x02 } else { 0x00 };
+    if !methods.contains(&wanted) {
+        conn.write_all(&[5, 0xff]).await?;    // No acceptable method
+        return Ok(());
+    }
+    conn.write_all(&[5, wanted]).await?;
+
+    // RFC 1929: VER, ULEN, UNAME, PLEN, PASSWD
+    if let Some((user, pass)) = credentials {
+        let mut ver_len = [0u8; 2];
+        conn.read_exact(&mut ver_len).await?;
+        let mut username = vec![0u8; ver_len[1] as usize];
+        conn.read_exact(&mut username).await?;
+        let mut plen = [0u8; 1];
+        conn.read_exact(&mut plen).await?;
+        let mut password = vec![0u8; plen[0] as usize];
+        conn.read_exact(&mut password).await?;
+
+        if username != user.as_bytes() || password != pass.as_bytes() {
+            conn.write_all(&[1, 1]).await?;
+            return Ok(());
+        }
+        conn.write_all(&[1, 0]).await?;
+    }
+
+    // Request: VER, CMD, RSV, ATYP, DST.ADDR, DST.PORT; the destination is ignored
+    let mut request = [0u8; 4];
+    conn.read_exact(&mut request).await?;
+    let addr_len = match request[3] {
+        1 => 4,
+        4 => 16,
+        _ => return Ok(()),
+    };
+    let mut destination = vec![0u8; addr_len + 2];
+    conn.read_exact(&mut destination).await?;
+
+    let target = match upstream {
+        Upstream::Forward(target) => target,
+        Upstream::Echo => {
+            conn.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+            let (mut reader, mut writer) = conn.split();
+            tokio::io::copy(&mut reader, &mut writer).await?;
+            return Ok(());
+        }
+    };
+    let Ok(mut upstream) = TcpStream::connect(target).await else {
+        conn.write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]).await?;    // Connection refused
+        return Ok(());
+    };
+    conn.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+    tokio::io::copy_bidirectional(&mut conn, &mut upstream).await?;
+    Ok(())
+}
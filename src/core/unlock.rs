@@ -0,0 +1,85 @@
+use crate::core::identity::Identity;
+use crate::database::db_paths;
+use dioxus::prelude::*;
+
+/// Unlock Page
+///
+/// Shown at startup instead of the app when the identity key file is sealed
+/// with a passphrase (see `Identity::save`). Decrypting the key is deliberately
+/// slow, so it runs off the UI thread. `on_unlock` receives the identity once
+/// the right passphrase was entered; a wrong one is reported inline.
+#[component]
+pub fn UnlockIdentity(on_unlock: EventHandler<Identity>) -> Element {
+    let mut passphrase = use_signal(String::new);
+    let mut unlock_error = use_signal(|| None::<String>);
+    let mut unlocking = use_signal(|| false);
+
+    let on_submit = move |evt: FormEvent| {
+        evt.prevent_default();
+        if *unlocking.read() || passphrase.read().is_empty() {
+            return;
+        }
+
+        let entered = passphrase.read().clone();
+        unlock_error.set(None);
+        unlocking.set(true);
+
+        spawn(async move {
+            let loaded = tokio::task::spawn_blocking(move || {
+                let path = db_paths::get_identity_path().map_err(|e| e.to_string())?;
+                Identity::load(&path, Some(&entered)).map_err(|e| e.to_string())
+            })
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|identity| identity);
+
+            match loaded {
+                Ok(identity) => on_unlock.call(identity),
+                Err(e) => {
+                    passphrase.set(String::new());
+                    unlock_error.set(Some(e));
+                }
+            }
+            unlocking.set(false);
+        });
+    };
+
+    rsx! {
+        div {
+            class: "unlock-container",
+
+            header {
+                class: "top-bar",
+                h1 { "Syggrel Chat" }
+            }
+
+            form {
+                class: "unlock-form",
+                onsubmit: on_submit,
+
+                div {
+                    class: "form-field",
+                    label { r#for: "identity-passphrase", "Passphrase" }
+                    input {
+                        id: "identity-passphrase",
+                        r#type: "password",
+                        autofocus: true,
+                        value: "{passphrase}",
+                        aria_invalid: unlock_error.read().is_some(),
+                        oninput: move |e| passphrase.set(e.value()),
+                    }
+                    if let Some(message) = unlock_error.read().as_ref() {
+                        p { class: "field-error", role: "alert", "{message}" }
+                    }
+                }
+
+                button {
+                    class: "primary-button",
+                    r#type: "submit",
+                    disabled: *unlocking.read() || passphrase.read().is_empty(),
+                    if *unlocking.read() { "Unlocking..." } else { "Unlock" }
+                }
+            }
+        }
+    }
+}
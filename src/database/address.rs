@@ -0,0 +1,121 @@
+/// Yggdrasil Address Parsing for Syggrel Chat
+///
+/// Contacts are stored by Yggdrasil address, which must be an IPv6 address
+/// inside 200::/7: either a node address (02xx prefix) or an address from a
+/// node's routed /64 subnet (03xx prefix). A port may be given in the
+/// bracketed `[addr]:port` form; without one the default listen port is used.
+///
+/// Addresses are stored in canonical form (RFC 5952 compressed, lower case),
+/// so `0200:0000::0001` and `200::1` can't end up as two different contacts.
+use crate::database::models::ValidationError;
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+
+/// A validated Yggdrasil address with an optional port
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct YggdrasilAddress {
+    pub ip: Ipv6Addr,
+    pub port: Option<u16>,
+}
+
+impl YggdrasilAddress {
+    /// Parses `addr` or `[addr]:port`, surrounding whitespace ignored
+    pub fn parse(input: &str) -> Result<Self, ValidationError> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Err(ValidationError::InvalidAddress);
+        }
+
+        let (ip, port) = if let Some(bracketed) = input.strip_prefix('[') {
+            let (ip, port) = bracketed.split_once("]:").ok_or(ValidationError::InvalidAddress)?;
+            // A bad port gets its own error rather than the generic address one
+            let port = port.parse::<u16>().map_err(|_| ValidationError::InvalidAddressPort)?;
+            match ip.parse::<IpAddr>() {
+                Ok(IpAddr::V6(ip)) => (ip, Some(port)),
+                Ok(IpAddr::V4(_)) | Err(_) => return Err(ValidationError::InvalidAddress),
+            }
+        } else {
+            match input.parse::<IpAddr>() {
+                Ok(IpAddr::V6(ip)) => (ip, None),
+                Ok(IpAddr::V4(_)) => return Err(ValidationError::AddressOutsideYggdrasilRange),
+                Err(_) => return Err(ValidationError::InvalidAddress),
+            }
+        };
+
+        if !is_yggdrasil_ip(&ip) {
+            return Err(ValidationError::AddressOutsideYggdrasilRange);
+        }
+        if port == Some(0) {
+            return Err(ValidationError::InvalidAddressPort);
+        }
+
+        Ok(Self { ip, port })
+    }
+
+    /// Socket address to dial, falling back to `default_port`
+    pub fn socket_addr(&self, default_port: u16) -> SocketAddr {
+        SocketAddr::new(IpAddr::V6(self.ip), self.port.unwrap_or(default_port))
+    }
+}
+
+/// Canonical text form, as stored in the `contacts` table
+impl fmt::Display for YggdrasilAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.port {
+            Some(port) => write!(f, "[{}]:{}", self.ip, port),
+            None => write!(f, "{}", self.ip),
+        }
+    }
+}
+
+/// Whether `ip` lies in the Yggdrasil range 200::/7
+pub fn is_yggdrasil_ip(ip: &Ipv6Addr) -> bool {
+    ip.octets()[0] & 0xfe == 0x02
+}
+
+/// Validates an address and returns its canonical form
+pub fn normalize_address(input: &str) -> Result<String, ValidationError> {
+    YggdrasilAddress::parse(input).map(|addr| addr.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_and_subnet_addresses_accepted() {
+        let node = YggdrasilAddress::parse("201:5a4f:2b6c::1").unwrap();
+        let subnet = YggdrasilAddress::parse("301:5a4f:2b6c::1").unwrap();
+
+        assert_eq!(node.to_string(), "201:5a4f:2b6c::1");
+        assert_eq!(subnet.to_string(), "301:5a4f:2b6c::1");
+        assert_eq!(node.port, None);
+    }
+
+    #[test]
+    fn test_spellings_normalize_to_one_form() {
+        let canonical = "200:abcd::1";
+
+        assert_eq!(normalize_address("0200:ABCD:0000:0000:0000:0000:0000:0001").unwrap(), canonical);
+        assert_eq!(normalize_address("  200:abcd:0::1 ").unwrap(), canonical);
+        assert_eq!(normalize_address("[200:ABCD::0001]:7331").unwrap(), "[200:abcd::1]:7331");
+    }
+
+    #[test]
+    fn test_addresses_outside_range_rejected() {
+        assert_eq!(normalize_address("fe80::1"), Err(ValidationError::AddressOutsideYggdrasilRange));
+        assert_eq!(normalize_address("2001:db8::1"), Err(ValidationError::AddressOutsideYggdrasilRange));
+        assert_eq!(normalize_address("10.0.0.1"), Err(ValidationError::AddressOutsideYggdrasilRange));
+    }
+
+    #[test]
+    fn test_malformed_input_rejected() {
+        assert_eq!(normalize_address(""), Err(ValidationError::InvalidAddress));
+        assert_eq!(normalize_address("not-an-address"), Err(ValidationError::InvalidAddress));
+        assert_eq!(normalize_address("200::1:7331:zz"), Err(ValidationError::InvalidAddress));
+        assert_eq!(normalize_address("[200::1]"), Err(ValidationError::InvalidAddress));
+        assert_eq!(normalize_address("[200::1]:0"), Err(ValidationError::InvalidAddressPort));
+        assert_eq!(normalize_address("[200::1]:99999"), Err(ValidationError::InvalidAddressPort));
+        assert_eq!(normalize_address("[200::1]:http"), Err(ValidationError::InvalidAddressPort));
+    }
+}
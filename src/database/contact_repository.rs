@@ -0,0 +1,390 @@
+use crate::database::address::YggdrasilAddress;
+use crate::database::error::DatabaseError;
+use crate::database::models::{Contact, ValidationError};
+use crate::database::schema::{ActiveModel, Column, Entity};
+use sea_orm::sea_query::{Expr, LikeExpr};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, QueryFilter,
+    QueryOrder, Set, SqlErr,
+};
+use std::sync::Arc;
+
+/// Errors produced by contact operations
+#[derive(Debug, Clone)]
+pub enum ContactError {
+    Validation(ValidationError),
+    DuplicateAddress(String),    // Another contact already uses this Yggdrasil address
+    NotFound(i32),
+    Database(DatabaseError),
+}
+
+impl std::fmt::Display for ContactError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContactError::Validation(err) => write!(f, "{}", err),
+            ContactError::DuplicateAddress(address) => {
+                write!(f, "A contact with address {} already exists", address)
+            }
+            ContactError::NotFound(id) => write!(f, "Contact {} not found", id),
+            ContactError::Database(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ContactError {}
+
+impl From<ValidationError> for ContactError {
+    fn from(err: ValidationError) -> Self {
+        ContactError::Validation(err)
+    }
+}
+
+impl From<DatabaseError> for ContactError {
+    fn from(err: DatabaseError) -> Self {
+        ContactError::Database(err)
+    }
+}
+
+/// Repository for the `contacts` table
+///
+/// Works in terms of the application-level `Contact`; every write validates
+/// the contact first and stores its address in canonical form. Deactivating
+/// keeps the contact and its history but hides it from the chat list,
+/// deleting removes it along with its messages.
+/// `updated_at` is maintained by the entity's `before_save` hook.
+pub struct ContactRepository {
+    db: Arc<DatabaseConnection>,
+}
+
+impl ContactRepository {
+    pub fn new(db: Arc<DatabaseConnection>) -> Self {
+        Self { db }
+    }
+
+    /// Validates and inserts a new contact, returning it with its id
+    pub async fn create(&self, contact: Contact) -> Result<Contact, ContactError> {
+        let contact = contact.normalized()?;
+
+        let address = contact.yggdrasil_address.clone();
+        self.ensure_address_free(&address).await?;
+
+        ActiveModel::from(Contact { id: None, ..contact })
+            .insert(&*self.db)
+            .await
+            .map(Contact::from)
+            .map_err(|e| write_error(e, &address, "Failed to create contact"))
+    }
+
+    /// Validates and saves the user-editable fields of an existing contact
+    ///
+    /// Only the display name, proxy and notes are written. The address,
+    /// pinned key, status and timestamps keep their stored values, so a form
+    /// holding a stale copy can't roll back a key pinned in the meantime.
+    pub async fn update(&self, contact: Contact) -> Result<Contact, ContactError> {
+        let contact = contact.normalized()?;
+
+        let id = contact.id.ok_or_else(|| {
+            ContactError::Database(DatabaseError::Query("Cannot update an unsaved contact".to_string()))
+        })?;
+        self.require(id).await?;
+
+        let active = ActiveModel {
+            id: ActiveValue::Unchanged(id),
+            display_name: Set(contact.display_name),
+            socks5_proxy: Set(contact.socks5_proxy),
+            notes: Set(contact.notes),
+            ..ActiveModelTrait::default()
+        };
+
+        active
+            .update(&*self.db)
+            .await
+            .map(Contact::from)
+            .map_err(|e| query_error("Failed to update contact", e))
+    }
+
+    /// Shows or hides a contact in the chat list, keeping its history either way
+    pub async fn set_active(&self, id: i32, is_active: bool) -> Result<Contact, ContactError> {
+        let model = self.require(id).await?;
+
+        let mut active: ActiveModel = model.into();
+        active.is_active = Set(is_active);
+
+        active
+            .update(&*self.db)
+            .await
+            .map(Contact::from)
+            .map_err(|e| query_error("Failed to update contact status", e))
+    }
+
+    /// Stores when the contact was last active
+    ///
+    /// Written directly, without touching `updated_at`: this is bookkeeping,
+    /// not an edit of the contact.
+    pub async fn record_last_seen(&self, id: i32, at: chrono::DateTime<chrono::Utc>) -> Result<(), ContactError> {
+        let result = Entity::update_many()
+            .col_expr(Column::LastSeen, Expr::value(Some(at)))
+            .filter(Column::Id.eq(id))
+            .exec(&*self.db)
+            .await
+            .map_err(|e| query_error("Failed to update last seen", e))?;
+
+        if result.rows_affected == 0 {
+            return Err(ContactError::NotFound(id));
+        }
+        Ok(())
+    }
+
+    /// Permanently removes a contact; its messages and outbox entries cascade
+    pub async fn delete(&self, id: i32) -> Result<(), ContactError> {
+        let result = Entity::delete_by_id(id)
+            .exec(&*self.db)
+            .await
+            .map_err(|e| query_error("Failed to delete contact", e))?;
+
+        if result.rows_affected == 0 {
+            return Err(ContactError::NotFound(id));
+        }
+        Ok(())
+    }
+
+    pub async fn find(&self, id: i32) -> Result<Option<Contact>, ContactError> {
+        Entity::find_by_id(id)
+            .one(&*self.db)
+            .await
+            .map(|model| model.map(Contact::from))
+            .map_err(|e| query_error("Failed to load contact", e))
+    }
+
+    /// Contacts whose display name or address contains `query`, ordered by name
+    ///
+    /// An empty query matches everyone. Inactive contacts are only included
+    /// when `include_inactive` is set.
+    pub async fn list(&self, query: &str, include_inactive: bool) -> Result<Vec<Contact>, ContactError> {
+        let pattern = contains(query);
+        let mut select = Entity::find().filter(
+            Condition::any()
+                .add(Column::DisplayName.like(pattern.clone()))
+                .add(Column::YggdrasilAddress.like(pattern)),
+        );
+        if !include_inactive {
+            select = select.filter(Column::IsActive.eq(true));
+        }
+
+        select
+            .order_by_asc(Column::DisplayName)
+            .all(&*self.db)
+            .await
+            .map(|models| models.into_iter().map(Contact::from).collect())
+            .map_err(|e| query_error("Failed to list contacts", e))
+    }
+
+    /// Fails if a contact already has the same IP, whatever its port
+    ///
+    /// The UNIQUE constraint only covers the stored text, and `200::1` and
+    /// `[200::1]:7331` are the same peer. `address` must be canonical.
+    async fn ensure_address_free(&self, address: &str) -> Result<(), ContactError> {
+        let ip = YggdrasilAddress::parse(address).map_err(ContactError::Validation)?.ip;
+
+        // Canonical addresses are either the bare IP or `[ip]:port`
+        let taken = Entity::find()
+            .filter(
+                Condition::any()
+                    .add(Column::YggdrasilAddress.eq(ip.to_string()))
+                    .add(Column::YggdrasilAddress.starts_with(format!("[{}]:", ip))),
+            )
+            .one(&*self.db)
+            .await
+            .map_err(|e| query_error("Failed to check for an existing contact", e))?;
+
+        match taken {
+            Some(existing) => Err(ContactError::DuplicateAddress(existing.yggdrasil_address)),
+            None => Ok(()),
+        }
+    }
+
+    async fn require(&self, id: i32) -> Result<crate::database::schema::Model, ContactError> {
+        Entity::find_by_id(id)
+            .one(&*self.db)
+            .await
+            .map_err(|e| query_error("Failed to load contact", e))?
+            .ok_or(ContactError::NotFound(id))
+    }
+}
+
+fn query_error(context: &str, err: DbErr) -> ContactError {
+    ContactError::Database(DatabaseError::Query(format!("{}: {}", context, err)))
+}
+
+/// Maps insert/update failures, recognizing a taken address
+fn write_error(err: DbErr, address: &str, context: &str) -> ContactError {
+    match err.sql_err() {
+        Some(SqlErr::UniqueConstraintViolation(_)) => ContactError::DuplicateAddress(address.to_string()),
+        _ => query_error(context, err),
+    }
+}
+
+/// LIKE pattern matching values that contain `query` literally
+///
+/// `%` and `_` in the query are escaped so they don't act as wildcards.
+fn contains(query: &str) -> LikeExpr {
+    let escaped = query.trim().replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    LikeExpr::new(format!("%{}%", escaped)).escape('\\')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testing::open_migrated_db;
+    use tempfile::NamedTempFile;
+
+    async fn open_repository(file: &NamedTempFile) -> ContactRepository {
+        ContactRepository::new(open_migrated_db(file).await)
+    }
+
+    #[tokio::test]
+    async fn test_create_find_and_update() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let repo = open_repository(&temp_file).await;
+
+        let created = repo.create(Contact::new("200:1::1", "", "Alice", false)).await.unwrap();
+        let id = created.id.unwrap();
+
+        let found = repo.find(id).await.unwrap().unwrap();
+        assert_eq!(found.display_name, "Alice");
+
+        // Presence bookkeeping is not an edit
+        let seen = chrono::Utc::now();
+        repo.record_last_seen(id, seen).await.unwrap();
+        let touched = repo.find(id).await.unwrap().unwrap();
+        assert_eq!(touched.last_seen.map(|t| t.timestamp_millis()), Some(seen.timestamp_millis()));
+        assert_eq!(touched.updated_at, found.updated_at);
+
+        let renamed = repo.update(Contact { display_name: "Alice B".to_string(), ..found }).await.unwrap();
+        assert_eq!(renamed.id, Some(id));
+        assert_eq!(repo.find(id).await.unwrap().unwrap().display_name, "Alice B");
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_address_is_typed_error() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let repo = open_repository(&temp_file).await;
+
+        repo.create(Contact::new("200:1::1", "", "Alice", false)).await.unwrap();
+        let result = repo.create(Contact::new("200:1::1", "", "Mallory", false)).await;
+
+        assert!(matches!(result, Err(ContactError::DuplicateAddress(ref a)) if a == "200:1::1"));
+
+        // A different spelling of the same address is still a duplicate
+        let respelled = repo.create(Contact::new("0200:0001:0::0001", "", "Mallory", false)).await;
+        assert!(matches!(respelled, Err(ContactError::DuplicateAddress(_))));
+        assert_eq!(repo.list("", true).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_same_ip_with_other_port_is_duplicate() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let repo = open_repository(&temp_file).await;
+
+        let alice = repo.create(Contact::new("200:1::1", "", "Alice", false)).await.unwrap();
+        let with_port = repo.create(Contact::new("[200:1::1]:7331", "", "Mallory", false)).await;
+        assert!(matches!(with_port, Err(ContactError::DuplicateAddress(ref a)) if a == "200:1::1"));
+
+        let bob = repo.create(Contact::new("[200:1::2]:7331", "", "Bob", false)).await.unwrap();
+        let without_port = repo.create(Contact::new("200:1::2", "", "Mallory", false)).await;
+        assert!(matches!(without_port, Err(ContactError::DuplicateAddress(_))));
+
+        let ids: Vec<_> = repo.list("", true).await.unwrap().into_iter().map(|c| c.id).collect();
+        assert_eq!(ids, [alice.id, bob.id]);
+    }
+
+    #[tokio::test]
+    async fn test_update_only_writes_editable_fields() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let repo = open_repository(&temp_file).await;
+
+        let alice = repo.create(Contact::new("200:1::1", "", "Alice", false)).await.unwrap();
+        let id = alice.id.unwrap();
+        Entity::update_many()
+            .col_expr(Column::PublicKey, Expr::value(Some("pinned-key")))
+            .filter(Column::Id.eq(id))
+            .exec(&*repo.db)
+            .await
+            .unwrap();
+        repo.set_active(id, false).await.unwrap();
+
+        // A stale copy of the contact, as an edit form would hold it
+        let edited = Contact {
+            display_name: "Alice B".to_string(),
+            socks5_proxy: "socks5://127.0.0.1:1080".to_string(),
+            notes: Some("met at the meetup".to_string()),
+            yggdrasil_address: "200:1::9".to_string(),
+            ..alice
+        };
+        let saved = repo.update(edited).await.unwrap();
+
+        assert_eq!(saved.display_name, "Alice B");
+        assert_eq!(saved.socks5_proxy, "socks5://127.0.0.1:1080");
+        assert_eq!(saved.notes.as_deref(), Some("met at the meetup"));
+        assert_eq!(saved.yggdrasil_address, "200:1::1");
+        assert_eq!(saved.public_key.as_deref(), Some("pinned-key"));
+        assert!(!saved.is_active);
+    }
+
+    #[test]
+    fn test_new_active_model_is_active_and_unsaved() {
+        let model = <ActiveModel as sea_orm::ActiveModelBehavior>::new();
+
+        assert_eq!(model.is_active, Set(true));
+        assert!(model.id.is_not_set());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_contact_is_not_stored() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let repo = open_repository(&temp_file).await;
+
+        let result = repo.create(Contact::new("200:1::1", "", "  ", false)).await;
+
+        assert!(matches!(result, Err(ContactError::Validation(ValidationError::InvalidDisplayName))));
+        assert!(repo.list("", true).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_deactivate_hides_from_list_and_delete_removes() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let repo = open_repository(&temp_file).await;
+
+        let alice = repo.create(Contact::new("200:1::1", "", "Alice", false)).await.unwrap();
+        repo.create(Contact::new("200:1::2", "", "alicia", false)).await.unwrap();
+        assert_eq!(repo.list("ALI", false).await.unwrap().len(), 2);
+
+        let id = alice.id.unwrap();
+        assert!(!repo.set_active(id, false).await.unwrap().is_active);
+        assert_eq!(repo.list("ali", false).await.unwrap().len(), 1);
+
+        // Listing can still include it, by name or address
+        let all = repo.list("200:1::1", true).await.unwrap();
+        assert_eq!(all.len(), 1);
+        assert!(!all[0].is_active);
+        assert!(repo.set_active(id, true).await.unwrap().is_active);
+
+        repo.delete(id).await.unwrap();
+        assert!(repo.find(id).await.unwrap().is_none());
+        assert!(matches!(repo.delete(id).await, Err(ContactError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_search_matches_wildcards_literally() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let repo = open_repository(&temp_file).await;
+
+        repo.create(Contact::new("200:1::1", "", "100% Bob", false)).await.unwrap();
+        repo.create(Contact::new("200:1::2", "", "1000 Bob", false)).await.unwrap();
+
+        assert_eq!(repo.list("0%", false).await.unwrap().len(), 1);
+        assert_eq!(repo.list("%", false).await.unwrap().len(), 1);
+        assert!(repo.list("_", true).await.unwrap().is_empty());
+        assert_eq!(repo.list("", true).await.unwrap().len(), 2);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::database::DatabaseConfig;
-use sea_orm::{Database, DatabaseConnection, DbErr};
+use crate::database::error::DatabaseError;
+use sea_orm::{Database, DatabaseConnection};
 use std::sync::Arc;
 use tokio::time::{timeout, Duration};
 
@@ -20,7 +21,7 @@ impl DatabaseManager {
 
         let connection = Database::connect(&db_url)
             .await
-            .map_err(|e| DatabaseError::ConnectionFailed(e.to_string()))?;
+            .map_err(|e| DatabaseError::Connection(e.to_string()))?;
 
         let manager = Self {
             connection: Arc::new(connection),
@@ -46,8 +47,8 @@ impl DatabaseManager {
 
         match result {
             Ok(Ok(_)) => Ok(()),
-            Ok(Err(e)) => Err(DatabaseError::ConnectionFailed(format!("Validation query failed: {}", e))),
-            Err(_) => Err(DatabaseError::Timeout("Connection validation timed out".to_string)),
+            Ok(Err(e)) => Err(DatabaseError::Connection(format!("Validation query failed: {}", e))),
+            Err(_) => Err(DatabaseError::Connection("Connection validation timed out".to_string)),
         }
     }
 
@@ -75,33 +76,6 @@ impl Drop for DatabaseManager {
     }
 }
 
-#[derive(Debug, Clone)]
-pub enum DatabaseError {
-    ConnectionFailed(String),
-    InvalidConfig(String),
-    MigrationFailed(String),
-    Timeout(String),
-}
-
-impl std::fmt::Display for DatabaseError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            DatabaseError::ConnectionFailed(msg) => write!(f, "Database connection failed: {}", msg),
-            DatabaseError::InvalidConfig(msg) => write!(f, "Invalid database configuration: {}", msg),
-            DatabaseError::MigrationFailed(msg) => write!(f, "Database migration failed: {}", msg),
-            DatabaseError::Timeout(msg) => write!(f, "Database operation timed out: {}", msg),
-        }
-    }
-}
-
-impl std::error::Error for DatabaseError {}
-
-impl From<DbErr> for DatabaseError {
-    fn from(err: DbErr) -> Self {
-        DatabaseError::ConnectionFailed(err.to_string())
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -135,7 +109,7 @@ mod tests {
         };
 
         let result = DatabaseManager::new(config).await;
-        assert!(matches!(result, Err(DatabaseError::ConnectionFailed(_))));
+        assert!(matches!(result, Err(DatabaseError::Connection(_))));
     }
 
     #[tokio::test]
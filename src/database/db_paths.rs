@@ -11,6 +11,13 @@ pub fn get_database_path() -> Result<PathBuf, std::io::Error> {
     Ok(path)
 }
 
+/// Get the path of the file holding this installation's identity keypair
+pub fn get_identity_path() -> Result<PathBuf, std::io::Error> {
+    let mut path = get_config_dir()?;
+    path.push("identity.key");
+    Ok(path)
+}
+
 /// Get the platform-specific configuration directory
 /// 
 /// Returns the appropriate directory for storing application configuration
@@ -0,0 +1,30 @@
+/// Error type shared by the database layer
+///
+/// Returned by the migration runner and the repositories. Kept apart from
+/// `db_connection`, whose `DatabaseManager` is not wired into the app.
+use sea_orm::DbErr;
+
+#[derive(Debug, Clone)]
+pub enum DatabaseError {
+    Connection(String),
+    Migration(String),
+    Query(String),
+}
+
+impl std::fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DatabaseError::Connection(msg) => write!(f, "Database connection failed: {}", msg),
+            DatabaseError::Migration(msg) => write!(f, "Database migration failed: {}", msg),
+            DatabaseError::Query(msg) => write!(f, "Database query failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+impl From<DbErr> for DatabaseError {
+    fn from(err: DbErr) -> Self {
+        DatabaseError::Connection(err.to_string())
+    }
+}
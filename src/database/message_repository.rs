@@ -0,0 +1,390 @@
+use crate::database::error::DatabaseError;
+use crate::database::message_schema::{ActiveModel, Column, Entity, Model};
+use sea_orm::sea_query::{Expr, Func, Query};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter,
+    QueryOrder, QuerySelect, Set,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Message to be persisted for a contact
+///
+/// Carries only the caller-provided fields; the row id and `created_at`
+/// are assigned by the database layer on insert.
+#[derive(Clone, Debug)]
+pub struct NewMessage {
+    pub contact_id: i32,
+    pub is_outgoing: bool,
+    pub body: String,
+    pub sent_at: chrono::DateTime<chrono::Utc>,
+    pub received_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub envelope_id: Option<String>,
+}
+
+/// A single page of message history
+///
+/// `messages` are ordered oldest to newest so they can be rendered directly.
+/// `next_cursor` is the id to pass as `before` to fetch the previous (older)
+/// page, or `None` when the beginning of the history has been reached.
+#[derive(Clone, Debug)]
+pub struct MessagePage {
+    pub messages: Vec<Model>,
+    pub next_cursor: Option<i32>,
+}
+
+/// Repository for the `messages` table
+///
+/// Persists chat history so it survives restarts and eviction from the
+/// in-memory message buffer. Paging is keyset-based on the row id, which is
+/// monotonically increasing, so pages stay stable while new messages arrive.
+pub struct MessageRepository {
+    db: Arc<DatabaseConnection>,
+}
+
+impl MessageRepository {
+    pub fn new(db: Arc<DatabaseConnection>) -> Self {
+        Self { db }
+    }
+
+    /// Inserts a message and returns the stored row
+    pub async fn insert(&self, message: NewMessage) -> Result<Model, DatabaseError> {
+        let active = ActiveModel {
+            contact_id: Set(message.contact_id),
+            is_outgoing: Set(message.is_outgoing),
+            body: Set(message.body),
+            sent_at: Set(message.sent_at),
+            received_at: Set(message.received_at),
+            created_at: Set(chrono::Utc::now()),
+            envelope_id: Set(message.envelope_id),
+            delivered_at: Set(None),
+            read_at: Set(None),
+            ..ActiveModelTrait::default()
+        };
+
+        active
+            .insert(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to insert message: {}", e)))
+    }
+
+    /// Loads up to `limit` messages for a contact older than `before`
+    ///
+    /// Pass `None` as the cursor to start from the most recent message.
+    pub async fn page_before(
+        &self,
+        contact_id: i32,
+        before: Option<i32>,
+        limit: u64,
+    ) -> Result<MessagePage, DatabaseError> {
+        let mut query = Entity::find().filter(Column::ContactId.eq(contact_id));
+        if let Some(cursor) = before {
+            query = query.filter(Column::Id.lt(cursor));
+        }
+
+        let mut messages = query
+            .order_by_desc(Column::Id)
+            .limit(limit)
+            .all(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to load messages: {}", e)))?;
+
+        // A short page means there is nothing older left to fetch
+        let next_cursor = if (messages.len() as u64) < limit {
+            None
+        } else {
+            messages.last().map(|m| m.id)
+        };
+
+        messages.reverse();    // Newest-first from the query, oldest-first for the caller
+
+        Ok(MessagePage { messages, next_cursor })
+    }
+
+    /// Loads a contact's messages from `from_id` onwards, oldest first
+    ///
+    /// Used to refresh the newest part of a loaded history, picking up both
+    /// new messages and receipt changes on recent ones.
+    pub async fn since(&self, contact_id: i32, from_id: i32) -> Result<Vec<Model>, DatabaseError> {
+        Entity::find()
+            .filter(Column::ContactId.eq(contact_id))
+            .filter(Column::Id.gte(from_id))
+            .order_by_asc(Column::Id)
+            .all(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to load messages: {}", e)))
+    }
+
+    /// Loads some of a contact's messages by id, oldest first
+    pub async fn find_many(&self, contact_id: i32, ids: &[i32]) -> Result<Vec<Model>, DatabaseError> {
+        Entity::find()
+            .filter(Column::ContactId.eq(contact_id))
+            .filter(Column::Id.is_in(ids.iter().copied()))
+            .order_by_asc(Column::Id)
+            .all(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to load messages: {}", e)))
+    }
+
+    /// Finds a contact's message by the id of the envelope that carried it
+    pub async fn find_by_envelope(&self, contact_id: i32, envelope_id: &str) -> Result<Option<Model>, DatabaseError> {
+        Entity::find()
+            .filter(Column::ContactId.eq(contact_id))
+            .filter(Column::EnvelopeId.eq(envelope_id))
+            .one(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to load message: {}", e)))
+    }
+
+    /// Records the peer's delivery ack for one of our messages
+    pub async fn mark_delivered(&self, contact_id: i32, envelope_id: &str) -> Result<bool, DatabaseError> {
+        let result = Entity::update_many()
+            .col_expr(Column::DeliveredAt, Expr::value(chrono::Utc::now()))
+            .filter(Column::ContactId.eq(contact_id))
+            .filter(Column::IsOutgoing.eq(true))
+            .filter(Column::EnvelopeId.eq(envelope_id))
+            .filter(Column::DeliveredAt.is_null())
+            .exec(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to record delivery: {}", e)))?;
+
+        Ok(result.rows_affected > 0)
+    }
+
+    /// Records the peer's read receipt for some of our messages
+    ///
+    /// A read message was necessarily delivered, so `delivered_at` is filled
+    /// in too if the ack was lost.
+    pub async fn mark_read_by_peer(&self, contact_id: i32, envelope_ids: &[String]) -> Result<u64, DatabaseError> {
+        let now = chrono::Utc::now();
+        let result = Entity::update_many()
+            .col_expr(Column::ReadAt, Expr::value(now))
+            .col_expr(Column::DeliveredAt, Func::if_null(Expr::col(Column::DeliveredAt), now).into())
+            .filter(Column::ContactId.eq(contact_id))
+            .filter(Column::IsOutgoing.eq(true))
+            .filter(Column::EnvelopeId.is_in(envelope_ids.iter().cloned()))
+            .filter(Column::ReadAt.is_null())
+            .exec(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to record read receipt: {}", e)))?;
+
+        Ok(result.rows_affected)
+    }
+
+    /// Marks every unread incoming message from a contact as read
+    ///
+    /// Returns the envelope ids of the messages that were newly read, for
+    /// sending a read receipt back to the peer.
+    pub async fn mark_incoming_read(&self, contact_id: i32) -> Result<Vec<String>, DatabaseError> {
+        let unread_filter = Condition::all()
+            .add(Column::ContactId.eq(contact_id))
+            .add(Column::IsOutgoing.eq(false))
+            .add(Column::ReadAt.is_null());
+
+        let unread = Entity::find()
+            .filter(unread_filter.clone())
+            .order_by_asc(Column::Id)
+            .all(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to load unread messages: {}", e)))?;
+
+        if unread.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        Entity::update_many()
+            .col_expr(Column::ReadAt, Expr::value(chrono::Utc::now()))
+            .filter(unread_filter)
+            .filter(Column::Id.lte(unread.iter().map(|m| m.id).max().unwrap_or_default()))
+            .exec(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to mark messages read: {}", e)))?;
+
+        Ok(unread.into_iter().filter_map(|m| m.envelope_id).collect())
+    }
+
+    /// Number of unread incoming messages per contact
+    ///
+    /// Contacts without unread messages are absent from the map.
+    pub async fn unread_counts(&self) -> Result<HashMap<i32, u32>, DatabaseError> {
+        let rows: Vec<(i32, i64)> = Entity::find()
+            .select_only()
+            .column(Column::ContactId)
+            .column_as(Column::Id.count(), "unread")
+            .filter(Column::IsOutgoing.eq(false))
+            .filter(Column::ReadAt.is_null())
+            .group_by(Column::ContactId)
+            .into_tuple()
+            .all(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to count unread messages: {}", e)))?;
+
+        Ok(rows.into_iter().map(|(contact_id, count)| (contact_id, count as u32)).collect())
+    }
+
+    /// Most recent message of every contact that has one, keyed by contact id
+    pub async fn last_messages(&self) -> Result<HashMap<i32, Model>, DatabaseError> {
+        let newest_ids = Query::select()
+            .expr(Expr::col(Column::Id).max())
+            .from(Entity)
+            .group_by_col(Column::ContactId)
+            .to_owned();
+
+        let rows = Entity::find()
+            .filter(Column::Id.in_subquery(newest_ids))
+            .all(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to load last messages: {}", e)))?;
+
+        Ok(rows.into_iter().map(|m| (m.contact_id, m)).collect())
+    }
+
+    /// Deletes a single message, returning whether a row was removed
+    pub async fn delete(&self, id: i32) -> Result<bool, DatabaseError> {
+        let result = Entity::delete_by_id(id)
+            .exec(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to delete message: {}", e)))?;
+
+        Ok(result.rows_affected > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::contact_repository::ContactRepository;
+    use crate::core::testing::open_migrated_db;
+    use crate::database::models::Contact;
+    use tempfile::NamedTempFile;
+
+    // Repository over a fresh database holding one contact, whose id is returned
+    async fn open_repository(file: &NamedTempFile) -> (MessageRepository, i32) {
+        let db = open_migrated_db(file).await;
+        let contact = ContactRepository::new(db.clone())
+            .create(Contact::new("200:1::1", "", "Alice", false))
+            .await
+            .unwrap();
+        (MessageRepository::new(db), contact.id.unwrap())
+    }
+
+    fn message(contact_id: i32, body: &str) -> NewMessage {
+        NewMessage {
+            contact_id,
+            is_outgoing: true,
+            body: body.to_string(),
+            sent_at: chrono::Utc::now(),
+            received_at: None,
+            envelope_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pages_walk_history_without_overlap() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let (repo, contact_id) = open_repository(&temp_file).await;
+        for i in 1..=5 {
+            repo.insert(message(contact_id, &format!("m{}", i))).await.unwrap();
+        }
+
+        let bodies = |page: &MessagePage| page.messages.iter().map(|m| m.body.clone()).collect::<Vec<_>>();
+
+        // The first page is the newest messages, oldest first
+        let first = repo.page_before(contact_id, None, 2).await.unwrap();
+        assert_eq!(bodies(&first), ["m4", "m5"]);
+        assert_eq!(first.next_cursor, Some(first.messages[0].id));
+
+        let second = repo.page_before(contact_id, first.next_cursor, 2).await.unwrap();
+        assert_eq!(bodies(&second), ["m2", "m3"]);
+
+        // A short page is the end of the history
+        let last = repo.page_before(contact_id, second.next_cursor, 2).await.unwrap();
+        assert_eq!(bodies(&last), ["m1"]);
+        assert_eq!(last.next_cursor, None);
+
+        // Other contacts' history is not mixed in
+        let empty = repo.page_before(contact_id + 1, None, 2).await.unwrap();
+        assert!(empty.messages.is_empty());
+        assert_eq!(empty.next_cursor, None);
+    }
+
+    #[tokio::test]
+    async fn test_delete_single_message() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let (repo, contact_id) = open_repository(&temp_file).await;
+        let first = repo.insert(message(contact_id, "one")).await.unwrap();
+        repo.insert(message(contact_id, "two")).await.unwrap();
+        repo.insert(message(contact_id, "three")).await.unwrap();
+
+        assert!(repo.delete(first.id).await.unwrap());
+        assert!(!repo.delete(first.id).await.unwrap());
+        assert_eq!(repo.page_before(contact_id, None, 10).await.unwrap().messages.len(), 2);
+    }
+
+    // Message carried by envelope `envelope_id`, sent or received
+    fn envelope(contact_id: i32, is_outgoing: bool, envelope_id: &str) -> NewMessage {
+        NewMessage {
+            is_outgoing,
+            envelope_id: Some(envelope_id.to_string()),
+            ..message(contact_id, envelope_id)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_peer_acks_and_receipts_update_only_our_messages() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let (repo, contact_id) = open_repository(&temp_file).await;
+        let first = repo.insert(envelope(contact_id, true, "out-1")).await.unwrap();
+        let second = repo.insert(envelope(contact_id, true, "out-2")).await.unwrap();
+        let incoming = repo.insert(envelope(contact_id, false, "in-1")).await.unwrap();
+
+        // Acks are recorded once, and only for our messages to that contact
+        assert!(repo.mark_delivered(contact_id, "out-1").await.unwrap());
+        assert!(!repo.mark_delivered(contact_id, "out-1").await.unwrap());
+        assert!(!repo.mark_delivered(contact_id + 1, "out-2").await.unwrap());
+        assert!(!repo.mark_delivered(contact_id, "in-1").await.unwrap());
+
+        // A receipt for a message whose ack was lost marks it delivered too
+        let ids = ["out-1".to_string(), "out-2".to_string(), "in-1".to_string()];
+        assert_eq!(repo.mark_read_by_peer(contact_id, &ids).await.unwrap(), 2);
+        assert_eq!(repo.mark_read_by_peer(contact_id, &ids).await.unwrap(), 0);
+
+        let find = |id| repo.find_by_envelope(contact_id, id);
+        let (first_now, second_now) = (find("out-1").await.unwrap().unwrap(), find("out-2").await.unwrap().unwrap());
+        assert_eq!(first_now.id, first.id);
+        assert!(first_now.delivered_at.is_some() && first_now.read_at.is_some());
+        assert_eq!(second_now.id, second.id);
+        assert!(second_now.delivered_at.is_some() && second_now.read_at.is_some());
+
+        let incoming_now = find("in-1").await.unwrap().unwrap();
+        assert_eq!(incoming_now.id, incoming.id);
+        assert_eq!((incoming_now.delivered_at, incoming_now.read_at), (None, None));
+    }
+
+    #[tokio::test]
+    async fn test_reading_a_chat_clears_its_unread_count() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let (repo, alice) = open_repository(&temp_file).await;
+        let bob = ContactRepository::new(repo.db.clone())
+            .create(Contact::new("200:1::2", "", "Bob", false))
+            .await
+            .unwrap()
+            .id
+            .unwrap();
+
+        repo.insert(envelope(alice, false, "a-1")).await.unwrap();
+        repo.insert(envelope(alice, false, "a-2")).await.unwrap();
+        repo.insert(envelope(alice, true, "a-out")).await.unwrap();
+        repo.insert(envelope(bob, false, "b-1")).await.unwrap();
+
+        // Our own messages never count as unread
+        let counts = repo.unread_counts().await.unwrap();
+        assert_eq!(counts, HashMap::from([(alice, 2), (bob, 1)]));
+
+        // The newly read envelope ids are returned for the read receipt
+        assert_eq!(repo.mark_incoming_read(alice).await.unwrap(), ["a-1", "a-2"]);
+        assert!(repo.mark_incoming_read(alice).await.unwrap().is_empty());
+
+        assert_eq!(repo.unread_counts().await.unwrap(), HashMap::from([(bob, 1)]));
+    }
+}
@@ -0,0 +1,46 @@
+use sea_orm::entity::prelude::*;
+use sea_orm::Set;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize, Deserialize)]
+#[sea_orm(table_name = "messages")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i32,
+    #[sea_orm(indexed)]
+    pub contact_id: i32,    // Owning contact (contacts.id)
+    pub is_outgoing: bool,    // true if sent by us, false if received from the peer
+    pub body: String,    // Message text
+    pub sent_at: DateTimeUtc,    // Sender-side timestamp
+    pub received_at: Option<DateTimeUtc>,    // Local arrival time for incoming messages
+    pub created_at: DateTimeUtc,    // Record creation time
+    pub envelope_id: Option<String>,    // Wire id of the envelope that carried it
+    pub delivered_at: Option<DateTimeUtc>,    // When the peer acknowledged an outgoing message
+    pub read_at: Option<DateTimeUtc>,    // When the message was read (by us if incoming, by the peer if outgoing)
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::schema::Entity",
+        from = "Column::ContactId",
+        to = "super::schema::Column::Id",
+        on_delete = "Cascade"
+    )]
+    Contact,
+}
+
+impl Related<super::schema::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Contact.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {
+    fn new() -> Self {
+        Self {
+            created_at: Set(chrono::Utc::now()),
+            ..ActiveModelTrait::default()    // `Default` calls back into `new`
+        }
+    }
+}
@@ -0,0 +1,347 @@
+/// Versioned schema migrations for the Syggrel Chat database
+///
+/// Migrations are applied in ascending `version` order and recorded in the
+/// `schema_version` table, one row per applied step. Each step runs inside a
+/// transaction together with its bookkeeping row, so a failed step leaves the
+/// database at the previous version.
+///
+/// To evolve the schema, append a new `Migration` to `MIGRATIONS` with the next
+/// version number. Never edit or reorder a migration that has already shipped:
+/// user databases have recorded it as applied and will not run it again.
+///
+/// Down steps undo migrations, newest first. Running the app with
+/// `--migrate-down-to <version>` applies them, so an older release can open
+/// the database again.
+use crate::database::error::DatabaseError;
+use sea_orm::{ConnectionTrait, DatabaseConnection, Statement, TransactionTrait};
+use tracing::{info, warn};
+
+/// A single ordered schema change with its inverse
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: &'static [&'static str],
+    pub down: &'static [&'static str],
+}
+
+/// All known migrations, in ascending version order
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_contacts",
+        // IF NOT EXISTS lets databases created before versioning adopt this step
+        up: &[r#"
+            CREATE TABLE IF NOT EXISTS contacts (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                yggdrasil_address TEXT NOT NULL UNIQUE,
+                socks5_proxy TEXT NOT NULL,
+                display_name TEXT NOT NULL,
+                is_active BOOLEAN DEFAULT TRUE,
+                last_seen TIMESTAMP,
+                created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                is_hidden_peer BOOLEAN DEFAULT FALSE,
+                notes TEXT
+            )
+        "#],
+        down: &["DROP TABLE IF EXISTS contacts"],
+    },
+    Migration {
+        version: 2,
+        name: "create_messages",
+        up: &[
+            r#"
+            CREATE TABLE IF NOT EXISTS messages (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                contact_id INTEGER NOT NULL REFERENCES contacts(id) ON DELETE CASCADE,
+                is_outgoing BOOLEAN NOT NULL,
+                body TEXT NOT NULL,
+                sent_at TIMESTAMP NOT NULL,
+                received_at TIMESTAMP,
+                created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+            "CREATE INDEX IF NOT EXISTS idx_messages_contact_id ON messages (contact_id, id)",
+        ],
+        down: &[
+            "DROP INDEX IF EXISTS idx_messages_contact_id",
+            "DROP TABLE IF EXISTS messages",
+        ],
+    },
+    Migration {
+        version: 3,
+        name: "create_outbox",
+        up: &[
+            "ALTER TABLE messages ADD COLUMN envelope_id TEXT",
+            "CREATE UNIQUE INDEX idx_messages_envelope_id ON messages (contact_id, envelope_id)",
+            r#"
+            CREATE TABLE outbox (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                message_id INTEGER NOT NULL UNIQUE REFERENCES messages(id) ON DELETE CASCADE,
+                contact_id INTEGER NOT NULL REFERENCES contacts(id) ON DELETE CASCADE,
+                envelope_id TEXT NOT NULL UNIQUE,
+                status TEXT NOT NULL DEFAULT 'pending',
+                attempts INTEGER NOT NULL DEFAULT 0,
+                last_error TEXT,
+                last_attempt_at TIMESTAMP,
+                created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+            "CREATE INDEX idx_outbox_contact_status ON outbox (contact_id, status)",
+        ],
+        down: &[
+            "DROP INDEX IF EXISTS idx_outbox_contact_status",
+            "DROP TABLE IF EXISTS outbox",
+            "DROP INDEX IF EXISTS idx_messages_envelope_id",
+            "ALTER TABLE messages DROP COLUMN envelope_id",
+        ],
+    },
+    Migration {
+        version: 4,
+        name: "add_message_receipts",
+        up: &[
+            "ALTER TABLE messages ADD COLUMN delivered_at TIMESTAMP",
+            "ALTER TABLE messages ADD COLUMN read_at TIMESTAMP",
+            "CREATE INDEX idx_messages_unread ON messages (contact_id, is_outgoing, read_at)",
+        ],
+        down: &[
+            "DROP INDEX IF EXISTS idx_messages_unread",
+            "ALTER TABLE messages DROP COLUMN read_at",
+            "ALTER TABLE messages DROP COLUMN delivered_at",
+        ],
+    },
+    Migration {
+        version: 5,
+        name: "add_contact_public_key",
+        up: &["ALTER TABLE contacts ADD COLUMN public_key TEXT"],
+        down: &["ALTER TABLE contacts DROP COLUMN public_key"],
+    },
+    Migration {
+        version: 6,
+        name: "create_settings",
+        up: &[r#"
+            CREATE TABLE settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL,
+                updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+        "#],
+        down: &["DROP TABLE IF EXISTS settings"],
+    },
+    Migration {
+        version: 7,
+        name: "add_contact_card_fingerprint",
+        up: &["ALTER TABLE contacts ADD COLUMN card_fingerprint TEXT"],
+        down: &["ALTER TABLE contacts DROP COLUMN card_fingerprint"],
+    },
+];
+
+/// Highest schema version this build of the application understands
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+async fn ensure_version_table(db: &DatabaseConnection) -> Result<(), DatabaseError> {
+    let sql = r#"
+        CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+    "#;
+
+    db.execute(Statement::from_string(db.get_database_backend(), sql))
+        .await
+        .map_err(|e| DatabaseError::Migration(format!("Cannot create schema_version table: {}", e)))?;
+
+    Ok(())
+}
+
+/// Returns the schema version currently recorded in the database (0 if none)
+pub async fn current_version(db: &DatabaseConnection) -> Result<i64, DatabaseError> {
+    ensure_version_table(db).await?;
+
+    let row = db
+        .query_one(Statement::from_string(
+            db.get_database_backend(),
+            "SELECT COALESCE(MAX(version), 0) AS version FROM schema_version",
+        ))
+        .await
+        .map_err(|e| DatabaseError::Migration(format!("Cannot read schema version: {}", e)))?;
+
+    match row {
+        Some(row) => row
+            .try_get::<i64>("", "version")
+            .map_err(|e| DatabaseError::Migration(format!("Cannot read schema version: {}", e))),
+        None => Ok(0),
+    }
+}
+
+/// Brings the database up to `latest_version()`
+///
+/// Refuses to touch a database whose recorded version is newer than this
+/// build knows about, since it was written by a newer application release
+/// and silently using it could lose data.
+pub async fn run_migrations(db: &DatabaseConnection) -> Result<i64, DatabaseError> {
+    let current = current_version(db).await?;
+    let latest = latest_version();
+
+    if current > latest {
+        return Err(DatabaseError::Migration(format!(
+            "Database schema version {} is newer than the supported version {}; please update Syggrel Chat",
+            current, latest
+        )));
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        apply(db, migration).await?;
+        info!("Applied migration {} ({})", migration.version, migration.name);
+    }
+
+    Ok(latest)
+}
+
+/// Reverts applied migrations, newest first, until the database is at `target`
+pub async fn migrate_down_to(db: &DatabaseConnection, target: i64) -> Result<(), DatabaseError> {
+    let current = current_version(db).await?;
+
+    if current > latest_version() {
+        return Err(DatabaseError::Migration(format!(
+            "Cannot revert schema version {}: no down steps known for versions above {}",
+            current,
+            latest_version()
+        )));
+    }
+
+    for migration in MIGRATIONS.iter().rev().filter(|m| m.version > target && m.version <= current) {
+        revert(db, migration).await?;
+        warn!("Reverted migration {} ({})", migration.version, migration.name);
+    }
+
+    Ok(())
+}
+
+async fn apply(db: &DatabaseConnection, migration: &Migration) -> Result<(), DatabaseError> {
+    let backend = db.get_database_backend();
+    let fail = |e: sea_orm::DbErr| {
+        DatabaseError::Migration(format!("Migration {} ({}) failed: {}", migration.version, migration.name, e))
+    };
+
+    let txn = db.begin().await.map_err(fail)?;
+
+    for sql in migration.up {
+        txn.execute(Statement::from_string(backend, *sql)).await.map_err(fail)?;
+    }
+
+    txn.execute(Statement::from_sql_and_values(
+        backend,
+        "INSERT INTO schema_version (version, name) VALUES (?, ?)",
+        [migration.version.into(), migration.name.into()],
+    ))
+        .await
+        .map_err(fail)?;
+
+    txn.commit().await.map_err(fail)
+}
+
+async fn revert(db: &DatabaseConnection, migration: &Migration) -> Result<(), DatabaseError> {
+    let backend = db.get_database_backend();
+    let fail = |e: sea_orm::DbErr| {
+        DatabaseError::Migration(format!("Revert of {} ({}) failed: {}", migration.version, migration.name, e))
+    };
+
+    let txn = db.begin().await.map_err(fail)?;
+
+    for sql in migration.down {
+        txn.execute(Statement::from_string(backend, *sql)).await.map_err(fail)?;
+    }
+
+    txn.execute(Statement::from_sql_and_values(
+        backend,
+        "DELETE FROM schema_version WHERE version = ?",
+        [migration.version.into()],
+    ))
+        .await
+        .map_err(fail)?;
+
+    txn.commit().await.map_err(fail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm::Database;
+    use tempfile::NamedTempFile;
+
+    async fn open_temp_db(file: &NamedTempFile) -> DatabaseConnection {
+        let db_url = format!("sqlite:{}?mode=rwc", file.path().to_string_lossy());
+        Database::connect(&db_url).await.unwrap()
+    }
+
+    async fn table_exists(db: &DatabaseConnection, table: &str) -> bool {
+        db.query_one(Statement::from_sql_and_values(
+            db.get_database_backend(),
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?",
+            [table.into()],
+        ))
+            .await
+            .unwrap()
+            .is_some()
+    }
+
+    #[tokio::test]
+    async fn test_migrations_apply_on_fresh_database() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let db = open_temp_db(&temp_file).await;
+
+        let version = run_migrations(&db).await.unwrap();
+        assert_eq!(version, latest_version());
+        assert_eq!(current_version(&db).await.unwrap(), latest_version());
+        assert!(table_exists(&db, "contacts").await);
+        assert!(table_exists(&db, "messages").await);
+        assert!(table_exists(&db, "outbox").await);
+        assert!(table_exists(&db, "settings").await);
+    }
+
+    #[tokio::test]
+    async fn test_migrations_are_idempotent() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let db = open_temp_db(&temp_file).await;
+
+        run_migrations(&db).await.unwrap();
+        run_migrations(&db).await.unwrap();
+        assert_eq!(current_version(&db).await.unwrap(), latest_version());
+    }
+
+    #[tokio::test]
+    async fn test_newer_database_is_refused() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let db = open_temp_db(&temp_file).await;
+
+        run_migrations(&db).await.unwrap();
+        db.execute(Statement::from_sql_and_values(
+            db.get_database_backend(),
+            "INSERT INTO schema_version (version, name) VALUES (?, 'from_the_future')",
+            [(latest_version() + 1).into()],
+        ))
+            .await
+            .unwrap();
+
+        let result = run_migrations(&db).await;
+        assert!(matches!(result, Err(DatabaseError::Migration(_))));
+    }
+
+    #[tokio::test]
+    async fn test_migrate_down_reverts_steps() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let db = open_temp_db(&temp_file).await;
+
+        run_migrations(&db).await.unwrap();
+        migrate_down_to(&db, 0).await.unwrap();
+
+        assert_eq!(current_version(&db).await.unwrap(), 0);
+        assert!(!table_exists(&db, "contacts").await);
+        assert!(!table_exists(&db, "messages").await);
+    }
+}
@@ -4,16 +4,27 @@
 /// and provides functions for database operations. It uses SeaORM as the
 /// ORM layer and maintains a single shared connection pool accessible
 /// globally via the OnceCell pattern.
-use crate::database::models::Contact;
 use sea_orm::{
-    ColumnTrait, EntityTrait, Database, DatabaseConnection, QueryFilter, QuerySelect
+    ColumnTrait, EntityTrait, Database, DatabaseConnection, QueryFilter
 };
 use std::sync::Arc;
 use tokio::sync::OnceCell;
 use tracing::{info, error, instrument};
 
 pub mod schema;
+pub mod db_paths;
 pub mod models;
+pub mod address;
+pub mod proxy_url;
+pub mod contact_repository;
+pub mod error;
+pub mod migrations;
+pub mod message_schema;
+pub mod message_repository;
+pub mod outbox_schema;
+pub mod outbox_repository;
+pub mod settings_schema;
+pub mod settings_repository;
 
 static DB: OnceCell<Arc<DatabaseConnection>> = OnceCell::const_new();
 
@@ -23,80 +34,88 @@ pub async fn init_db(db_path: &str) -> Result<(), Box<dyn std::error::Error + Se
         .await
         .map_err(|e| format!("Database connection failed: {}", e))?;
 
-    // Run migrations
-    run_migrations(&db).await?;
+    // Bring the schema up to date (refuses databases from newer app versions)
+    migrations::run_migrations(&db).await?;
 
     DB.set(Arc::new(db)).map_err(|_| "Failed to set database connection")?;
 
     Ok(())
 }
 
-pub fn get_db() -> Option<Arc<DatabaseConnection>> {
-    DB.get().cloned()
-}
-
-async fn run_migrations(db: &DatabaseConnection) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    use sea_orm::{ConnectionTrait, Statement};
-
-    // Create contacts table if it doesn't exist
-    let create_table_sql = r#"
-        CREATE TABLE IF NOT EXISTS contacts (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            yggdrasil_address TEXT NOT NULL UNIQUE,
-            socks5_proxy TEXT NOT NULL,
-            display_name TEXT NOT NULL,
-            is_active BOOLEAN DEFAULT TRUE,
-            last_seen TIMESTAMP,
-            created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
-            updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
-            is_hidden_peer BOOLEAN DEFAULT FALSE,
-            notes TEXT
-        )
-    "#;
-
-    db.execute(Statement::from_string(db.get_database_backend(), create_table_sql))
+/// Reverts the database at `db_path` to schema version `target`
+///
+/// Used before going back to an older release, which refuses to open a
+/// database migrated past the versions it knows.
+pub async fn migrate_down(db_path: &str, target: i64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let db_url = format!("sqlite:{}?mode=rwc", db_path);
+    let db = Database::connect(&db_url)
         .await
-        .map_err(|e| format!("Migration failed: {}", e))?;
+        .map_err(|e| format!("Database connection failed: {}", e))?;
 
-    Ok(())    
+    migrations::migrate_down_to(&db, target).await?;
+    info!("Database schema is now at version {}", migrations::current_version(&db).await?);
+    Ok(())
 }
 
-pub async fn ensure_db_initialized() -> Result<(), &'static str> {
-    if DB.get().is_none() {
-        return Err("Database not initialized. Call init_db() first.");
-    }
-    Ok(())
+pub fn get_db() -> Option<Arc<DatabaseConnection>> {
+    DB.get().cloned()
 }
 
 #[instrument(skip())]
-pub async fn load_contacts_from_db() -> Result<Vec<crate::chat_item::ChatItem>, String> {
+pub async fn load_contacts_from_db() -> Result<Vec<crate::core::chat_data::ChatItem>, String> {
     let db = get_db()
         .ok_or_else(|| {
             error!("Database not initialized - call init_db() first");
             "Database not initialized".to_string()
         })?;
-    
-    // Execute the query to fetch active contacts
-    let active_contacts = entity::contact::Entity::find()
-        .filter(entity::contact::Column::IsActive.eq(true))
+
+    let active_contacts = schema::Entity::find()
+        .filter(schema::Column::IsActive.eq(true))
         .all(&*db)    // Dereference Arc to get DatabaseConnection
         .await
         .map_err(|e| {
             error!("Database query failed: {}", e);
             "Failed to load contacts from database".to_string()
         })?;
-    
-    let chat_items: Vec<crate::chat_item::ChatItem > = active_contacts
+
+    let messages = message_repository::MessageRepository::new(db.clone());
+
+    // Incoming messages not yet opened, keyed by contact id
+    let unread = messages
+        .unread_counts()
+        .await
+        .map_err(|e| {
+            error!("{}", e);
+            "Failed to load unread counts".to_string()
+        })?;
+
+    // Newest message in either direction, keyed by contact id
+    let mut last_messages = messages
+        .last_messages()
+        .await
+        .map_err(|e| {
+            error!("{}", e);
+            "Failed to load last messages".to_string()
+        })?;
+
+    let chat_items: Vec<crate::core::chat_data::ChatItem> = active_contacts
         .into_iter()
-        .map(|model| crate::chat_item::ChatItem {
-            id: model.id.to_string(),    // Convert i32 to string for ChatItem
-            name: model.display_name,
-            last_message: String::new(),
-            timestamp: model.last_seen.map(|dt| dt.to_rfc3339()).unwrap_or_default(),
+        .map(|model| {
+            let last = last_messages.remove(&model.id);
+            crate::core::chat_data::ChatItem {
+                id: crate::core::chat_data::ChatId::from(model.id),
+                name: model.display_name,
+                address: model.yggdrasil_address,
+                timestamp: last.as_ref().map(|m| m.sent_at).or(model.last_seen).unwrap_or(model.created_at),
+                last_message: last.map(|m| m.body),
+                unread_count: unread.get(&model.id).copied().unwrap_or(0),
+                is_online: false,
+                presence: crate::core::protocol::PresenceStatus::Offline,
+            }
         })
         .collect();
 
     info!("Successfully loaded {} active contacts from database", chat_items.len());
 
     Ok(chat_items)
-}
\ No newline at end of file
+}
@@ -1,5 +1,7 @@
-use crate::database::schema::Entity;
-use sea_orm::entity::prelude::*;
+use crate::database::proxy_url::ProxyConfig;
+use crate::database::address::normalize_address;
+use crate::database::schema::{ActiveModel, Model};
+use sea_orm::{ActiveValue, Set};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
@@ -15,6 +17,8 @@ pub struct Contact {
     pub updated_at: chrono::DateTime<chrono::Utc>,
     pub is_hidden_peer: bool,
     pub notes: Option<String>,
+    pub public_key: Option<String>,
+    pub card_fingerprint: Option<String>,
 }
 
 impl Contact {
@@ -36,36 +40,64 @@ impl Contact {
             updated_at: now,             // Set update timestamp to current time
             is_hidden_peer,              // Store whether this is a hidden peer (no TUN interface)
             notes: None,                 // No user notes initially
+            public_key: None,            // Pinned on the first successful handshake
+            card_fingerprint: None,      // Only known when added from a contact card
         }
     }
 
     pub fn validate(&self) -> Result<(), ValidationError> {
-        if self.yggdrasil_address.is_empty() {
-            return Err(ValidationError::InvalidAddress);
-        }
+        normalize_address(&self.yggdrasil_address)?;
         if self.display_name.trim().is_empty() {
             return Err(ValidationError::InvalidDisplayName);
         }
-        if self.is_hidden_peer && self.socks5_proxy.is_empty() {
-            return Err(ValidationError::InvalidProxy);
+        // An empty proxy inherits the app-wide default when dialing
+        if !self.socks5_proxy.trim().is_empty() {
+            ProxyConfig::parse(&self.socks5_proxy)?;
         }
         Ok(())
     }
+
+    /// Validates the contact and rewrites its address and proxy in canonical form
+    ///
+    /// Everything written to the database goes through this, so differently
+    /// spelled addresses of one node always compare equal.
+    pub fn normalized(mut self) -> Result<Self, ValidationError> {
+        self.validate()?;
+        self.yggdrasil_address = normalize_address(&self.yggdrasil_address)?;
+        if !self.socks5_proxy.trim().is_empty() {
+            self.socks5_proxy = ProxyConfig::parse(&self.socks5_proxy)?.to_url();
+        }
+        Ok(self)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ValidationError {
     InvalidAddress,
+    AddressOutsideYggdrasilRange,
+    InvalidAddressPort,
     InvalidDisplayName,
     InvalidProxy,
+    InvalidProxyAuth,
 }
 
 impl fmt::Display for ValidationError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ValidationError::InvalidAddress => write!(f, "Unvalid Yggdrasil address"),
+            ValidationError::InvalidAddress => {
+                write!(f, "Invalid Yggdrasil address, expected an IPv6 address or [address]:port")
+            }
+            ValidationError::AddressOutsideYggdrasilRange => {
+                write!(f, "Address is not a Yggdrasil address (must be within 200::/7)")
+            }
+            ValidationError::InvalidAddressPort => write!(f, "Invalid port in Yggdrasil address"),
             ValidationError::InvalidDisplayName => write!(f, "Invalid display name"),
-            ValidationError::InvalidProxy => write!(f, "Invalid SOCKS5 proxy configuration"),
+            ValidationError::InvalidProxy => {
+                write!(f, "Invalid SOCKS5 proxy, expected socks5://[user:password@]host[:port]")
+            }
+            ValidationError::InvalidProxyAuth => {
+                write!(f, "Invalid proxy credentials, both username and password are required (max 255 bytes)")
+            }
         }
     }
 }
@@ -73,7 +105,7 @@ impl fmt::Display for ValidationError {
 impl std::error::Error for ValidationError {}
 
 impl From<Contact> for ActiveModel {    // Implement conversion from Contact to SeaORM ActiveModel
-    fn fro(contact: Contact) -> Self {    // Define the conversion function
+    fn from(contact: Contact) -> Self {    // Define the conversion function
         ActiveModel {                     // Create new ActiveModel instance
             id: match contact.id {        
                 Some(id) => Set(id),      // If Contact has an ID, tell SeaORM to set it
@@ -88,6 +120,27 @@ impl From<Contact> for ActiveModel {    // Implement conversion from Contact to
             updated_at: Set(contact.updated_at),
             is_hidden_peer: Set(contact.is_hidden_peer),
             notes: Set(contact.notes),
+            public_key: Set(contact.public_key),
+            card_fingerprint: Set(contact.card_fingerprint),
+        }
+    }
+}
+
+impl From<Model> for Contact {
+    fn from(model: Model) -> Self {
+        Self {
+            id: Some(model.id),
+            yggdrasil_address: model.yggdrasil_address,
+            socks5_proxy: model.socks5_proxy,
+            display_name: model.display_name,
+            is_active: model.is_active,
+            last_seen: model.last_seen,
+            created_at: model.created_at,
+            updated_at: model.updated_at,
+            is_hidden_peer: model.is_hidden_peer,
+            notes: model.notes,
+            public_key: model.public_key,
+            card_fingerprint: model.card_fingerprint,
         }
     }
 }
@@ -112,6 +165,8 @@ impl From<sea_orm::QueryResult> for Contact {
             updated_at: row.try_get("", "updated_at").unwrap_or_else(|_| chrono::Utc::now()),
             is_hidden_peer: row.try_get("", "is_hidden_peer").unwrap_or(false),
             notes: row.try_get("", "notes").ok(),
+            public_key: row.try_get("", "public_key").ok(),
+            card_fingerprint: row.try_get("", "card_fingerprint").ok(),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,301 @@
+use crate::database::error::DatabaseError;
+use crate::database::message_schema;
+use crate::database::outbox_schema::{ActiveModel, Column, DeliveryStatus, Entity, Model};
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveEnum, ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait,
+    IntoActiveModel, QueryFilter, QueryOrder, QuerySelect, Set, TransactionTrait,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Attempts after which an unacknowledged message is marked failed
+pub const MAX_SEND_ATTEMPTS: i32 = 5;
+
+/// Repository for the `outbox` table
+///
+/// Every outgoing message is stored in `messages` and gets an outbox row
+/// tracking its delivery. Rows stay `Pending` until the peer acknowledges
+/// the envelope, so queued messages survive restarts and offline peers.
+pub struct OutboxRepository {
+    db: Arc<DatabaseConnection>,
+}
+
+impl OutboxRepository {
+    pub fn new(db: Arc<DatabaseConnection>) -> Self {
+        Self { db }
+    }
+
+    /// Stores an outgoing message and queues it for delivery in one transaction
+    pub async fn enqueue(
+        &self,
+        contact_id: i32,
+        body: String,
+        envelope_id: String,
+    ) -> Result<(message_schema::Model, Model), DatabaseError> {
+        let fail = |e: sea_orm::DbErr| DatabaseError::Query(format!("Failed to queue message: {}", e));
+        let now = chrono::Utc::now();
+
+        let txn = self.db.begin().await.map_err(fail)?;
+
+        let message = message_schema::ActiveModel {
+            contact_id: Set(contact_id),
+            is_outgoing: Set(true),
+            body: Set(body),
+            sent_at: Set(now),
+            received_at: Set(None),
+            created_at: Set(now),
+            envelope_id: Set(Some(envelope_id.clone())),
+            ..ActiveModelTrait::default()
+        }
+            .insert(&txn)
+            .await
+            .map_err(fail)?;
+
+        let entry = ActiveModel {
+            message_id: Set(message.id),
+            contact_id: Set(contact_id),
+            envelope_id: Set(envelope_id),
+            status: Set(DeliveryStatus::Pending),
+            attempts: Set(0),
+            created_at: Set(now),
+            updated_at: Set(now),
+            ..ActiveModelTrait::default()
+        }
+            .insert(&txn)
+            .await
+            .map_err(fail)?;
+
+        txn.commit().await.map_err(fail)?;
+
+        Ok((message, entry))
+    }
+
+    /// Pending entries for a contact with their messages, oldest first
+    pub async fn pending_for_contact(
+        &self,
+        contact_id: i32,
+    ) -> Result<Vec<(Model, message_schema::Model)>, DatabaseError> {
+        self.pending(contact_id, Condition::all()).await
+    }
+
+    /// Pending entries for a contact not written to a session since `cutoff`
+    ///
+    /// These were sent but never acknowledged, or never sent at all.
+    pub async fn unacked_since(
+        &self,
+        contact_id: i32,
+        cutoff: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<(Model, message_schema::Model)>, DatabaseError> {
+        let stale = Condition::any()
+            .add(Column::LastAttemptAt.is_null())
+            .add(Column::LastAttemptAt.lt(cutoff));
+        self.pending(contact_id, stale).await
+    }
+
+    /// Ids of the contacts that have pending entries, ascending
+    pub async fn contacts_with_pending(&self) -> Result<Vec<i32>, DatabaseError> {
+        Entity::find()
+            .select_only()
+            .column(Column::ContactId)
+            .distinct()
+            .filter(Column::Status.eq(DeliveryStatus::Pending))
+            .order_by_asc(Column::ContactId)
+            .into_tuple()
+            .all(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to load outbox: {}", e)))
+    }
+
+    async fn pending(
+        &self,
+        contact_id: i32,
+        condition: Condition,
+    ) -> Result<Vec<(Model, message_schema::Model)>, DatabaseError> {
+        let rows = Entity::find()
+            .filter(Column::ContactId.eq(contact_id))
+            .filter(Column::Status.eq(DeliveryStatus::Pending))
+            .filter(condition)
+            .order_by_asc(Column::Id)
+            .find_also_related(message_schema::Entity)
+            .all(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to load outbox: {}", e)))?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(entry, message)| message.map(|m| (entry, m)))
+            .collect())
+    }
+
+    /// Records a write of the envelope to a session, or a failure to do so
+    ///
+    /// Once `MAX_SEND_ATTEMPTS` writes have gone unacknowledged the entry is
+    /// marked `Failed`; a late ack still moves it to `Sent`.
+    pub async fn record_attempt(&self, id: i32, error: Option<String>) -> Result<Model, DatabaseError> {
+        let entry = self.find(id).await?;
+        let attempts = entry.attempts + 1;
+        let status = if entry.status == DeliveryStatus::Pending && attempts >= MAX_SEND_ATTEMPTS {
+            DeliveryStatus::Failed
+        } else {
+            entry.status
+        };
+
+        let mut active = entry.into_active_model();
+        active.attempts = Set(attempts);
+        active.status = Set(status);
+        active.last_error = Set(error);
+        active.last_attempt_at = Set(Some(chrono::Utc::now()));
+        active.updated_at = Set(chrono::Utc::now());
+
+        active
+            .update(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to update outbox entry: {}", e)))
+    }
+
+    /// Marks the entry for an envelope acknowledged by `contact_id` as sent
+    ///
+    /// Returns `None` for unknown ids, e.g. acks for messages already deleted
+    /// or for envelopes that were sent to a different contact.
+    pub async fn mark_sent(&self, contact_id: i32, envelope_id: &str) -> Result<Option<Model>, DatabaseError> {
+        let entry = Entity::find()
+            .filter(Column::ContactId.eq(contact_id))
+            .filter(Column::EnvelopeId.eq(envelope_id))
+            .one(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to load outbox entry: {}", e)))?;
+
+        let Some(entry) = entry else {
+            return Ok(None);
+        };
+
+        let mut active = entry.into_active_model();
+        active.status = Set(DeliveryStatus::Sent);
+        active.last_error = Set(None);
+        active.updated_at = Set(chrono::Utc::now());
+
+        active
+            .update(&*self.db)
+            .await
+            .map(Some)
+            .map_err(|e| DatabaseError::Query(format!("Failed to update outbox entry: {}", e)))
+    }
+
+    /// Puts failed entries for a contact back in the queue
+    pub async fn retry_failed(&self, contact_id: i32) -> Result<u64, DatabaseError> {
+        let result = Entity::update_many()
+            .col_expr(Column::Status, Expr::value(DeliveryStatus::Pending.to_value()))
+            .col_expr(Column::Attempts, Expr::value(0))
+            .col_expr(Column::UpdatedAt, Expr::value(chrono::Utc::now()))
+            .filter(Column::ContactId.eq(contact_id))
+            .filter(Column::Status.eq(DeliveryStatus::Failed))
+            .exec(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to requeue messages: {}", e)))?;
+
+        Ok(result.rows_affected)
+    }
+
+    /// Delivery status of each given outgoing message, keyed by `messages.id`
+    ///
+    /// Messages without an outbox row (incoming ones) are absent from the map.
+    pub async fn statuses(&self, message_ids: &[i32]) -> Result<HashMap<i32, DeliveryStatus>, DatabaseError> {
+        let rows = Entity::find()
+            .filter(Column::MessageId.is_in(message_ids.iter().copied()))
+            .all(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to load delivery status: {}", e)))?;
+
+        Ok(rows.into_iter().map(|r| (r.message_id, r.status)).collect())
+    }
+
+    async fn find(&self, id: i32) -> Result<Model, DatabaseError> {
+        Entity::find_by_id(id)
+            .one(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to load outbox entry: {}", e)))?
+            .ok_or_else(|| DatabaseError::Query(format!("Outbox entry {} not found", id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::contact_repository::ContactRepository;
+    use crate::core::testing::open_migrated_db;
+    use crate::database::models::Contact;
+    use tempfile::NamedTempFile;
+
+    // Repository over a fresh database holding two contacts, whose ids are returned
+    async fn open_repository(file: &NamedTempFile) -> (OutboxRepository, i32, i32) {
+        let db = open_migrated_db(file).await;
+        let contacts = ContactRepository::new(db.clone());
+        let alice = contacts.create(Contact::new("200:1::1", "", "Alice", false)).await.unwrap();
+        let bob = contacts.create(Contact::new("200:1::2", "", "Bob", false)).await.unwrap();
+        (OutboxRepository::new(db), alice.id.unwrap(), bob.id.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_stores_message_and_pending_entry_in_order() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let (repo, alice, bob) = open_repository(&temp_file).await;
+
+        let (message, entry) = repo.enqueue(alice, "first".to_string(), "env-1".to_string()).await.unwrap();
+        assert!(message.is_outgoing);
+        assert_eq!(message.envelope_id.as_deref(), Some("env-1"));
+        assert_eq!((entry.message_id, entry.status, entry.attempts), (message.id, DeliveryStatus::Pending, 0));
+
+        repo.enqueue(bob, "for bob".to_string(), "env-2".to_string()).await.unwrap();
+        repo.enqueue(alice, "second".to_string(), "env-3".to_string()).await.unwrap();
+
+        let pending = repo.pending_for_contact(alice).await.unwrap();
+        let bodies: Vec<_> = pending.iter().map(|(_, m)| m.body.as_str()).collect();
+        assert_eq!(bodies, ["first", "second"]);
+
+        // Both were never written, so both are due
+        assert_eq!(repo.unacked_since(alice, chrono::Utc::now()).await.unwrap().len(), 2);
+        assert_eq!(repo.contacts_with_pending().await.unwrap(), [alice, bob]);
+    }
+
+    #[tokio::test]
+    async fn test_unacknowledged_entry_fails_after_max_attempts() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let (repo, alice, _) = open_repository(&temp_file).await;
+        let (message, entry) = repo.enqueue(alice, "hi".to_string(), "env-1".to_string()).await.unwrap();
+
+        for _ in 1..MAX_SEND_ATTEMPTS {
+            assert_eq!(repo.record_attempt(entry.id, None).await.unwrap().status, DeliveryStatus::Pending);
+        }
+        let failed = repo.record_attempt(entry.id, Some("Not connected".to_string())).await.unwrap();
+        assert_eq!(failed.status, DeliveryStatus::Failed);
+        assert_eq!(failed.last_error.as_deref(), Some("Not connected"));
+        assert!(repo.pending_for_contact(alice).await.unwrap().is_empty());
+        assert_eq!(repo.statuses(&[message.id]).await.unwrap()[&message.id], DeliveryStatus::Failed);
+
+        // Retrying puts it back in the queue with a fresh attempt budget
+        assert_eq!(repo.retry_failed(alice).await.unwrap(), 1);
+        let (requeued, _) = repo.pending_for_contact(alice).await.unwrap().remove(0);
+        assert_eq!((requeued.status, requeued.attempts), (DeliveryStatus::Pending, 0));
+
+        // Written just now, so not due for a resend yet
+        repo.record_attempt(entry.id, None).await.unwrap();
+        let an_hour_ago = chrono::Utc::now() - chrono::Duration::hours(1);
+        assert!(repo.unacked_since(alice, an_hour_ago).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mark_sent_only_accepts_the_recipients_ack() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let (repo, alice, bob) = open_repository(&temp_file).await;
+        let (message, _) = repo.enqueue(alice, "hi".to_string(), "env-1".to_string()).await.unwrap();
+
+        assert_eq!(repo.mark_sent(bob, "env-1").await.unwrap(), None);
+        assert_eq!(repo.mark_sent(alice, "unknown").await.unwrap(), None);
+
+        let sent = repo.mark_sent(alice, "env-1").await.unwrap().unwrap();
+        assert_eq!(sent.status, DeliveryStatus::Sent);
+        assert!(repo.pending_for_contact(alice).await.unwrap().is_empty());
+        assert_eq!(repo.statuses(&[message.id]).await.unwrap()[&message.id], DeliveryStatus::Sent);
+    }
+}
@@ -0,0 +1,66 @@
+use sea_orm::entity::prelude::*;
+use sea_orm::Set;
+use serde::{Deserialize, Serialize};
+
+/// Delivery state of an outgoing message
+///
+/// `Sent` is only reached once the peer acknowledges the envelope; writing
+/// it to the socket is not enough.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::None)")]
+pub enum DeliveryStatus {
+    #[sea_orm(string_value = "pending")]
+    Pending,
+    #[sea_orm(string_value = "sent")]
+    Sent,
+    #[sea_orm(string_value = "failed")]
+    Failed,
+}
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize, Deserialize)]
+#[sea_orm(table_name = "outbox")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i32,
+    #[sea_orm(unique)]
+    pub message_id: i32,    // Queued message (messages.id)
+    #[sea_orm(indexed)]
+    pub contact_id: i32,    // Recipient (contacts.id)
+    #[sea_orm(unique)]
+    pub envelope_id: String,    // Wire id the peer echoes back in its ack
+    pub status: DeliveryStatus,
+    pub attempts: i32,    // Times the envelope was written to a session
+    pub last_error: Option<String>,
+    pub last_attempt_at: Option<DateTimeUtc>,
+    pub created_at: DateTimeUtc,
+    pub updated_at: DateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::message_schema::Entity",
+        from = "Column::MessageId",
+        to = "super::message_schema::Column::Id",
+        on_delete = "Cascade"
+    )]
+    Message,
+}
+
+impl Related<super::message_schema::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Message.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {
+    fn new() -> Self {
+        Self {
+            status: Set(DeliveryStatus::Pending),
+            attempts: Set(0),
+            created_at: Set(chrono::Utc::now()),
+            updated_at: Set(chrono::Utc::now()),
+            ..ActiveModelTrait::default()    // `Default` calls back into `new`
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use sea_orm::entity::prelude::*;
+use sea_orm::Set;
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize, Deserialize)]
@@ -9,7 +10,7 @@ pub struct Model {
     #[sea_orm(unique, indexed)]
     pub yggdrasil_address: String,    // The Yggdrasil IPv6 address
     #[sea_orm(indexed)]
-    pub socks5_proxy: String,    // SOCKS5 proxy for hidden peers
+    pub socks5_proxy: String,    // Per-contact SOCKS5 proxy URL, empty to use the default; password in plain text
     pub display_name: String,    // User-friendly name
     pub is_active: bool,    // Connection status
     pub last_seen: Option<DateTimeUtc>,    // Last activity timestamp
@@ -17,22 +18,37 @@ pub struct Model {
     pub updated_at: DateTimeUtc,    // Last update time
     pub is_hidden_peer: bool,
     pub notes: Option<String>,
+    pub public_key: Option<String>,    // Peer's static Noise key (base64), pinned on first contact
+    pub card_fingerprint: Option<String>,    // Key fingerprint from their contact card, checked before pinning
 }
 
-#[dertive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(has_many = "super::message_schema::Entity")]
+    Messages,
+}
+
+impl Related<super::message_schema::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Messages.def()
+    }
+}
 
+#[async_trait::async_trait]
 impl ActiveModelBehavior for ActiveModel {
     fn new() -> Self {
         Self {
             created_at: Set(chrono::Utc::now()),
             updated_at: Set(chrono::Utc::now()),
             is_active: Set(true),
-            ..Default::default()
+            ..ActiveModelTrait::default()    // `Default` calls back into `new`
         }
     }
 
-    fn before_save(mut self, _insert: bool) -> Result<Self, DdErr> {
+    async fn before_save<C>(mut self, _db: &C, _insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
         self.updated_at = Set(chrono::Utc::now());
         Ok(self)
     }
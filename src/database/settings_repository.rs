@@ -0,0 +1,92 @@
+use crate::database::error::DatabaseError;
+use crate::database::settings_schema::{ActiveModel, Column, Entity};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{DatabaseConnection, EntityTrait, Set, TransactionTrait};
+use std::sync::Arc;
+
+/// Repository for the `settings` table
+///
+/// Settings are stored one row per key so that new settings can be added
+/// without a migration. The repository only moves key/value pairs; turning
+/// them into `core::settings::Settings`, with defaults for keys that were
+/// never saved, is up to the caller.
+pub struct SettingsRepository {
+    db: Arc<DatabaseConnection>,
+}
+
+impl SettingsRepository {
+    pub fn new(db: Arc<DatabaseConnection>) -> Self {
+        Self { db }
+    }
+
+    /// Every stored key with its value
+    pub async fn load(&self) -> Result<Vec<(String, String)>, DatabaseError> {
+        let rows = Entity::find()
+            .all(&*self.db)
+            .await
+            .map_err(|e| DatabaseError::Query(format!("Failed to load settings: {}", e)))?;
+
+        Ok(rows.into_iter().map(|row| (row.key, row.value)).collect())
+    }
+
+    /// Writes every pair in one transaction, replacing stored values
+    pub async fn save(&self, pairs: Vec<(&str, String)>) -> Result<(), DatabaseError> {
+        let fail = |e: sea_orm::DbErr| DatabaseError::Query(format!("Failed to save settings: {}", e));
+        let now = chrono::Utc::now();
+
+        let rows = pairs.into_iter().map(|(key, value)| ActiveModel {
+            key: Set(key.to_string()),
+            value: Set(value),
+            updated_at: Set(now),
+        });
+
+        let txn = self.db.begin().await.map_err(fail)?;
+
+        Entity::insert_many(rows)
+            .on_conflict(
+                OnConflict::column(Column::Key)
+                    .update_columns([Column::Value, Column::UpdatedAt])
+                    .to_owned(),
+            )
+            .exec(&txn)
+            .await
+            .map_err(fail)?;
+
+        txn.commit().await.map_err(fail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testing::open_migrated_db;
+    use tempfile::NamedTempFile;
+
+    async fn open_repository(file: &NamedTempFile) -> SettingsRepository {
+        SettingsRepository::new(open_migrated_db(file).await)
+    }
+
+    #[tokio::test]
+    async fn test_empty_table_loads_nothing() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let repo = open_repository(&temp_file).await;
+
+        assert!(repo.load().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_save_overwrites_previous_values() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let repo = open_repository(&temp_file).await;
+
+        repo.save(vec![("listen_port", "7400".to_string()), ("theme", "dark".to_string())]).await.unwrap();
+        repo.save(vec![("listen_port", "7500".to_string())]).await.unwrap();
+
+        let mut stored = repo.load().await.unwrap();
+        stored.sort();
+        assert_eq!(stored, [
+            ("listen_port".to_string(), "7500".to_string()),
+            ("theme".to_string(), "dark".to_string()),
+        ]);
+    }
+}
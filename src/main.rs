@@ -1,28 +1,192 @@
+mod database;
+mod components {
+    pub mod chat_list;
+}
+mod ui {
+    pub mod pages {
+        pub mod home;
+        pub mod menu;
+    }
+}
 mod core {
     pub mod routes;
+    pub mod add_contact;
+    pub mod contact_card;
+    pub mod qr;
+    pub mod contacts_page;
+    pub mod conversation;
     pub mod chat_data;
+    pub mod buffer;
+    pub mod protocol;
+    pub mod identity;
+    pub mod proxy;
+    pub mod noise;
+    pub mod session;
+    pub mod heartbeat;
+    pub mod listener;
+    pub mod connection_manager;
+    pub mod retry;
+    pub mod supervisor;
+    pub mod outbox;
+    pub mod receipts;
+    pub mod events;
+    pub mod presence;
+    pub mod notifications;
+    pub mod messenger;
+    pub mod settings;
+    pub mod settings_page;
+    pub mod identity_settings;
+    pub mod unlock;
+    #[cfg(test)]
+    pub mod testing;
 }
 use core::routes::Route;
 use dioxus::prelude::*;
-use dioxus::desctop;
+use dioxus::desktop;
 use crate::core::chat_data::ChatDataProvider;
-use tracing_subscriber;
+use crate::core::identity::{Identity, IdentityError};
+use crate::core::listener::ListenerConfig;
+use crate::core::messenger::YggdrasilMessenger;
+use crate::core::outbox;
+use crate::core::settings::{self, LogLevel};
+use crate::core::unlock::UnlockIdentity;
+use crate::database::db_paths;
+use std::sync::{Arc, OnceLock};
+use tokio::time::Instant;
+
+/// Identity loaded by `main`; unset while a sealed one waits for its passphrase
+static STARTUP_IDENTITY: OnceLock<Identity> = OnceLock::new();
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize logging
-    tracing_subscriber::fmt::init();
-
-    // Launch desctop app with context
-    desktop::launch_cfg(
-        app,
-        desktop::Config::default()
-            .with_window(
-                desktop::WindowBuilder::new()
-                    .with_inner_size(764.0, 480.0)
-                    .with_title("Syggrel Chat")
-            )
-    );
+    // Initialize logging; the stored level is applied once settings load
+    settings::init_logging(LogLevel::default());
+
+    // Open the database (creating it on first run) before any page needs it
+    let db_path = db_paths::ensure_database_path()?;
+
+    // Going back to an older release: revert the schema it doesn't know and exit
+    if let Some(target) = migrate_down_target()? {
+        database::migrate_down(&db_path.to_string_lossy(), target)
+            .await
+            .map_err(|e| format!("Failed to migrate {} down: {}", db_path.display(), e))?;
+        return Ok(());
+    }
+
+    database::init_db(&db_path.to_string_lossy())
+        .await
+        .map_err(|e| format!("Failed to open database {}: {}", db_path.display(), e))?;
+
+    // A sealed identity is unlocked from the UI, which asks for the passphrase
+    let identity_path = db_paths::get_identity_path()?;
+    match Identity::load_or_generate(&identity_path, None) {
+        Ok(identity) => {
+            let _ = STARTUP_IDENTITY.set(identity);
+        }
+        Err(IdentityError::PassphraseRequired) => tracing::info!("Identity is sealed; waiting for the passphrase"),
+        Err(e) => return Err(format!("Failed to load identity {}: {}", identity_path.display(), e).into()),
+    }
+
+    // Launch desktop app with context
+    LaunchBuilder::desktop()
+        .with_cfg(
+            desktop::Config::default()
+                .with_window(
+                    desktop::WindowBuilder::new()
+                        .with_inner_size(desktop::LogicalSize::new(764.0, 480.0))
+                        .with_title("Syggrel Chat")
+                )
+        )
+        .launch(app);
 
     Ok(())
 }
+
+/// Schema version passed as `--migrate-down-to <version>`, if any
+fn migrate_down_target() -> Result<Option<i64>, String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--migrate-down-to" {
+            let version = args.next().ok_or("--migrate-down-to needs a schema version")?;
+            return version
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("Invalid schema version '{}'", version));
+        }
+    }
+    Ok(None)
+}
+
+/// Root component: provides the settings context, applies the theme and asks
+/// for the identity passphrase if `main` couldn't load the identity by itself
+///
+/// The identity is provided as a `Signal<Option<Identity>>` context; setting
+/// it to another identity (an import) restarts messaging with that one.
+fn app() -> Element {
+    let app_settings = settings::use_settings_provider();
+    let mut identity = use_context_provider(|| Signal::new(STARTUP_IDENTITY.get().cloned()));
+    let theme = app_settings.read().theme.as_str();
+
+    rsx! {
+        div {
+            class: "app theme-{theme}",
+            match identity() {
+                Some(identity) => rsx! { Messaging { key: "{identity.fingerprint()}", identity } },
+                None => rsx! {
+                    UnlockIdentity { on_unlock: move |unlocked| identity.set(Some(unlocked)) }
+                },
+            }
+        }
+    }
+}
+
+/// Runs messaging as `identity`: provides the messenger and chat list contexts
+/// and renders the pages
+#[component]
+fn Messaging(identity: Identity) -> Element {
+    let messenger = use_context_provider(|| Arc::new(YggdrasilMessenger::new(identity)));
+    let chat_data = use_context_provider(ChatDataProvider::new);
+
+    // Loads the chat list, then keeps it in sync with the messenger's events
+    let follower = use_hook(|| chat_data.follow(messenger.clone()).abort_handle());
+    use_drop(move || follower.abort());
+
+    // Stop listening and dialing when messaging restarts as another identity
+    let stopping = messenger.clone();
+    use_drop(move || {
+        tokio::spawn(async move {
+            let _ = stopping.disconnect().await;
+        });
+    });
+
+    // Apply the stored settings, accept sessions on the configured port and
+    // dial every contact, then keep dialing those with queued messages
+    use_hook(move || {
+        spawn(async move {
+            let stored = settings::load().await;
+            if let Err(e) = stored.apply_runtime(Some(&messenger)).await {
+                tracing::error!("Failed to apply settings: {}", e);
+            }
+            match messenger.start_listener(ListenerConfig::any(stored.listen_port)).await {
+                Ok(addr) => tracing::info!("Listening for contacts on {}", addr),
+                Err(e) => tracing::error!("Failed to start listener: {}", e),
+            }
+            match messenger.connect_all().await {
+                Ok(count) => tracing::info!("Dialing {} contacts", count),
+                Err(e) => tracing::error!("Failed to dial contacts: {}", e),
+            }
+
+            let mut ticker = tokio::time::interval_at(Instant::now() + outbox::REDIAL_INTERVAL, outbox::REDIAL_INTERVAL);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = messenger.redial_pending().await {
+                    tracing::warn!("Failed to redial contacts with queued messages: {}", e);
+                }
+            }
+        })
+    });
+
+    rsx! {
+        Router::<Route> {}
+    }
+}
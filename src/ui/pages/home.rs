@@ -1,7 +1,7 @@
 use dioxus::prelude::*;
-use crate::data::chat::{ChatDataProvider, ChatItem};
+use crate::core::chat_data::use_chats;
 use crate::components::chat_list::ChatList;
-use std::sync::Arc;
+use crate::core::routes::Route;
 
 /// Home Page Component for Syggrel Chat Application
 /// 
@@ -9,15 +9,16 @@ use std::sync::Arc;
 /// It displays a list of active chat conversations to the user with the following key features:
 /// 
 /// 1. Navigation Interface: Provides top navigation bar with menu toggle, app title,
-///    and quick access buttons to Home, New Chat, and Settings pages. Includes a collapsible
+///    and quick access buttons to Home, Add Contact, and Settings pages. Includes a collapsible
 ///    sidebar menu accessible via the hamburger menu.
 /// 
-/// 2. Chat List Management: Dynamically loads and displays active chat conversations
-///    using the ChatDataProvider context. Chats are sorted by most recent activity (timestamp).
+/// 2. Chat List Management: Displays chat conversations from the ChatDataProvider
+///    context through `use_chats`, so the list re-renders as messages arrive and
+///    contacts come online. Chats are sorted by most recent activity (timestamp).
 /// 
 /// 3. State Management: Handles multiple UI states including:
 ///    - Loading state: Shows spinner while fetching chat data
-///    - Empty state: Shows "No active chats" message with "Start New Chat" button when no chats exist
+///    - Empty state: Shows "No active chats" message with "Add Contact" button when no chats exist
 ///    - Active chats: Displays the list of conversations via ChatList component
 /// 
 /// 4. Responsive Design: Implements mobile-friendly navigation with collapsible sidebar
@@ -27,30 +28,26 @@ use std::sync::Arc;
 ///    context to fetch, cache, and display chat data with proper error handling and loading states.
 /// 
 /// The component expects a ChatDataProvider context to be available in the component tree
-/// (typically provided by a parent router or app wrapper component). The ChatList component
+/// (provided by the app's `Messaging` wrapper component). The ChatList component
 /// is responsible for rendering individual chat items in a scrollable list format.
 /// 
 /// Routes used:
-/// - "/home" - Home page navigation
-/// - "/new-chat" - Create new chat conversation
-/// - "/settings" - Application settings
-/// - "/contacts" - Contact management
-
+/// - `Route::Home` - Home page navigation
+/// - `Route::AddContact` - Add a contact to start a new chat
+/// - `Route::Settings` - Application settings
+/// - `Route::Contacts` - Contact management
 #[component]
 pub fn Home() -> Element {
-    let show_menu = use_signal(|| false);
-    let data_provider = use_context::<ChatDataProvider>();
+    let mut show_menu = use_signal(|| false);
+    let chats = use_chats();
 
-    // Momoize expensive computations
-    let sorted_chats = use_memo(&data_provider, |provider| {
-        match provider.get_chats() {
-            Some(chats) => {
-                let mut sorted = chats.to_vec();
-                sorted.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
-                Some(sorted)
-            }
-            None => None,
-        }
+    // Momoize expensive computations; re-sorted whenever the chats change
+    let sorted_chats = use_memo(move || {
+        chats.read().as_ref().map(|chats| {
+            let mut sorted = chats.to_vec();
+            sorted.sort_by_key(|chat| std::cmp::Reverse(chat.timestamp));
+            sorted
+        })
     });
 
     rsx! {
@@ -73,17 +70,20 @@ pub fn Home() -> Element {
                 div {
                     class: "right-section",
                     Link {
-                        to: "/home",
-                        class: "nav-button home-button"
-                    } { "🏠" }
+                        to: Route::Home {},
+                        class: "nav-button home-button",
+                        "🏠"
+                    }
                     Link {
-                        to: "/new-chat",
-                        class: "nav-button new-chat-button"
-                    } { "+" }
+                        to: Route::AddContact {},
+                        class: "nav-button new-chat-button",
+                        "+"
+                    }
                     Link {
-                        to: "/settings",
-                        class: "nav-button settings-button"
-                    } { "⚙️" }
+                        to: Route::Settings {},
+                        class: "nav-button settings-button",
+                        "⚙️"
+                    }
                 }
             }
             // Sidebar menu
@@ -93,20 +93,23 @@ pub fn Home() -> Element {
                     role: "navigation",
                     aria_label: "Main menu",
                     Link {
-                        to: "/contacts",
+                        to: Route::Contacts {},
                         class: "menu-item",
-                        onclick: move |_| show_menu.set(false)
-                    } { "Contacts" }
+                        onclick: move |_| show_menu.set(false),
+                        "Contacts"
+                    }
                     Link {
-                        to: "/home",
+                        to: Route::Home {},
                         class: "menu-item",
-                        onclick: move |_| show_menu.set(false)
-                    } { "Home" }
+                        onclick: move |_| show_menu.set(false),
+                        "Home"
+                    }
                     Link {
-                        to: "/settings",
+                        to: Route::Settings {},
                         class: "menu-item",
-                        onclick: move |_| show_menu.set(false)
-                    } { "Settings" }
+                        onclick: move |_| show_menu.set(false),
+                        "Settings"
+                    }
                 }
             }
 
@@ -117,8 +120,8 @@ pub fn Home() -> Element {
                     class: "chat-list-container",
 
                     // Display chats based on state
-                    match (data_provider.is_loading(), sorted_chats.as_ref()) {
-                        (true, _) => rsx! {
+                    match sorted_chats.read().as_ref() {
+                        None => rsx! {
                             div {
                                 class: "loading-container",
                                 aria_busy: "true",
@@ -126,7 +129,7 @@ pub fn Home() -> Element {
                                 p { "Loading chats..." }
                             }
                         },
-                        (_, Some(chats)) if !chats.is_empty() => rsx! {
+                        Some(chats) if !chats.is_empty() => rsx! {
                             div {
                                 class: "chat-list-content",
                                 ChatList {
@@ -134,14 +137,15 @@ pub fn Home() -> Element {
                                 }
                             }
                         },
-                        (_, Some(chats)) if chats.is_empty() => rsx! {
+                        Some(_) => rsx! {
                             div {
                                 class: "empty-state",
                                 p { "No active chats" }
                                 Link {
-                                    to: "/new-chat",
-                                    class: "primary-button"
-                                } { "Start New Chat" }
+                                    to: Route::AddContact {},
+                                    class: "primary-button",
+                                    "Add Contact"
+                                }
                             }
                         }
                     }
@@ -0,0 +1,35 @@
+use crate::core::routes::Route;
+use dioxus::prelude::*;
+
+/// Menu Page
+///
+/// Full-screen version of the Home sidebar, for narrow windows: links to
+/// every top-level page.
+#[component]
+pub fn Menu() -> Element {
+    rsx! {
+        div {
+            class: "menu-container",
+
+            header {
+                class: "top-bar",
+                Link {
+                    to: Route::Home {},
+                    class: "nav-button back-button",
+                    aria_label: "Back to chats",
+                    "←"
+                }
+                h1 { "Menu" }
+            }
+
+            nav {
+                class: "menu-list",
+                aria_label: "Main menu",
+                Link { to: Route::Home {}, class: "menu-item", "Chats" }
+                Link { to: Route::Contacts {}, class: "menu-item", "Contacts" }
+                Link { to: Route::AddContact {}, class: "menu-item", "Add Contact" }
+                Link { to: Route::Settings {}, class: "menu-item", "Settings" }
+            }
+        }
+    }
+}